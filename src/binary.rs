@@ -0,0 +1,568 @@
+//! A compact binary encoding of transactions, for archiving them and replaying
+//! them much faster than parsing csv.
+//!
+//! A file starts with an 8 byte header: the magic bytes `TXBN`, the format
+//! version as a little endian `u16`, the `CURRENCY_DECIMAL_SCALE` the amounts
+//! were written with, and a reserved zero byte. Every transaction after that is
+//! a fixed width 25 byte record, with all integers little endian:
+//!
+//! | Bytes   | Field                                                   |
+//! |---------|---------------------------------------------------------|
+//! | 0       | Type tag, see `TAGS`                                    |
+//! | 1..9    | Client id as a `u64`                                    |
+//! | 9..17   | Transaction id as a `u64`                               |
+//! | 17..25  | Amount as an `i64` count of `10^-scale` units, else `0` |
+//!
+//! Transaction ids that aren't numeric can't fit in a fixed width field, so
+//! the first time one is used it is defined with a `STRING_ID` record (the tag,
+//! a `u32` length and the id's bytes). Later records refer to it by its index
+//! in the order ids were defined, with `STRING_ID_FLAG` set in the tag.
+//!
+//! Batch ids are written the same way, with a `BATCH` record starting each
+//! batch, which every transaction after it belongs to. A `BATCH` record with
+//! no bytes ends the batch, as batch ids are never empty, so two batches in a
+//! row with the same id stay separate. Version 1 files were written without
+//! batches, and can still be read.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::ledger::{
+    Amount, ClientId, ClientIdRepr, Transaction, TxId, TxIdRepr, CURRENCY_DECIMAL_SCALE,
+};
+
+const MAGIC: &[u8; 4] = b"TXBN";
+const VERSION: u16 = 2;
+/// The oldest version that can still be read
+const MIN_VERSION: u16 = 1;
+const HEADER_LEN: usize = 8;
+const RECORD_LEN: usize = 25;
+
+/// The tag of a record defining the next string transaction id
+const STRING_ID: u8 = 0x7f;
+/// The tag of a record starting (or ending) the batch the next transactions
+/// belong to
+const BATCH: u8 = 0x7e;
+/// The longest string id or batch id that can be written. The length prefix
+/// is checked against this before anything is allocated, so a corrupt file
+/// can't make the reader allocate gigabytes
+const MAX_STRING_LEN: usize = 4096;
+/// Set in a record's tag when its transaction id is the index of a string id
+const STRING_ID_FLAG: u8 = 0x80;
+
+/// The tag for each type of transaction. Tags are part of the format, so they
+/// must never be changed or reused
+const TAGS: [(u8, &str); 6] = [
+    (1, "deposit"),
+    (2, "withdrawal"),
+    (3, "dispute"),
+    (4, "resolve"),
+    (5, "chargeback"),
+    (6, "unknown"),
+];
+
+fn tag(kind: &str) -> u8 {
+    TAGS.iter()
+        .find(|(_, k)| *k == kind)
+        .map(|(tag, _)| *tag)
+        .expect("every transaction kind has a tag")
+}
+
+fn kind(tag: u8) -> Option<&'static str> {
+    TAGS.iter().find(|(t, _)| *t == tag).map(|(_, kind)| *kind)
+}
+
+/// Widen an id to the `u64` it's written as. Ids are `u64` at their widest,
+/// so this takes any width rather than using `u64::from`, which would be a
+/// no-op conversion with some of the id width features
+fn widen(id: impl Into<u64>) -> u64 {
+    id.into()
+}
+
+/// Writes transactions in the binary format
+pub struct BinaryWriter<W: Write> {
+    wtr: W,
+    /// The index of every string transaction id defined so far
+    string_ids: HashMap<TxId, u64>,
+    /// The batch the last transaction written belongs to
+    batch: Option<String>,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    /// Write the header, ready for transactions to be written
+    pub fn new(mut wtr: W) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6] = CURRENCY_DECIMAL_SCALE as u8;
+        wtr.write_all(&header)?;
+        Ok(BinaryWriter {
+            wtr,
+            string_ids: HashMap::new(),
+            batch: None,
+        })
+    }
+
+    /// Write a transaction, along with the batch it belongs to if any. Every
+    /// transaction written until [`end_batch`](Self::end_batch) with the same
+    /// batch id is part of the same batch
+    pub fn write(&mut self, tx: &Transaction, batch: Option<&str>) -> io::Result<()> {
+        if self.batch.as_deref() != batch {
+            self.write_string(BATCH, batch.unwrap_or_default())?;
+            self.batch = batch.map(str::to_string);
+        }
+        let mut tag = tag(tx.kind());
+        let tx_id = match tx.tx_id().as_numeric() {
+            Some(n) => widen(n),
+            None => {
+                tag |= STRING_ID_FLAG;
+                self.string_id(tx.tx_id())?
+            }
+        };
+
+        let mut record = [0; RECORD_LEN];
+        record[0] = tag;
+        let client_id = widen(ClientIdRepr::from(tx.client_id()));
+        record[1..9].copy_from_slice(&client_id.to_le_bytes());
+        record[9..17].copy_from_slice(&tx_id.to_le_bytes());
+        let amount = tx.amount().unwrap_or(Amount::ZERO).units();
+        record[17..25].copy_from_slice(&amount.to_le_bytes());
+        self.wtr.write_all(&record)
+    }
+
+    /// End the open batch, if there is one, so the next transaction written
+    /// starts a new batch even if it has the same id
+    pub fn end_batch(&mut self) -> io::Result<()> {
+        if self.batch.take().is_some() {
+            self.write_string(BATCH, "")?;
+        }
+        Ok(())
+    }
+
+    /// The index of a string transaction id, defining it first if needed
+    fn string_id(&mut self, id: &TxId) -> io::Result<u64> {
+        if let Some(index) = self.string_ids.get(id) {
+            return Ok(*index);
+        }
+        self.write_string(STRING_ID, &id.to_string())?;
+        let index = self.string_ids.len() as u64;
+        self.string_ids.insert(id.clone(), index);
+        Ok(index)
+    }
+
+    /// Write a record defining a string, after its tag and length
+    fn write_string(&mut self, tag: u8, text: &str) -> io::Result<()> {
+        if text.len() > MAX_STRING_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "id of {} bytes is longer than the {} allowed",
+                    text.len(),
+                    MAX_STRING_LEN
+                ),
+            ));
+        }
+        let len = text.len() as u32;
+        self.wtr.write_all(&[tag])?;
+        self.wtr.write_all(&len.to_le_bytes())?;
+        self.wtr.write_all(text.as_bytes())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+/// Why a record couldn't be read
+enum ReadError {
+    /// The input itself couldn't be read, e.g. because it doesn't decompress
+    Io(io::Error),
+    /// The bytes read aren't a valid record
+    Invalid(String),
+}
+
+impl From<String> for ReadError {
+    fn from(reason: String) -> Self {
+        ReadError::Invalid(reason)
+    }
+}
+
+/// Reads transactions back out of the binary format, along with the byte
+/// offset each record started at. An input that can't be read any further is
+/// an error, while a record that isn't valid is returned as its own error
+pub struct BinaryReader<R: Read> {
+    rdr: R,
+    /// The byte offset of the next record
+    byte: u64,
+    string_ids: Vec<TxId>,
+    /// The batch the records being read belong to
+    batch: Option<String>,
+    /// Whether a batch was started since the last transaction read
+    starts_batch: bool,
+    /// The bytes of the record being read, kept so that one that isn't valid
+    /// can still be reported as it was
+    record: Vec<u8>,
+    /// Whether the input was found to be corrupt, after which nothing more can
+    /// be read
+    done: bool,
+}
+
+impl<R: Read> BinaryReader<R> {
+    /// Read and check the header
+    pub fn new(mut rdr: R) -> Result<Self, String> {
+        let mut header = [0; HEADER_LEN];
+        rdr.read_exact(&mut header)
+            .map_err(|_| "not a binary transaction file".to_string())?;
+        if &header[..4] != MAGIC {
+            return Err("not a binary transaction file".to_string());
+        }
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if !(MIN_VERSION..=VERSION).contains(&version) {
+            return Err(format!("unsupported binary format version {}", version));
+        }
+        if u32::from(header[6]) != CURRENCY_DECIMAL_SCALE {
+            return Err(format!(
+                "amounts were written with {} decimal places, expected {}",
+                header[6], CURRENCY_DECIMAL_SCALE
+            ));
+        }
+        Ok(BinaryReader {
+            rdr,
+            byte: HEADER_LEN as u64,
+            string_ids: Vec::new(),
+            batch: None,
+            starts_batch: false,
+            record: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Read exactly `buf.len()` bytes, or nothing at all at the end of the input
+    fn read_exact_or_eof(&mut self, buf: &mut [u8]) -> Result<bool, ReadError> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.rdr.read(&mut buf[read..]) {
+                Ok(0) if read == 0 => return Ok(false),
+                Ok(0) => return Err("truncated record".to_string().into()),
+                Ok(n) => {
+                    self.record.extend_from_slice(&buf[read..read + n]);
+                    read += n;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(ReadError::Io(e)),
+            }
+        }
+        self.byte += buf.len() as u64;
+        Ok(true)
+    }
+
+    /// The batch the last transaction read belongs to
+    pub fn batch(&self) -> Option<&str> {
+        self.batch.as_deref()
+    }
+
+    /// The bytes of the last record read, as far as it could be read
+    pub fn record(&self) -> &[u8] {
+        &self.record
+    }
+
+    /// Whether the last transaction read is the first of its batch
+    pub fn starts_batch(&self) -> bool {
+        self.starts_batch && self.batch.is_some()
+    }
+
+    /// Read a string defined by a `STRING_ID` or `BATCH` record, after its tag
+    fn read_string(&mut self) -> Result<String, ReadError> {
+        let mut len = [0; 4];
+        if !self.read_exact_or_eof(&mut len)? {
+            return Err("truncated record".to_string().into());
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_STRING_LEN {
+            return Err(format!(
+                "string of {} bytes is longer than the {} allowed",
+                len, MAX_STRING_LEN
+            )
+            .into());
+        }
+        let mut text = vec![0; len];
+        if !self.read_exact_or_eof(&mut text)? {
+            return Err("truncated record".to_string().into());
+        }
+        String::from_utf8(text).map_err(|e| format!("invalid utf-8: {}", e).into())
+    }
+
+    /// Decode a single record
+    fn decode(&self, record: &[u8; RECORD_LEN]) -> Result<Transaction, String> {
+        let field = |range: std::ops::Range<usize>| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&record[range]);
+            bytes
+        };
+        let kind = kind(record[0] & !STRING_ID_FLAG)
+            .ok_or_else(|| format!("unknown record tag {:#04x}", record[0]))?;
+        let client_id = ClientIdRepr::try_from(u64::from_le_bytes(field(1..9)))
+            .map_err(|_| "client id out of range".to_string())?;
+        let tx_id = u64::from_le_bytes(field(9..17));
+        let tx_id = if record[0] & STRING_ID_FLAG != 0 {
+            usize::try_from(tx_id)
+                .ok()
+                .and_then(|i| self.string_ids.get(i))
+                .ok_or_else(|| format!("undefined string transaction id {}", tx_id))?
+                .clone()
+        } else {
+            TxId::from(
+                TxIdRepr::try_from(tx_id).map_err(|_| "transaction id out of range".to_string())?,
+            )
+        };
+        let amount = Amount::from_units(i64::from_le_bytes(field(17..25)));
+        Ok(Transaction::from_parts(
+            kind,
+            ClientId::from(client_id),
+            tx_id,
+            amount,
+        ))
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = io::Result<(u64, Result<Transaction, String>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.starts_batch = false;
+        while !self.done {
+            let start = self.byte;
+            self.record.clear();
+            let mut tag = [0; 1];
+            let read = self.read_exact_or_eof(&mut tag).and_then(|read| {
+                if !read {
+                    return Ok(None);
+                }
+                match tag[0] {
+                    STRING_ID => {
+                        let id = self.read_string()?;
+                        self.string_ids.push(TxId::new(&id));
+                        return Ok(Some(None));
+                    }
+                    BATCH => {
+                        let batch = self.read_string()?;
+                        self.batch = Some(batch).filter(|batch| !batch.is_empty());
+                        self.starts_batch = true;
+                        return Ok(Some(None));
+                    }
+                    _ => {}
+                }
+                let mut record = [0; RECORD_LEN];
+                record[0] = tag[0];
+                if !self.read_exact_or_eof(&mut record[1..])? {
+                    return Err("truncated record".to_string().into());
+                }
+                Ok(Some(Some(self.decode(&record))))
+            });
+            match read {
+                Ok(None) => return None,
+                // a string id or batch, which isn't a transaction itself
+                Ok(Some(None)) => continue,
+                Ok(Some(Some(tx))) => return Some(Ok((start, tx))),
+                // once the input can't be read, or is out of step with the
+                // records in it, nothing after this point can be trusted
+                Err(ReadError::Io(e)) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                Err(ReadError::Invalid(e)) => {
+                    self.done = true;
+                    return Some(Ok((start, Err(e))));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(row: &str) -> Transaction {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(row.as_bytes());
+        let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        rdr.records()
+            .next()
+            .unwrap()
+            .unwrap()
+            .deserialize(Some(&headers))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let txs = [
+            parse("deposit,1,1,1.5"),
+            parse("withdrawal,2,2,0.0001"),
+            parse("deposit,3,abc-123,7"),
+            parse("dispute,3,abc-123,"),
+            parse("resolve,1,1,"),
+            parse("chargeback,1,1,"),
+            parse("teleport,4,5,3"),
+        ];
+        let mut wtr = BinaryWriter::new(Vec::new()).unwrap();
+        for tx in &txs {
+            wtr.write(tx, None).unwrap();
+        }
+        let bytes = wtr.wtr;
+        // every record plus one definition of the string id
+        assert_eq!(
+            bytes.len(),
+            HEADER_LEN + txs.len() * RECORD_LEN + 1 + 4 + "abc-123".len()
+        );
+
+        let read: Vec<_> = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .map(|record| record.unwrap().1.unwrap())
+            .collect();
+        assert_eq!(read, txs);
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        assert!(BinaryReader::new(&b"type,client,tx,amount\n"[..]).is_err());
+
+        let mut wtr = BinaryWriter::new(Vec::new()).unwrap();
+        wtr.write(&parse("deposit,1,1,1.5"), None).unwrap();
+        let mut bytes = wtr.wtr;
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        let read: Vec<_> = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].0, HEADER_LEN as u64);
+        assert!(read[0].1.is_ok());
+        assert_eq!(
+            read[1],
+            (
+                (HEADER_LEN + RECORD_LEN) as u64,
+                Err("truncated record".to_string())
+            )
+        );
+
+        // the bytes of the record that couldn't be read are kept
+        let mut rdr = BinaryReader::new(bytes.as_slice()).unwrap();
+        assert!(rdr.record().is_empty());
+        assert!(rdr.next().unwrap().unwrap().1.is_ok());
+        assert_eq!(rdr.record().len(), RECORD_LEN);
+        assert!(rdr.next().unwrap().unwrap().1.is_err());
+        assert_eq!(rdr.record(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_batches() {
+        let rows = [
+            ("deposit,1,1,1", None),
+            ("deposit,1,2,1", Some("a")),
+            ("deposit,1,3,1", Some("a")),
+            ("deposit,1,4,1", Some("b")),
+            ("deposit,1,5,1", None),
+        ];
+        let mut wtr = BinaryWriter::new(Vec::new()).unwrap();
+        for (row, batch) in rows {
+            wtr.write(&parse(row), batch).unwrap();
+        }
+        let bytes = wtr.wtr;
+        // a batch record each time the batch changes
+        assert_eq!(
+            bytes.len(),
+            HEADER_LEN + rows.len() * RECORD_LEN + 3 * (1 + 4) + "a".len() + "b".len()
+        );
+
+        let mut rdr = BinaryReader::new(bytes.as_slice()).unwrap();
+        for (row, batch) in rows {
+            assert_eq!(rdr.next().unwrap().unwrap().1, Ok(parse(row)));
+            assert_eq!(rdr.batch(), batch);
+        }
+        assert!(rdr.next().is_none());
+    }
+
+    #[test]
+    fn test_end_batch() {
+        let mut wtr = BinaryWriter::new(Vec::new()).unwrap();
+        wtr.write(&parse("deposit,1,1,1"), Some("a")).unwrap();
+        wtr.end_batch().unwrap();
+        wtr.write(&parse("deposit,1,2,1"), Some("a")).unwrap();
+        wtr.write(&parse("deposit,1,3,1"), Some("a")).unwrap();
+        wtr.end_batch().unwrap();
+        // with no batch open there's nothing to end
+        wtr.end_batch().unwrap();
+        wtr.write(&parse("deposit,1,4,1"), None).unwrap();
+
+        let mut rdr = BinaryReader::new(wtr.wtr.as_slice()).unwrap();
+        let mut read = Vec::new();
+        while let Some(record) = rdr.next() {
+            let tx = record.unwrap().1.unwrap();
+            read.push((
+                tx.tx_id().clone(),
+                rdr.batch().is_some(),
+                rdr.starts_batch(),
+            ));
+        }
+        assert_eq!(
+            read,
+            [
+                (TxId::from(1), true, true),
+                (TxId::from(2), true, true),
+                (TxId::from(3), true, false),
+                (TxId::from(4), false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_version_1() {
+        let mut wtr = BinaryWriter::new(Vec::new()).unwrap();
+        wtr.write(&parse("deposit,1,1,1.5"), None).unwrap();
+        let mut bytes = wtr.wtr;
+        bytes[4..6].copy_from_slice(&1_u16.to_le_bytes());
+        let read: Vec<_> = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(read, [(HEADER_LEN as u64, Ok(parse("deposit,1,1,1.5")))]);
+
+        bytes[4..6].copy_from_slice(&3_u16.to_le_bytes());
+        assert_eq!(
+            BinaryReader::new(bytes.as_slice()).err(),
+            Some("unsupported binary format version 3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_string_too_long() {
+        let long = "x".repeat(MAX_STRING_LEN + 1);
+        let mut wtr = BinaryWriter::new(Vec::new()).unwrap();
+        let tx =
+            Transaction::from_parts("deposit", ClientId::from(1), TxId::new(&long), Amount::ZERO);
+        assert!(wtr.write(&tx, None).is_err());
+        assert!(wtr.write(&parse("deposit,1,1,1"), Some(&long)).is_err());
+
+        // a length prefix that claims far more than is allowed is refused
+        // before anything is allocated for it
+        let mut bytes = BinaryWriter::new(Vec::new()).unwrap().wtr;
+        bytes.push(STRING_ID);
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        let read: Vec<_> = BinaryReader::new(bytes.as_slice())
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            read,
+            [(
+                HEADER_LEN as u64,
+                Err("string of 4294967295 bytes is longer than the 4096 allowed".to_string())
+            )]
+        );
+    }
+}
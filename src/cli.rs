@@ -0,0 +1,446 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::builder::PossibleValue;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::ledger::{ClientIdRepr, Precision, Sequence, DEFAULT_MAX_DISPUTES};
+
+/// Process transactions in a csv file and output the final balances of each account.
+///
+/// Running without a subcommand behaves like `process`.
+#[derive(Debug, Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub process: Option<ProcessArgs>,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+/// Options for the structured logs written to stderr, taken by every command
+#[derive(Debug, Args)]
+pub struct LogArgs {
+    /// The level to log at, e.g. `info` or `debug`, or a full filter like
+    /// `transactions_rs::ledger=trace` [default: $RUST_LOG, or off]
+    #[arg(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<String>,
+
+    /// The format to write logs in
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Process transactions and output the final balances of each account
+    Process(ProcessArgs),
+    /// Parse transactions without processing them, reporting any problems
+    Validate(ValidateArgs),
+    /// Process transactions on top of a previously saved ledger snapshot
+    Replay(ReplayArgs),
+    /// Compare two account csv files, exiting with a failure if they differ
+    Diff(DiffArgs),
+    /// Convert transactions to the compact binary format, for archiving and
+    /// fast replay
+    Convert(ConvertArgs),
+    /// Run the ledger as a local HTTP service, taking transactions and
+    /// answering balance queries until stopped
+    Serve(ServeArgs),
+    /// Accept transactions streamed over a TCP or Unix socket, one csv row per
+    /// line, until stopped
+    Listen(ListenArgs),
+    /// Stream transactions to a running `listen`
+    Send(SendArgs),
+    /// Explore a ledger interactively: process transactions one at a time,
+    /// inspect accounts and transactions, and undo changes
+    Repl(ReplArgs),
+}
+
+// Note that the fields here can't be split out into smaller flattened structs,
+// clap can only tell whether an optional flattened struct (like the one on
+// `Cli`) was given when its arguments are defined directly on it
+#[derive(Debug, Args)]
+pub struct ProcessArgs {
+    /// The transaction files, with the columns type,client,tx,amount, read in
+    /// order. Use `-` to read from stdin
+    #[arg(required = true, value_name = "INPUT")]
+    pub inputs: Vec<PathBuf>,
+
+    /// The format of the inputs [default: picked from each file's extension,
+    /// or csv]
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+
+    /// What to do with amounts that have more than four decimal places. Any
+    /// rounding is reported along with failed transactions
+    #[arg(long, value_enum, default_value_t = Precision::Reject)]
+    pub precision: Precision,
+
+    /// Write the accounts to this file instead of stdout
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// The format to write the accounts in [default: picked from the extension
+    /// of the output file, or csv]
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+
+    /// The field to order accounts by, accounts with the same value are ordered
+    /// by client
+    #[arg(long, value_enum, default_value_t = SortKey::Client)]
+    pub sort: SortKey,
+
+    /// Order accounts from the largest value to the smallest
+    #[arg(long)]
+    pub descending: bool,
+
+    /// Write a statement of every change made to this client's account, with
+    /// the running balance, instead of the accounts
+    #[arg(long, value_name = "CLIENT")]
+    pub statement: Option<ClientIdRepr>,
+
+    /// Write the accounts as they were once the transaction with this sequence
+    /// number had been processed, instead of at the end. Every transaction
+    /// processed counts, starting at 1, including ones that are declined
+    #[arg(long, value_name = "SEQUENCE")]
+    pub as_of: Option<Sequence>,
+
+    /// Write failed transactions to this file instead of stderr
+    #[arg(long, value_name = "PATH")]
+    pub errors: Option<PathBuf>,
+
+    /// The format to report failed transactions in
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub errors_format: ReportFormat,
+
+    /// Copy rows that can't be parsed, or have an unknown type, to this csv
+    #[arg(long, value_name = "PATH")]
+    pub quarantine: Option<PathBuf>,
+
+    /// Abort on the first transaction that fails to parse or process, without
+    /// writing any accounts or snapshot
+    #[arg(long)]
+    pub strict: bool,
+
+    /// The most times a single transaction can be disputed, once unless this
+    /// is given. Once it's reached, resolving the last dispute is final
+    #[arg(long, value_name = "N")]
+    pub max_disputes: Option<usize>,
+
+    /// Enforce the deposit and withdrawal limits declared in this JSON file
+    #[arg(long, value_name = "PATH")]
+    pub limits: Option<PathBuf>,
+
+    /// Assign clients to the tiers declared with `--limits`, from a csv with
+    /// the columns client,tier
+    #[arg(long, value_name = "PATH", requires = "limits")]
+    pub tiers: Option<PathBuf>,
+
+    /// Enforce the fraud rules declared in this JSON file
+    #[arg(long, value_name = "PATH")]
+    pub rules: Option<PathBuf>,
+
+    /// Write alerts raised by the fraud rules to this file, one JSON object
+    /// per line, instead of stderr
+    #[arg(long, value_name = "PATH", requires = "rules")]
+    pub alerts: Option<PathBuf>,
+
+    /// Once processing is done, look for the money laundering patterns
+    /// declared in this JSON file and report any suspicious activity found
+    #[arg(long, value_name = "PATH")]
+    pub aml: Option<PathBuf>,
+
+    /// Write the suspicious activity found with `--aml` to this file instead
+    /// of stderr
+    #[arg(long, value_name = "PATH", requires = "aml")]
+    pub suspicious_activity: Option<PathBuf>,
+
+    /// The format to report suspicious activity in. The csv has a row for
+    /// each transaction involved, numbered by case
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub suspicious_activity_format: ReportFormat,
+
+    /// Save a snapshot of the ledger once processing is done, for use with `replay`
+    #[arg(long, value_name = "PATH")]
+    pub save_snapshot: Option<PathBuf>,
+
+    /// Print a summary of what was read and processed to stderr
+    #[arg(long)]
+    pub summary: bool,
+
+    /// Write metrics for the run to this file, in the Prometheus text format
+    #[arg(long, value_name = "PATH")]
+    pub metrics: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+    /// The transaction files, with the columns type,client,tx,amount, read in
+    /// order. Use `-` to read from stdin
+    #[arg(required = true, value_name = "INPUT")]
+    pub inputs: Vec<PathBuf>,
+
+    /// The format of the inputs [default: picked from each file's extension,
+    /// or csv]
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+
+    /// What to do with amounts that have more than four decimal places. Any
+    /// rounding is reported along with failed transactions
+    #[arg(long, value_enum, default_value_t = Precision::Reject)]
+    pub precision: Precision,
+
+    /// Write problems to this file instead of stderr
+    #[arg(long, value_name = "PATH")]
+    pub errors: Option<PathBuf>,
+
+    /// The format to report problems in
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub errors_format: ReportFormat,
+
+    /// Copy rows that can't be parsed, or have an unknown type, to this csv
+    #[arg(long, value_name = "PATH")]
+    pub quarantine: Option<PathBuf>,
+
+    /// Stop at the first problem found
+    #[arg(long)]
+    pub strict: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// The ledger snapshot to start from, as saved by `--save-snapshot`
+    #[arg(long, value_name = "PATH")]
+    pub snapshot: PathBuf,
+
+    #[command(flatten)]
+    pub process: ProcessArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// The transaction files to convert, read in order. Use `-` to read from
+    /// stdin
+    #[arg(required = true, value_name = "INPUT")]
+    pub inputs: Vec<PathBuf>,
+
+    /// The format of the inputs [default: picked from each file's extension,
+    /// or csv]
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+
+    /// What to do with amounts that have more than four decimal places. Any
+    /// rounding is reported along with failed transactions
+    #[arg(long, value_enum, default_value_t = Precision::Reject)]
+    pub precision: Precision,
+
+    /// The binary file to write, conventionally ending in `.txb`
+    #[arg(short, long, value_name = "PATH")]
+    pub output: PathBuf,
+
+    /// Write rows that couldn't be converted to this file instead of stderr
+    #[arg(long, value_name = "PATH")]
+    pub errors: Option<PathBuf>,
+
+    /// The format to report rows that couldn't be converted in
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub errors_format: ReportFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// The address to listen on
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+
+    #[command(flatten)]
+    pub ledger: LedgerArgs,
+
+    /// What to do with amounts that have more than four decimal places
+    #[arg(long, value_enum, default_value_t = Precision::Reject)]
+    pub precision: Precision,
+}
+
+#[derive(Debug, Args)]
+pub struct ListenArgs {
+    #[command(flatten)]
+    pub endpoint: EndpointArgs,
+
+    #[command(flatten)]
+    pub ledger: LedgerArgs,
+
+    /// What to do with amounts that have more than four decimal places
+    #[arg(long, value_enum, default_value_t = Precision::Reject)]
+    pub precision: Precision,
+
+    /// Serve metrics over HTTP on this address, at `/metrics`
+    #[arg(long, value_name = "ADDR")]
+    pub metrics: Option<SocketAddr>,
+}
+
+#[derive(Debug, Args)]
+pub struct SendArgs {
+    #[command(flatten)]
+    pub endpoint: EndpointArgs,
+
+    /// The transaction files to send, read in order. Use `-` to read from stdin
+    #[arg(required = true, value_name = "INPUT")]
+    pub inputs: Vec<PathBuf>,
+
+    /// The format of the inputs [default: picked from each file's extension,
+    /// or csv]
+    #[arg(long, value_enum)]
+    pub input_format: Option<InputFormat>,
+
+    /// What to do with amounts that have more than four decimal places
+    #[arg(long, value_enum, default_value_t = Precision::Reject)]
+    pub precision: Precision,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplArgs {
+    #[command(flatten)]
+    pub ledger: LedgerArgs,
+
+    /// What to do with amounts that have more than four decimal places
+    #[arg(long, value_enum, default_value_t = Precision::Reject)]
+    pub precision: Precision,
+}
+
+/// How the ledger kept in memory by `serve`, `listen` and `repl` is set up
+#[derive(Debug, Args)]
+pub struct LedgerArgs {
+    /// A ledger snapshot to start from, as saved by `--save-snapshot`
+    #[arg(long, value_name = "PATH")]
+    pub snapshot: Option<PathBuf>,
+
+    /// The most times a single transaction can be disputed. Once it's reached,
+    /// resolving the last dispute is final
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_DISPUTES)]
+    pub max_disputes: usize,
+
+    /// Enforce the deposit and withdrawal limits declared in this JSON file
+    #[arg(long, value_name = "PATH")]
+    pub limits: Option<PathBuf>,
+
+    /// Assign clients to the tiers declared with `--limits`, from a csv with
+    /// the columns client,tier
+    #[arg(long, value_name = "PATH", requires = "limits")]
+    pub tiers: Option<PathBuf>,
+
+    /// Enforce the fraud rules declared in this JSON file, writing the alerts
+    /// they raise to stderr
+    #[arg(long, value_name = "PATH")]
+    pub rules: Option<PathBuf>,
+}
+
+/// The socket to listen on or connect to, exactly one of which must be given
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+pub struct EndpointArgs {
+    /// A TCP address, e.g. 127.0.0.1:9000
+    #[arg(long, value_name = "ADDR")]
+    pub tcp: Option<SocketAddr>,
+
+    /// The path of a Unix domain socket
+    #[arg(long, value_name = "PATH")]
+    pub unix: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// The account csv to compare from
+    pub left: PathBuf,
+
+    /// The account csv to compare to
+    pub right: PathBuf,
+}
+
+/// The formats transactions can be read in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// A csv file with a header row
+    Csv,
+    /// One JSON object per line for each transaction
+    Jsonl,
+    /// The compact binary format written by `convert`
+    Binary,
+}
+
+/// The formats the final accounts can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// A csv file with a header row
+    Csv,
+    /// A JSON array of accounts
+    Json,
+    /// One JSON object per line for each account
+    Jsonl,
+}
+
+/// The fields accounts can be ordered by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortKey {
+    Client,
+    Available,
+    Held,
+    Total,
+}
+
+/// The formats logs can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// One human readable line per event
+    Text,
+    /// One JSON object per line for each event, with the fields of the span
+    /// it happened in
+    Json,
+}
+
+/// The formats rejected transactions can be reported in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// One human readable line per rejection
+    Text,
+    /// A csv file with one record per rejection
+    Csv,
+    /// One JSON object per line for each rejection
+    Jsonl,
+}
+
+// The ledger doesn't depend on clap, so the values `Precision` takes on the
+// command line are declared here
+impl ValueEnum for Precision {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Precision::Reject,
+            Precision::HalfEven,
+            Precision::HalfUp,
+            Precision::Truncate,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        let (name, help) = match self {
+            Precision::Reject => ("reject", "Reject the amount, along with its transaction"),
+            Precision::HalfEven => (
+                "half-even",
+                "Round to the nearest amount, with ties going to the even digit",
+            ),
+            Precision::HalfUp => (
+                "half-up",
+                "Round to the nearest amount, with ties going away from zero",
+            ),
+            Precision::Truncate => (
+                "truncate",
+                "Drop the extra decimal places, rounding towards zero",
+            ),
+        };
+        Some(PossibleValue::new(name).help(help))
+    }
+}
@@ -0,0 +1,41 @@
+use crate::ledger::Ledger;
+
+/// A group of rows sharing a batch id, which are applied to the ledger as a
+/// whole or not at all. `T` is whatever the caller needs to keep about each
+/// applied row to report it if the batch is rolled back
+pub(crate) struct Batch<T> {
+    pub id: String,
+    /// The rows applied to the ledger so far
+    pub applied: Vec<T>,
+    /// Whether a row in the batch has failed, and the batch was rolled back
+    pub failed: bool,
+}
+
+impl<T> Batch<T> {
+    pub fn begin(id: String, ledger: &mut Ledger) -> Self {
+        ledger.begin();
+        Batch {
+            id,
+            applied: Vec::new(),
+            failed: false,
+        }
+    }
+
+    /// Undo the batch after one of its rows failed, returning the rows that had
+    /// already been applied
+    pub fn rollback(&mut self, ledger: &mut Ledger) -> Vec<T> {
+        if self.failed {
+            return Vec::new();
+        }
+        ledger.rollback();
+        self.failed = true;
+        std::mem::take(&mut self.applied)
+    }
+
+    /// Commit the batch, unless it was already rolled back
+    pub fn finish(self, ledger: &mut Ledger) {
+        if !self.failed {
+            ledger.commit();
+        }
+    }
+}
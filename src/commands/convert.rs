@@ -0,0 +1,63 @@
+use std::io::BufWriter;
+use std::process::ExitCode;
+
+use crate::binary::BinaryWriter;
+use crate::cli::ConvertArgs;
+use crate::input::{batches, TransactionReader};
+use crate::ledger::Error;
+use crate::report::RejectionReport;
+
+use super::{create, error_writer, Result, EXIT_PROBLEMS};
+
+/// Convert every transaction in the inputs to the binary format. Rows that
+/// can't be parsed are reported and left out, along with the rest of their
+/// batch
+pub fn convert(args: ConvertArgs) -> Result<ExitCode> {
+    let mut report =
+        RejectionReport::new(error_writer(args.errors.as_deref())?, args.errors_format);
+    let readers = TransactionReader::from_paths(&args.inputs, args.input_format, args.precision)?;
+    let mut wtr = BinaryWriter::new(BufWriter::new(create(&args.output)?))?;
+
+    let mut rows = 0;
+    let mut skipped = 0;
+    for group in readers.into_iter().flat_map(batches) {
+        let group = group?;
+        rows += group.len();
+
+        for row in &group {
+            if let (Ok(tx), Some(rounding)) = (&row.transaction, &row.rounding) {
+                report.rounded(row, tx, rounding)?;
+            }
+        }
+        // a batch with a row that can't be parsed would be rolled back, so
+        // none of it is written
+        let unparsed = group.iter().any(|row| row.transaction.is_err());
+        for row in &group {
+            match &row.transaction {
+                Err(reason) => {
+                    report.parse_error(row, reason)?;
+                    skipped += 1;
+                }
+                Ok(tx) if unparsed => {
+                    report.rejected(row, tx, &Error::BatchRolledBack)?;
+                    skipped += 1;
+                }
+                Ok(tx) => wtr.write(tx, row.batch.as_deref())?,
+            }
+        }
+        // the next group is a batch of its own, even with the same id
+        wtr.end_batch()?;
+    }
+    wtr.flush()?;
+    report.flush()?;
+    eprintln!(
+        "Converted {} rows, skipped {} that couldn't be parsed or were in a batch with one that couldn't",
+        rows - skipped,
+        skipped
+    );
+
+    if skipped > 0 {
+        return Ok(ExitCode::from(EXIT_PROBLEMS));
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::ExitCode;
+
+use crate::cli::DiffArgs;
+use crate::ledger::{Account, ClientId};
+
+use super::{Result, EXIT_PROBLEMS};
+
+/// Compare two account csv files by client, printing every account that only
+/// appears in one of them or differs between them. Amounts are compared by
+/// value, so `1.5` and `1.5000` are the same.
+pub fn diff(args: DiffArgs) -> Result<ExitCode> {
+    let left = read_accounts(&args.left)?;
+    let right = read_accounts(&args.right)?;
+
+    let mut clients: Vec<_> = left.keys().chain(right.keys()).collect();
+    clients.sort();
+    clients.dedup();
+
+    let mut differences = 0;
+    for client in clients {
+        let (l, r) = (left.get(client), right.get(client));
+        if l == r {
+            continue;
+        }
+        differences += 1;
+        if let Some(account) = l {
+            println!("< {}", row(account));
+        }
+        if let Some(account) = r {
+            println!("> {}", row(account));
+        }
+    }
+
+    if differences > 0 {
+        eprintln!("{} accounts differ", differences);
+        return Ok(ExitCode::from(EXIT_PROBLEMS));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn read_accounts(path: &Path) -> Result<BTreeMap<ClientId, Account>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .map_err(|e| format!("failed to open '{}': {}", path.display(), e))?;
+
+    let mut accounts = BTreeMap::new();
+    for result in rdr.deserialize() {
+        let account: Account =
+            result.map_err(|e| format!("invalid account in '{}': {}", path.display(), e))?;
+        accounts.insert(account.client, account);
+    }
+    Ok(accounts)
+}
+
+fn row(account: &Account) -> String {
+    format!(
+        "{},{},{},{},{}",
+        account.client, account.available, account.held, account.total, account.locked
+    )
+}
@@ -0,0 +1,29 @@
+use std::process::ExitCode;
+
+use crate::cli::{EndpointArgs, ListenArgs};
+use crate::server::{Endpoint, Listener, MetricsServer};
+
+use super::Result;
+
+pub fn listen(args: ListenArgs) -> Result<ExitCode> {
+    let mut ledger = args.ledger.load()?;
+    ledger.set_keep_history(args.ledger.needs_history());
+    let listener = Listener::bind(&args.endpoint.endpoint())?;
+    eprintln!("Listening on {}", listener.endpoint()?);
+    let metrics = args.metrics.map(MetricsServer::bind).transpose()?;
+    if let Some(addr) = metrics.as_ref().and_then(MetricsServer::addr) {
+        eprintln!("Serving metrics on http://{}/metrics", addr);
+    }
+    listener.run(ledger, args.precision, metrics);
+    Ok(ExitCode::SUCCESS)
+}
+
+impl EndpointArgs {
+    pub(super) fn endpoint(&self) -> Endpoint {
+        match (&self.tcp, &self.unix) {
+            (Some(addr), _) => Endpoint::Tcp(*addr),
+            (None, Some(path)) => Endpoint::Unix(path.clone()),
+            (None, None) => unreachable!("clap requires either --tcp or --unix"),
+        }
+    }
+}
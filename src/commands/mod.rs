@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub use convert::convert;
+pub use diff::diff;
+pub use listen::listen;
+pub use process::process;
+pub use repl::repl;
+pub use replay::replay;
+pub use send::send;
+pub use serve::serve;
+pub use validate::validate;
+
+pub(crate) use batch::Batch;
+
+mod batch;
+mod convert;
+mod diff;
+mod listen;
+mod process;
+mod repl;
+mod replay;
+mod send;
+mod serve;
+mod validate;
+
+/// Exit code for when a command ran to completion but found a problem, e.g.
+/// problems found by `validate` or two account files that differ
+pub const EXIT_PROBLEMS: u8 = 1;
+/// Exit code for when a command couldn't run at all, e.g. a missing input file.
+/// This matches the exit code used for invalid command line arguments
+pub const EXIT_ERROR: u8 = 2;
+/// Exit code for when processing in strict mode stopped at a failed
+/// transaction, in which case no output is written at all
+pub const EXIT_ABORTED: u8 = 3;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Open a file to write output to, or stdout if no path is given
+fn output_writer(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    match path {
+        Some(path) => Ok(Box::new(BufWriter::new(create(path)?))),
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+/// Open a file to report failed transactions to, or stderr if no path is given
+fn error_writer(path: Option<&Path>) -> Result<Box<dyn Write>> {
+    match path {
+        Some(path) => Ok(Box::new(BufWriter::new(create(path)?))),
+        None => Ok(Box::new(io::stderr())),
+    }
+}
+
+fn create(path: &Path) -> Result<File> {
+    let file =
+        File::create(path).map_err(|e| format!("failed to create '{}': {}", path.display(), e))?;
+    Ok(file)
+}
@@ -0,0 +1,410 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::process::ExitCode;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::{Format, ProcessArgs, SortKey};
+use crate::input::{Row, TransactionReader};
+use crate::ledger::{
+    Account, ClientId, ClientIdRepr, Error, Ledger, Limits, Patterns, Rules, Sequence,
+    DEFAULT_MAX_DISPUTES,
+};
+use crate::metrics::Metrics;
+use crate::report::{write_suspicious_activity, AlertReport, Quarantine, RejectionReport};
+
+use super::{create, error_writer, output_writer, Batch, Result, EXIT_ABORTED};
+
+pub fn process(args: ProcessArgs) -> Result<ExitCode> {
+    run(Ledger::new(), &args)
+}
+
+/// Process every transaction in each input, in order, against `ledger` and
+/// write out the resulting accounts. Shared with `replay`, which starts from a
+/// restored ledger
+pub(super) fn run(mut ledger: Ledger, args: &ProcessArgs) -> Result<ExitCode> {
+    let mut reports = Reports::create(args)?;
+    let readers = TransactionReader::from_paths(&args.inputs, args.input_format, args.precision)?;
+    let mut metrics = Metrics::new(&ledger);
+    let patterns = load_config(&mut ledger, args)?;
+
+    let applied = apply_rows(
+        &mut ledger,
+        readers,
+        &mut reports,
+        &mut metrics,
+        args.strict,
+    );
+    reports.flush()?;
+    if let ControlFlow::Break(aborted) = applied? {
+        return Ok(aborted);
+    }
+    eprintln!("Done processing!");
+
+    write_reports(&ledger, &metrics, patterns.as_ref(), args)?;
+    write_accounts(&ledger, args)?;
+    if let Some(path) = &args.save_snapshot {
+        let wtr = BufWriter::new(create(path)?);
+        serde_json::to_writer(wtr, &ledger.snapshot())?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Everything written about the rows as they are processed
+struct Reports {
+    rejections: RejectionReport,
+    quarantine: Option<Quarantine>,
+    alerts: AlertReport,
+    /// Whether to warn that a dispute was rejected by the default cap, which
+    /// earlier versions didn't have. Only the first one is warned about
+    warn_dispute_cap: bool,
+}
+
+impl Reports {
+    fn create(args: &ProcessArgs) -> Result<Self> {
+        let rejections =
+            RejectionReport::new(error_writer(args.errors.as_deref())?, args.errors_format);
+        let quarantine = args
+            .quarantine
+            .as_deref()
+            .map(Quarantine::create)
+            .transpose()?;
+        let alerts = match &args.alerts {
+            Some(path) => AlertReport::Jsonl(Box::new(BufWriter::new(create(path)?))),
+            None => AlertReport::Text(Box::new(io::stderr())),
+        };
+        Ok(Reports {
+            rejections,
+            quarantine,
+            alerts,
+            warn_dispute_cap: args.max_disputes.is_none(),
+        })
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.rejections.flush()?;
+        self.alerts.flush()?;
+        if let Some(quarantine) = &mut self.quarantine {
+            quarantine.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Set the ledger up as asked, returning the money laundering patterns to
+/// look for once processing is done
+fn load_config(ledger: &mut Ledger, args: &ProcessArgs) -> Result<Option<Patterns>> {
+    ledger.set_max_disputes(args.max_disputes.unwrap_or(DEFAULT_MAX_DISPUTES));
+    // only keep history if something is going to look at it
+    ledger.set_keep_history(
+        args.statement.is_some()
+            || args.as_of.is_some()
+            || args.save_snapshot.is_some()
+            || args.limits.is_some()
+            || args.rules.is_some()
+            || args.aml.is_some(),
+    );
+    if let Some(path) = &args.limits {
+        ledger.set_limits(load_limits(path, args.tiers.as_deref())?);
+    }
+    if let Some(path) = &args.rules {
+        ledger.set_rules(load_rules(path)?);
+    }
+    args.aml.as_deref().map(load_patterns).transpose()
+}
+
+/// Apply every row of the inputs to the ledger, reporting the ones that fail.
+/// Breaks with the exit code to leave with if processing was aborted in strict
+/// mode
+fn apply_rows(
+    ledger: &mut Ledger,
+    readers: Vec<TransactionReader>,
+    reports: &mut Reports,
+    metrics: &mut Metrics,
+    strict: bool,
+) -> Result<ControlFlow<ExitCode>> {
+    for rdr in readers {
+        // batches never span more than one input
+        let mut batch: Option<Batch<_>> = None;
+        for row in rdr {
+            let row = row?;
+            metrics.read(&row.transaction);
+            // Consecutive rows with the same batch id make up a batch, unless the
+            // input marks where a new one starts, and it's committed once a row
+            // outside of it is reached
+            if !row.continues_batch(batch.as_ref().map(|b| b.id.as_str())) {
+                if let Some(batch) = batch.take() {
+                    batch.finish(ledger);
+                }
+                batch = row.batch.clone().map(|id| Batch::begin(id, ledger));
+            }
+
+            if let (Ok(tx), Some(rounding)) = (&row.transaction, &row.rounding) {
+                reports.rejections.rounded(&row, tx, rounding)?;
+            }
+
+            // once one member of a batch has failed, the rest of it is skipped
+            if let (Some(Batch { failed: true, .. }), Ok(tx)) = (&batch, &row.transaction) {
+                reports
+                    .rejections
+                    .rejected(&row, tx, &Error::BatchRolledBack)?;
+                metrics.rejected(&Error::BatchRolledBack);
+                continue;
+            }
+
+            let Some(failure) = apply_row(ledger, &row, reports, metrics)? else {
+                if let Some(batch) = &mut batch {
+                    batch.applied.push(row);
+                }
+                continue;
+            };
+
+            // In strict mode the whole input is rejected on the first failure. The
+            // ledger only lives in memory until it's written out, so dropping it
+            // without writing anything rolls back every transaction before this one
+            if strict {
+                reports.flush()?;
+                eprintln!(
+                    "Aborted: line {} (byte {}) in '{}' failed in strict mode: {}: {}",
+                    row.line,
+                    row.byte,
+                    row.file,
+                    row.raw_text(),
+                    failure
+                );
+                eprintln!("No accounts were written");
+                return Ok(ControlFlow::Break(ExitCode::from(EXIT_ABORTED)));
+            }
+
+            if let Some(batch) = &mut batch {
+                for applied in batch.rollback(ledger) {
+                    if let Ok(tx) = &applied.transaction {
+                        reports
+                            .rejections
+                            .rejected(&applied, tx, &Error::BatchRolledBack)?;
+                        metrics.rejected(&Error::BatchRolledBack);
+                    }
+                }
+            }
+        }
+        if let Some(batch) = batch.take() {
+            batch.finish(ledger);
+        }
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Apply a single row to the ledger, reporting it if it fails along with any
+/// alerts it raised. Returns why it failed, if it did
+fn apply_row(
+    ledger: &mut Ledger,
+    row: &Row,
+    reports: &mut Reports,
+    metrics: &mut Metrics,
+) -> Result<Option<String>> {
+    let failure = match &row.transaction {
+        Ok(tx) => match ledger.process(tx.clone()) {
+            Ok(()) => None,
+            Err(err) => {
+                reports.rejections.rejected(row, tx, &err)?;
+                metrics.rejected(&err);
+                if let (Some(quarantine), Error::UnknownTransactionType) =
+                    (&mut reports.quarantine, &err)
+                {
+                    quarantine.add(row, &err.to_string())?;
+                }
+                if let (true, Error::DisputeLimitReached { .. }) = (reports.warn_dispute_cap, &err)
+                {
+                    reports.warn_dispute_cap = false;
+                    eprintln!(
+                        "Warning: a dispute was rejected as transactions can only be disputed \
+                         once by default, which earlier versions allowed any number of times. \
+                         Use --max-disputes to allow more"
+                    );
+                }
+                Some(err.to_string())
+            }
+        },
+        Err(reason) => {
+            reports.rejections.parse_error(row, reason)?;
+            if let Some(quarantine) = &mut reports.quarantine {
+                quarantine.add(row, reason)?;
+            }
+            Some(reason.clone())
+        }
+    };
+    for alert in ledger.take_alerts() {
+        reports.alerts.add(&alert)?;
+    }
+    Ok(failure)
+}
+
+/// Write everything asked for about the run besides the accounts themselves
+fn write_reports(
+    ledger: &Ledger,
+    metrics: &Metrics,
+    patterns: Option<&Patterns>,
+    args: &ProcessArgs,
+) -> Result<()> {
+    if args.summary {
+        metrics.write_summary(ledger, &mut io::stderr())?;
+    }
+    if let Some(path) = &args.metrics {
+        let mut wtr = BufWriter::new(create(path)?);
+        wtr.write_all(metrics.prometheus(ledger).as_bytes())?;
+        wtr.flush()?;
+    }
+    // the money laundering checks only report, so they don't change the
+    // accounts written out after
+    if let Some(patterns) = patterns {
+        let out = error_writer(args.suspicious_activity.as_deref())?;
+        write_suspicious_activity(
+            out,
+            args.suspicious_activity_format,
+            &ledger.analyze(patterns),
+        )?;
+    }
+    if let Some(as_of) = args.as_of.filter(|as_of| *as_of > ledger.sequence()) {
+        eprintln!(
+            "warning: --as-of {} is past the last transaction processed ({}), writing the final accounts",
+            as_of,
+            ledger.sequence()
+        );
+    }
+    Ok(())
+}
+
+/// Read and check the limits declared in a JSON file, assigning clients to
+/// tiers from the `tiers` csv if there is one
+pub(super) fn load_limits(path: &Path, tiers: Option<&Path>) -> Result<Limits> {
+    let mut limits: Limits = read_json(path, "limits", Limits::validate)?;
+
+    if let Some(path) = tiers {
+        #[derive(Deserialize)]
+        struct Assignment {
+            client: ClientIdRepr,
+            tier: String,
+        }
+        let invalid =
+            |e: &dyn std::fmt::Display| format!("invalid tiers '{}': {}", path.display(), e);
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| format!("failed to open '{}': {}", path.display(), e))?;
+        for assignment in rdr.deserialize() {
+            let Assignment { client, tier } = assignment.map_err(|e| invalid(&e))?;
+            limits
+                .assign(ClientId::from(client), &tier)
+                .map_err(|e| invalid(&e))?;
+        }
+    }
+    Ok(limits)
+}
+
+/// Read and check the fraud rules declared in a JSON file
+pub(super) fn load_rules(path: &Path) -> Result<Rules> {
+    read_json(path, "rules", Rules::validate)
+}
+
+/// Read and check the money laundering patterns declared in a JSON file
+fn load_patterns(path: &Path) -> Result<Patterns> {
+    read_json(path, "patterns", Patterns::validate)
+}
+
+/// Read a JSON file into `T`, checking it with `validate`. `what` names what
+/// the file holds in errors
+fn read_json<T: DeserializeOwned>(
+    path: &Path,
+    what: &str,
+    validate: fn(&T) -> std::result::Result<(), String>,
+) -> Result<T> {
+    let file =
+        File::open(path).map_err(|e| format!("failed to open '{}': {}", path.display(), e))?;
+    let invalid =
+        |e: &dyn std::fmt::Display| format!("invalid {} '{}': {}", what, path.display(), e);
+    let value = serde_json::from_reader(BufReader::new(file)).map_err(|e| invalid(&e))?;
+    validate(&value).map_err(|e| invalid(&e))?;
+    Ok(value)
+}
+
+/// Write the accounts, or the statement asked for, in the output format
+fn write_accounts(ledger: &Ledger, args: &ProcessArgs) -> Result<()> {
+    if let Some(client) = args.statement {
+        let as_of = args.as_of.unwrap_or(Sequence::MAX);
+        let lines: Vec<_> = ledger
+            .statement(ClientId::from(client))
+            .take_while(|line| line.sequence <= as_of)
+            .collect();
+        return write_output(&lines, args);
+    }
+    let accounts = match args.as_of {
+        Some(sequence) => ledger.accounts_at(sequence).collect(),
+        None => ledger.accounts().collect(),
+    };
+    write_output(&sorted_accounts(accounts, args.sort, args.descending), args)
+}
+
+fn write_output<T: Serialize>(records: &[T], args: &ProcessArgs) -> Result<()> {
+    let mut output = output_writer(args.output.as_deref())?;
+    let format = args
+        .format
+        .or_else(|| args.output.as_deref().and_then(Format::from_path))
+        .unwrap_or(Format::Csv);
+    match format {
+        Format::Csv => {
+            let mut wtr = csv::Writer::from_writer(output);
+            for record in records {
+                wtr.serialize(record)?;
+            }
+            wtr.flush()?;
+        }
+        Format::Json => {
+            serde_json::to_writer(&mut output, records)?;
+            writeln!(output)?;
+            output.flush()?;
+        }
+        Format::Jsonl => {
+            for record in records {
+                serde_json::to_writer(&mut output, record)?;
+                writeln!(output)?;
+            }
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Put the accounts in the order they should be written. Accounts start out
+/// ordered by client, and the sort is stable, so ties stay that way
+fn sorted_accounts(mut accounts: Vec<&Account>, key: SortKey, descending: bool) -> Vec<&Account> {
+    accounts.sort_by(|a, b| {
+        let order = match key {
+            SortKey::Client => a.client.cmp(&b.client),
+            SortKey::Available => a.available.cmp(&b.available),
+            SortKey::Held => a.held.cmp(&b.held),
+            SortKey::Total => a.total.cmp(&b.total),
+        };
+        if descending {
+            order.reverse()
+        } else {
+            order
+        }
+    });
+    accounts
+}
+
+impl Format {
+    /// Pick the format from the extension of the output file, if it's one
+    /// we know
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            "jsonl" | "ndjson" => Some(Format::Jsonl),
+            _ => None,
+        }
+    }
+}
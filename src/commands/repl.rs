@@ -0,0 +1,242 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufWriter, IsTerminal, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+use crate::cli::{LedgerArgs, ReplArgs};
+use crate::input::{parse_line, TransactionReader};
+use crate::ledger::{ClientId, ClientIdRepr, Ledger, Precision, TxId};
+use crate::server::{report_alerts, submit, Failure};
+
+use super::replay::load_snapshot;
+use super::{create, Result};
+
+/// How many steps can be undone. Past this the oldest step is kept for good,
+/// so the savepoints, and the memory they hold, don't grow with the session
+const MAX_UNDO: usize = 100;
+
+const HELP: &str = "\
+Commands:
+  <type>,<client>,<tx>,<amount>  process a transaction, e.g. deposit,1,1,5.0
+  account <client>               show an account
+  accounts                       show every account
+  tx <tx>                        show a logged transaction and its state
+  statement <client>             show every change made to an account
+  load <path>                    process every transaction in a file
+  save <path>                    save a snapshot of the ledger
+  restore <path>                 replace the ledger with a saved snapshot
+  undo                           undo the last transaction or load, up to 100 times
+  help                           show this message
+  quit                           leave";
+
+/// Explore a ledger interactively, reading commands from stdin
+pub fn repl(args: ReplArgs) -> Result<ExitCode> {
+    let mut repl = Repl {
+        ledger: args.ledger.load()?,
+        config: args.ledger,
+        precision: args.precision,
+        undo: VecDeque::new(),
+    };
+
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut out = io::stdout();
+    if interactive {
+        writeln!(out, "Type 'help' for a list of commands")?;
+    }
+    let mut lines = stdin.lock().lines();
+    loop {
+        if interactive {
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if matches!(line, "quit" | "exit") {
+            break;
+        }
+        // a command failing only means it's reported, the session carries on
+        if let Err(err) = repl.run(line, &mut out) {
+            writeln!(out, "error: {}", err)?;
+        }
+        out.flush()?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+struct Repl {
+    ledger: Ledger,
+    /// How the ledger is set up, to set up a restored one the same way
+    config: LedgerArgs,
+    precision: Precision,
+    /// What was done in each savepoint left open on the ledger, most recent
+    /// last. Every transaction or load that changed the ledger gets its own
+    /// savepoint, so it can be undone by rolling it back, up to `MAX_UNDO`
+    undo: VecDeque<String>,
+}
+
+impl Repl {
+    fn run(&mut self, line: &str, out: &mut impl Write) -> Result<()> {
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, Some(arg.trim())),
+            None => (line, None),
+        };
+        let arg = || arg.ok_or_else(|| format!("'{}' needs an argument, see 'help'", command));
+
+        match command {
+            "help" => writeln!(out, "{}", HELP)?,
+            "account" => {
+                let client = parse_client(arg()?)?;
+                match self.ledger.account(client) {
+                    Some(account) => write_csv(out, [account])?,
+                    None => writeln!(out, "account {} not found", client)?,
+                }
+            }
+            "accounts" => write_csv(out, self.ledger.accounts())?,
+            "tx" => match self.ledger.transaction(&TxId::new(arg()?)) {
+                Some(lt) => {
+                    let tx = &lt.transaction;
+                    let amount = tx.amount().map(|a| a.to_string()).unwrap_or_default();
+                    writeln!(out, "type,client,tx,amount,state")?;
+                    writeln!(
+                        out,
+                        "{},{},{},{},{:?}",
+                        tx.kind(),
+                        tx.client_id(),
+                        tx.tx_id(),
+                        amount,
+                        lt.state
+                    )?;
+                }
+                None => writeln!(out, "transaction {} not found", arg()?)?,
+            },
+            "statement" => {
+                let client = parse_client(arg()?)?;
+                match self.ledger.statement(client).collect::<Vec<_>>() {
+                    lines if lines.is_empty() => writeln!(out, "account {} not found", client)?,
+                    lines => write_csv(out, lines)?,
+                }
+            }
+            "load" => self.load(Path::new(arg()?), out)?,
+            "save" => {
+                let path = Path::new(arg()?);
+                let wtr = BufWriter::new(create(path)?);
+                serde_json::to_writer(wtr, &self.ledger.snapshot())?;
+                writeln!(out, "saved a snapshot to '{}'", path.display())?;
+            }
+            "restore" => {
+                let path = Path::new(arg()?);
+                let mut ledger = load_snapshot(path)?;
+                self.config.configure(&mut ledger)?;
+                self.ledger = ledger;
+                self.undo.clear();
+                writeln!(out, "restored the snapshot '{}'", path.display())?;
+            }
+            "undo" => match self.undo.pop_back() {
+                Some(done) => {
+                    self.ledger.rollback();
+                    writeln!(out, "undid {}", done)?;
+                }
+                None => writeln!(out, "nothing to undo")?,
+            },
+            _ => self.process(line, out)?,
+        }
+        Ok(())
+    }
+
+    /// Process a single transaction row, keeping a savepoint to undo it with
+    /// if it went through
+    fn process(&mut self, line: &str, out: &mut impl Write) -> Result<()> {
+        let (tx, rounding) = parse_line(line, self.precision);
+        let tx = tx.map_err(|reason| format!("failed to parse '{}': {}", line, reason))?;
+        if let Some(rounding) = rounding {
+            writeln!(out, "{}", rounding)?;
+        }
+
+        self.ledger.begin();
+        let result = self.ledger.process(tx.clone());
+        report_alerts(&mut self.ledger);
+        match result {
+            Ok(()) => {
+                self.done(format!(
+                    "{} {} of client {}",
+                    tx.kind(),
+                    tx.tx_id(),
+                    tx.client_id()
+                ));
+                writeln!(out, "ok")?;
+            }
+            Err(err) => {
+                self.ledger.rollback();
+                writeln!(out, "error: {} ({})", err, err.code())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Process every transaction in a file, as a single step to undo
+    fn load(&mut self, path: &Path, out: &mut impl Write) -> Result<()> {
+        // the whole file is read first, so nothing is processed if it can't be
+        let rows = TransactionReader::from_path(path, None, self.precision)?
+            .collect::<Result<Vec<_>>>()?;
+        self.ledger.begin();
+        let outcomes = submit(&mut self.ledger, rows);
+        let mut failed = 0;
+        for outcome in &outcomes {
+            if let Some(rounding) = &outcome.row.rounding {
+                writeln!(out, "line {}: {}", outcome.row.line, rounding)?;
+            }
+            let reason = match &outcome.result {
+                Ok(()) => continue,
+                Err(Failure::Parse(reason)) => format!("failed to parse: {}", reason),
+                Err(Failure::Rejected(err)) => format!("{} ({})", err, err.code()),
+            };
+            writeln!(out, "line {}: {}", outcome.row.line, reason)?;
+            failed += 1;
+        }
+        writeln!(
+            out,
+            "loaded {} transactions from '{}', {} failed",
+            outcomes.len() - failed,
+            path.display(),
+            failed
+        )?;
+        self.done(format!("loading '{}'", path.display()));
+        Ok(())
+    }
+
+    /// Keep the savepoint just opened to undo a step with, letting go of the
+    /// oldest one if there are too many
+    fn done(&mut self, step: String) {
+        self.undo.push_back(step);
+        if self.undo.len() > MAX_UNDO {
+            self.ledger.commit_outermost();
+            self.undo.pop_front();
+        }
+    }
+}
+
+fn parse_client(client: &str) -> Result<ClientId> {
+    let client = client
+        .parse::<ClientIdRepr>()
+        .map_err(|_| format!("invalid client id '{}'", client))?;
+    Ok(ClientId::from(client))
+}
+
+fn write_csv<T: serde::Serialize>(
+    out: &mut impl Write,
+    records: impl IntoIterator<Item = T>,
+) -> Result<()> {
+    let mut wtr = csv::Writer::from_writer(out);
+    for record in records {
+        wtr.serialize(record)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::process::ExitCode;
+
+use crate::cli::ReplayArgs;
+use crate::ledger::{Ledger, Snapshot};
+
+use super::{process, Result};
+
+pub fn replay(args: ReplayArgs) -> Result<ExitCode> {
+    process::run(load_snapshot(&args.snapshot)?, &args.process)
+}
+
+/// Restore a ledger from a snapshot saved with `--save-snapshot`
+pub(super) fn load_snapshot(path: &Path) -> Result<Ledger> {
+    let file =
+        File::open(path).map_err(|e| format!("failed to open '{}': {}", path.display(), e))?;
+    let snapshot: Snapshot = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format!("invalid snapshot '{}': {}", path.display(), e))?;
+    Ok(Ledger::from_snapshot(snapshot))
+}
@@ -0,0 +1,67 @@
+use std::process::ExitCode;
+
+use crate::cli::SendArgs;
+use crate::input::{batches, TransactionReader};
+use crate::ledger::Transaction;
+use crate::server::{Ack, Client};
+
+use super::{Result, EXIT_PROBLEMS};
+
+/// Send every transaction in the inputs to a listener, reporting the ones that
+/// couldn't be parsed or were rejected
+pub fn send(args: SendArgs) -> Result<ExitCode> {
+    let readers = TransactionReader::from_paths(&args.inputs, args.input_format, args.precision)?;
+    let endpoint = args.endpoint.endpoint();
+    let mut client = Client::connect(&endpoint)
+        .map_err(|e| format!("failed to connect to {}: {}", endpoint, e))?;
+
+    let mut sent = 0;
+    let mut failed = 0;
+    for group in readers.into_iter().flat_map(batches) {
+        let group = group?;
+
+        let unparsed = group.iter().filter(|row| row.transaction.is_err()).count();
+        for row in &group {
+            if let Err(reason) = &row.transaction {
+                eprintln!(
+                    "failed to parse line {} (byte {}) in '{}': {}",
+                    row.line, row.byte, row.file, reason
+                );
+            }
+        }
+        let txs: Vec<Transaction> = group
+            .iter()
+            .filter_map(|row| row.transaction.as_ref().ok().cloned())
+            .collect();
+        failed += unparsed;
+        // a batch with a row that can't be parsed would be rolled back, so
+        // none of it is sent
+        let acks = match (&group[0].batch, unparsed) {
+            (Some(batch), 0) => client.send_batch(batch, &txs)?,
+            (Some(batch), _) => {
+                for tx in &txs {
+                    eprintln!("{} not sent: batch '{}' failed to parse", tx, batch);
+                }
+                failed += txs.len();
+                continue;
+            }
+            (None, _) => txs
+                .iter()
+                .map(|tx| client.send_transaction(tx))
+                .collect::<std::io::Result<_>>()?,
+        };
+        sent += txs.len();
+        for (tx, ack) in txs.iter().zip(acks) {
+            if let Ack::Error { code, message } = ack {
+                eprintln!("{} failed: {} ({})", tx, message, code);
+                failed += 1;
+            }
+        }
+    }
+    eprintln!("Sent {} transactions, {} failed", sent, failed);
+
+    if failed > 0 {
+        return Ok(ExitCode::from(EXIT_PROBLEMS));
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,48 @@
+use std::process::ExitCode;
+
+use crate::cli::{LedgerArgs, ServeArgs};
+use crate::ledger::Ledger;
+use crate::server;
+
+use super::process::{load_limits, load_rules};
+use super::replay::load_snapshot;
+use super::Result;
+
+pub fn serve(args: ServeArgs) -> Result<ExitCode> {
+    let mut ledger = args.ledger.load()?;
+    ledger.set_keep_history(args.ledger.needs_history());
+    server::serve(ledger, args.listen, args.precision)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+impl LedgerArgs {
+    /// The ledger to start from, empty or loaded from the snapshot, set up
+    /// as asked
+    pub(super) fn load(&self) -> Result<Ledger> {
+        let mut ledger = match &self.snapshot {
+            Some(path) => load_snapshot(path)?,
+            None => Ledger::new(),
+        };
+        self.configure(&mut ledger)?;
+        Ok(ledger)
+    }
+
+    /// Set up a ledger as asked. None of it is saved in snapshots, so a ledger
+    /// restored from one needs setting up again
+    pub(super) fn configure(&self, ledger: &mut Ledger) -> Result<()> {
+        ledger.set_max_disputes(self.max_disputes);
+        if let Some(path) = &self.limits {
+            ledger.set_limits(load_limits(path, self.tiers.as_deref())?);
+        }
+        if let Some(path) = &self.rules {
+            ledger.set_rules(load_rules(path)?);
+        }
+        Ok(())
+    }
+
+    /// Whether the ledger has to keep history. The limits and rules look back
+    /// over each client's history, but nothing else a service offers does
+    pub(super) fn needs_history(&self) -> bool {
+        self.limits.is_some() || self.rules.is_some()
+    }
+}
@@ -0,0 +1,65 @@
+use std::process::ExitCode;
+
+use crate::cli::ValidateArgs;
+use crate::input::TransactionReader;
+use crate::ledger::Error;
+use crate::report::{Quarantine, RejectionReport};
+
+use super::{error_writer, Result, EXIT_PROBLEMS};
+
+/// Parse every transaction without processing it, reporting rows that can't be
+/// parsed along with transactions that could never be processed successfully
+pub fn validate(args: ValidateArgs) -> Result<ExitCode> {
+    let mut report =
+        RejectionReport::new(error_writer(args.errors.as_deref())?, args.errors_format);
+    let mut quarantine = args
+        .quarantine
+        .as_deref()
+        .map(Quarantine::create)
+        .transpose()?;
+    let readers = TransactionReader::from_paths(&args.inputs, args.input_format, args.precision)?;
+
+    let mut rows = 0;
+    let mut problems = 0;
+    for row in readers.into_iter().flatten() {
+        let row = row?;
+        rows += 1;
+        if let (Ok(tx), Some(rounding)) = (&row.transaction, &row.rounding) {
+            report.rounded(&row, tx, rounding)?;
+        }
+        match &row.transaction {
+            Ok(tx) => match tx.validate() {
+                Ok(()) => continue,
+                Err(err) => {
+                    report.rejected(&row, tx, &err)?;
+                    if let (Some(quarantine), Error::UnknownTransactionType) =
+                        (&mut quarantine, &err)
+                    {
+                        quarantine.add(&row, &err.to_string())?;
+                    }
+                }
+            },
+            Err(reason) => {
+                report.parse_error(&row, reason)?;
+                if let Some(quarantine) = &mut quarantine {
+                    quarantine.add(&row, reason)?;
+                }
+            }
+        }
+
+        problems += 1;
+        if args.strict {
+            break;
+        }
+    }
+    report.flush()?;
+    if let Some(quarantine) = &mut quarantine {
+        quarantine.flush()?;
+    }
+    eprintln!("Validated {} rows, found {} problems", rows, problems);
+
+    if problems > 0 {
+        return Ok(ExitCode::from(EXIT_PROBLEMS));
+    }
+    Ok(ExitCode::SUCCESS)
+}
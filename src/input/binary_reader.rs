@@ -0,0 +1,74 @@
+use std::io::Read;
+use std::rc::Rc;
+
+use crate::binary::BinaryReader;
+use crate::commands::Result;
+
+use super::{read_error, Row};
+
+/// Reads rows from a binary transaction file. Each record is reported as its
+/// own line, numbered from 1, and since the record itself isn't text, the raw
+/// row is the transaction written out as a csv row, with its batch id if it has
+/// one. A record that can't be decoded is kept as its bytes in hex instead, so
+/// it can still be recovered
+pub struct BinaryRowReader<R: Read> {
+    file: Rc<str>,
+    rdr: BinaryReader<R>,
+    /// The number of records read so far
+    records: u64,
+}
+
+impl<R: Read> BinaryRowReader<R> {
+    /// Read the header, checking the file is in a format we can read. `file`
+    /// is the name rows are reported against
+    pub fn new(rdr: R, file: &str) -> Result<Self> {
+        let rdr = BinaryReader::new(rdr).map_err(|e| format!("'{}': {}", file, e))?;
+        Ok(BinaryRowReader {
+            file: file.into(),
+            rdr,
+            records: 0,
+        })
+    }
+}
+
+impl<R: Read> Iterator for BinaryRowReader<R> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        let (byte, transaction) = match self.rdr.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(read_error(&self.file, e))),
+        };
+        self.records += 1;
+        let mut raw = match &transaction {
+            Ok(tx) => format!(
+                "{},{},{},{}",
+                tx.kind(),
+                tx.client_id(),
+                tx.tx_id(),
+                tx.amount().map(|a| a.to_string()).unwrap_or_default()
+            ),
+            Err(_) => self
+                .rdr
+                .record()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        };
+        if let (Ok(_), Some(batch)) = (&transaction, self.rdr.batch()) {
+            raw.push(',');
+            raw.push_str(batch);
+        }
+        let raw = raw.into_bytes();
+        Some(Ok(Row {
+            file: self.file.clone(),
+            line: self.records,
+            byte,
+            raw,
+            batch: self.rdr.batch().map(str::to_string),
+            starts_batch: self.rdr.starts_batch(),
+            transaction,
+            rounding: None,
+        }))
+    }
+}
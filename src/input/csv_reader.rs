@@ -0,0 +1,214 @@
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use csv::{ErrorKind, Position, StringRecord};
+
+use crate::commands::Result;
+use crate::ledger::Precision;
+
+use super::{parse_record, read_error, Row, REQUIRED_COLUMNS};
+
+/// Reads rows from a transaction csv, keeping track of where each row came
+/// from so failures can be reported against the original input
+pub struct CsvReader<R> {
+    file: Rc<str>,
+    rdr: csv::Reader<Recorder<R>>,
+    headers: StringRecord,
+    /// The index of the `batch` column, if the input has one
+    batch_column: Option<usize>,
+    precision: Precision,
+    record: StringRecord,
+}
+
+impl<R: Read> CsvReader<R> {
+    /// Read the header of the csv, checking it has every required column.
+    /// `file` is the name rows are reported against
+    pub fn new(rdr: R, file: &str, precision: Precision) -> Result<Self> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(Recorder::new(rdr));
+        let headers = rdr
+            .headers()
+            .map_err(|e| format!("failed to read header of '{}': {}", file, e))?
+            .clone();
+        if let Some(missing) = REQUIRED_COLUMNS
+            .iter()
+            .find(|col| !headers.iter().any(|h| h == **col))
+        {
+            return Err(format!("'{}' is missing the '{}' column", file, missing).into());
+        }
+        let batch_column = headers.iter().position(|h| h == "batch");
+        let end = rdr.position().byte();
+        rdr.get_mut().take(end);
+
+        Ok(CsvReader {
+            file: file.into(),
+            rdr,
+            headers,
+            batch_column,
+            precision,
+            record: StringRecord::new(),
+        })
+    }
+}
+
+impl<R: Read> Iterator for CsvReader<R> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        let read = self.rdr.read_record(&mut self.record);
+        let start = self
+            .record
+            .position()
+            .cloned()
+            .unwrap_or_else(Position::new);
+        let end = self.rdr.position().byte();
+        let raw = self.rdr.get_mut().take(end);
+
+        let (transaction, rounding) = match read {
+            Ok(false) => return None,
+            // the rest of the input can't be read, which isn't a problem with
+            // this row but with the whole input
+            Err(e) if e.is_io_error() => return Some(Err(read_error(&self.file, e))),
+            Err(e) => (Err(parse_reason(&e)), None),
+            Ok(true) => parse_record(&self.record, &self.headers, self.precision),
+        };
+        let batch = self
+            .batch_column
+            .and_then(|i| self.record.get(i))
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+
+        // blank lines before a row are skipped over by the csv reader, but are
+        // still part of the bytes read for it
+        let is_terminator = |b: &u8| matches!(b, b'\r' | b'\n');
+        let skipped = raw.iter().take_while(|b| is_terminator(b)).count();
+        let line = start.line() + raw[..skipped].iter().filter(|b| **b == b'\n').count() as u64;
+        let end = raw.len() - raw.iter().rev().take_while(|b| is_terminator(b)).count();
+        Some(Ok(Row {
+            file: self.file.clone(),
+            line,
+            byte: start.byte() + skipped as u64,
+            raw: raw[skipped..end.max(skipped)].to_vec(),
+            batch,
+            starts_batch: false,
+            transaction,
+            rounding,
+        }))
+    }
+}
+
+/// A short description of why a row couldn't be parsed, without the position
+/// information the csv crate includes in its errors
+pub(super) fn parse_reason(err: &csv::Error) -> String {
+    match err.kind() {
+        ErrorKind::UnequalLengths {
+            expected_len, len, ..
+        } => format!("found {} fields, expected {}", len, expected_len),
+        ErrorKind::Deserialize { err, .. } => err.to_string(),
+        // already starts with "invalid utf-8"
+        ErrorKind::Utf8 { err, .. } => err.to_string(),
+        _ => err.to_string(),
+    }
+}
+
+/// Keeps a copy of every byte read through it until it is taken back out, so
+/// the exact text of each row can be recovered after the csv reader (which
+/// reads ahead into its own buffer) has parsed it
+struct Recorder<R> {
+    inner: R,
+    buf: Vec<u8>,
+    /// The offset in the input of the first byte in `buf`
+    offset: u64,
+}
+
+impl<R> Recorder<R> {
+    fn new(inner: R) -> Self {
+        Recorder {
+            inner,
+            buf: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Take every byte recorded up to (but not including) the offset `end`
+    fn take(&mut self, end: u64) -> Vec<u8> {
+        let len = ((end - self.offset) as usize).min(self.buf.len());
+        self.offset += len as u64;
+        self.buf.drain(..len).collect()
+    }
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.buf.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_are_verbatim() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\r\n\
+                     \n\
+                     withdrawal,100,1.134\n\
+                     deposit,1,\"2\",5";
+        let rows: Vec<_> = CsvReader::new(input.as_bytes(), "input.csv", Precision::Reject)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|row| &*row.file == "input.csv"));
+
+        assert_eq!(rows[0].raw_text(), "deposit, 1, 1, 1.0");
+        assert_eq!((rows[0].line, rows[0].byte), (2, 25));
+        assert!(rows[0].transaction.is_ok());
+
+        assert_eq!(rows[1].raw_text(), "withdrawal,100,1.134");
+        assert_eq!((rows[1].line, rows[1].byte), (4, 46));
+        assert_eq!(
+            rows[1].transaction.as_ref().unwrap_err(),
+            "found 3 fields, expected 4"
+        );
+
+        assert_eq!(rows[2].raw_text(), "deposit,1,\"2\",5");
+        assert_eq!((rows[2].line, rows[2].byte), (5, 67));
+        assert!(rows[2].transaction.is_ok());
+    }
+
+    #[test]
+    fn test_read_error() {
+        /// An input that fails part way through, like a truncated gzip file
+        struct Broken;
+
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt"))
+            }
+        }
+
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\n"
+            .as_bytes()
+            .chain(Broken);
+        let mut rows = CsvReader::new(input, "input.csv", Precision::Reject).unwrap();
+        assert!(rows.next().unwrap().unwrap().transaction.is_ok());
+        assert_eq!(
+            rows.next().unwrap().unwrap_err().to_string(),
+            "failed to read 'input.csv': corrupt"
+        );
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let input = "type,client,amount\ndeposit,1,1.0\n";
+        let err = CsvReader::new(input.as_bytes(), "input.csv", Precision::Reject)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "'input.csv' is missing the 'tx' column");
+    }
+}
@@ -0,0 +1,192 @@
+use std::io::BufRead;
+use std::rc::Rc;
+
+use csv::StringRecord;
+use serde_json::{Map, Value};
+
+use crate::commands::Result;
+use crate::ledger::{Precision, Rounding, Transaction};
+
+use super::{parse_record, read_error, Row, REQUIRED_COLUMNS};
+
+/// Reads rows from a transaction JSON Lines file, with one object per line
+/// holding the same fields as the csv columns
+pub struct JsonlReader<R> {
+    file: Rc<str>,
+    rdr: R,
+    headers: StringRecord,
+    precision: Precision,
+    /// The line number of the next line to be read
+    line: u64,
+    /// The byte offset of the next line to be read
+    byte: u64,
+    buf: Vec<u8>,
+    /// Whether reading failed, after which nothing more is read
+    done: bool,
+}
+
+impl<R: BufRead> JsonlReader<R> {
+    /// `file` is the name rows are reported against
+    pub fn new(rdr: R, file: &str, precision: Precision) -> Self {
+        JsonlReader {
+            file: file.into(),
+            rdr,
+            headers: StringRecord::from(REQUIRED_COLUMNS.to_vec()),
+            precision,
+            line: 1,
+            byte: 0,
+            buf: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Parse a single line into a transaction, along with its batch id and any
+    /// rounding applied to its amount
+    fn parse(
+        &self,
+        raw: &str,
+    ) -> (
+        Option<String>,
+        std::result::Result<Transaction, String>,
+        Option<Rounding>,
+    ) {
+        let object: Map<String, Value> = match serde_json::from_str(raw) {
+            Ok(object) => object,
+            Err(e) => return (None, Err(e.to_string()), None),
+        };
+        let batch = field(&object, "batch")
+            .ok()
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+
+        // The fields are lined up into a csv record so that they're
+        // deserialized in exactly the same way as a csv row would be
+        let record = REQUIRED_COLUMNS
+            .iter()
+            .map(|name| field(&object, name))
+            .collect::<std::result::Result<StringRecord, _>>();
+        let (transaction, rounding) = match record {
+            Ok(record) => parse_record(&record, &self.headers, self.precision),
+            Err(reason) => (Err(reason), None),
+        };
+        (batch, transaction, rounding)
+    }
+}
+
+/// The text of a field as it would appear in a csv column. Numbers keep the
+/// exact digits they were written with, and a missing or null field is empty
+fn field<'a>(object: &'a Map<String, Value>, name: &str) -> std::result::Result<&'a str, String> {
+    match object.get(name) {
+        None | Some(Value::Null) => Ok(""),
+        Some(Value::String(s)) => Ok(s.trim()),
+        Some(Value::Number(n)) => Ok(n.as_str()),
+        Some(_) => Err(format!("field '{}' must be a string or a number", name)),
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlReader<R> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        while !self.done {
+            self.buf.clear();
+            let n = match self.rdr.read_until(b'\n', &mut self.buf) {
+                Ok(0) => return None,
+                Ok(n) => n,
+                // the rest of the input can't be read, which isn't a problem
+                // with a single row but with the whole input
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(read_error(&self.file, e)));
+                }
+            };
+            let (line, byte) = (self.line, self.byte);
+            self.line += 1;
+            self.byte += n as u64;
+
+            let end = self.buf.len()
+                - self
+                    .buf
+                    .iter()
+                    .rev()
+                    .take_while(|b| matches!(b, b'\r' | b'\n'))
+                    .count();
+            let raw = self.buf[..end].to_vec();
+            // blank lines are skipped, like in csv inputs
+            if raw.trim_ascii().is_empty() {
+                continue;
+            }
+
+            let (batch, transaction, rounding) = match std::str::from_utf8(&raw) {
+                Ok(text) => self.parse(text),
+                // already starts with "invalid utf-8"
+                Err(e) => (None, Err(e.to_string()), None),
+            };
+            return Some(Ok(Row {
+                file: self.file.clone(),
+                line,
+                byte,
+                raw,
+                batch,
+                starts_batch: false,
+                transaction,
+                rounding,
+            }));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jsonl_rows() {
+        let input = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1.0}\r\n\
+                     \n\
+                     {\"type\": \"dispute\", \"client\": 1, \"tx\": \"1\"}\n\
+                     {\"type\": \"teleport\", \"client\": 1, \"tx\": 2, \"amount\": \"5\"}\n\
+                     {\"type\": \"deposit\", \"client\": 1, \"tx\": 3, \"amount\": 1.00001}\n\
+                     not json\n\
+                     {\"type\": \"deposit\", \"client\": [1], \"tx\": 4, \"batch\": \"a\"}";
+        let rows: Vec<_> = JsonlReader::new(input.as_bytes(), "input.jsonl", Precision::Reject)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 6);
+
+        assert_eq!((rows[0].line, rows[0].byte), (1, 0));
+        assert_eq!(
+            rows[0].raw_text(),
+            "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1.0}"
+        );
+        assert_eq!(rows[0].transaction.as_ref().unwrap().kind(), "deposit");
+
+        // missing amounts are fine for disputes, the same as empty csv fields
+        assert_eq!((rows[1].line, rows[1].byte), (3, 59));
+        assert_eq!(rows[1].transaction.as_ref().unwrap().kind(), "dispute");
+
+        assert_eq!(rows[2].transaction.as_ref().unwrap().kind(), "unknown");
+        assert!(rows[3].transaction.is_err());
+        assert!(rows[4].transaction.is_err());
+
+        assert_eq!(rows[5].batch.as_deref(), Some("a"));
+        assert_eq!(
+            rows[5].transaction.as_ref().unwrap_err(),
+            "field 'client' must be a string or a number"
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_kept_verbatim() {
+        let input = b"{\"type\": \"deposit\", \"client\": 1, \"tx\": \"\xff\"}\r\n";
+        let rows: Vec<_> = JsonlReader::new(&input[..], "input.jsonl", Precision::Reject)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows[0].raw, &input[..input.len() - 2]);
+        assert_eq!(
+            rows[0].transaction.as_ref().unwrap_err(),
+            "invalid utf-8 sequence of 1 bytes from index 40"
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::rc::Rc;
+
+use flate2::read::MultiGzDecoder;
+
+use csv::StringRecord;
+
+use crate::cli::InputFormat;
+use crate::commands::Result;
+use crate::ledger::{Precision, Rounding, Transaction, TransactionRecord};
+
+pub use binary_reader::BinaryRowReader;
+pub use csv_reader::CsvReader;
+pub use jsonl_reader::JsonlReader;
+
+mod binary_reader;
+mod csv_reader;
+mod jsonl_reader;
+
+/// The fields every transaction needs to have, as columns in a csv or keys in
+/// a JSON object
+const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// The input path that reads from stdin, and the name it is reported as
+const STDIN_PATH: &str = "-";
+const STDIN_NAME: &str = "<stdin>";
+
+/// A single row read from a transaction input
+#[derive(Debug)]
+pub struct Row {
+    /// The name of the input the row was read from
+    pub file: Rc<str>,
+    /// The line number the row starts on
+    pub line: u64,
+    /// The byte offset the row starts at
+    pub byte: u64,
+    /// The row exactly as it appears in the input, without the line terminator.
+    /// This isn't necessarily valid utf-8
+    pub raw: Vec<u8>,
+    /// The id of the batch the row belongs to, from the optional `batch` field
+    pub batch: Option<String>,
+    /// Whether the input marks the row as the first of its batch, even if the
+    /// row before it has the same batch id. Only binary inputs mark this, as
+    /// batches in text inputs are told apart by their ids alone
+    pub starts_batch: bool,
+    /// The parsed transaction, or why the row couldn't be parsed
+    pub transaction: std::result::Result<Transaction, String>,
+    /// The rounding applied to the transaction's amount, if it had more decimal
+    /// places than are kept
+    pub rounding: Option<Rounding>,
+}
+
+impl Row {
+    /// The row as text, for reports and messages. Anything that isn't valid
+    /// utf-8 is replaced
+    pub fn raw_text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.raw)
+    }
+
+    /// Whether the row carries on `batch`, the batch the row before it in the
+    /// same input belongs to. Rows outside of a batch never carry one on
+    pub fn continues_batch(&self, batch: Option<&str>) -> bool {
+        self.batch.is_some() && self.batch.as_deref() == batch && !self.starts_batch
+    }
+}
+
+/// Reads the rows of a single transaction input, in whichever format it is in
+pub enum TransactionReader {
+    Csv(CsvReader<Box<dyn Read>>),
+    Jsonl(JsonlReader<BufReader<Box<dyn Read>>>),
+    Binary(BinaryRowReader<BufReader<Box<dyn Read>>>),
+}
+
+impl TransactionReader {
+    /// Open the transaction input at `path`, or stdin if the path is `-`. A gzip
+    /// or zstd compressed input is decompressed as it is read. Unless `format`
+    /// is given, it is picked from the file extension, defaulting to csv.
+    /// Amounts with too many decimal places are handled with `precision`
+    pub fn from_path(
+        path: &Path,
+        format: Option<InputFormat>,
+        precision: Precision,
+    ) -> Result<Self> {
+        if path == Path::new(STDIN_PATH) {
+            let rdr = decompress(BufReader::new(io::stdin().lock()), None)
+                .map_err(|e| format!("failed to read '{}': {}", STDIN_NAME, e))?;
+            let format = format.unwrap_or(InputFormat::Csv);
+            return TransactionReader::new(rdr, STDIN_NAME, format, precision);
+        }
+        let name = path.display().to_string();
+        let rdr = File::open(path)
+            .and_then(|file| decompress(BufReader::new(file), Compression::from_path(path)))
+            .map_err(|e| format!("failed to open '{}': {}", name, e))?;
+        let format = format.unwrap_or_else(|| InputFormat::from_path(path));
+        TransactionReader::new(rdr, &name, format, precision)
+    }
+
+    /// Open every input up front, so a missing file or bad header is found
+    /// before any transactions are processed
+    pub fn from_paths(
+        paths: &[impl AsRef<Path>],
+        format: Option<InputFormat>,
+        precision: Precision,
+    ) -> Result<Vec<Self>> {
+        paths
+            .iter()
+            .map(|p| Self::from_path(p.as_ref(), format, precision))
+            .collect()
+    }
+
+    fn new(
+        rdr: Box<dyn Read>,
+        file: &str,
+        format: InputFormat,
+        precision: Precision,
+    ) -> Result<Self> {
+        Ok(match format {
+            InputFormat::Csv => TransactionReader::Csv(CsvReader::new(rdr, file, precision)?),
+            InputFormat::Jsonl => {
+                TransactionReader::Jsonl(JsonlReader::new(BufReader::new(rdr), file, precision))
+            }
+            InputFormat::Binary => {
+                TransactionReader::Binary(BinaryRowReader::new(BufReader::new(rdr), file)?)
+            }
+        })
+    }
+}
+
+impl Iterator for TransactionReader {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        match self {
+            TransactionReader::Csv(rdr) => rdr.next(),
+            TransactionReader::Jsonl(rdr) => rdr.next(),
+            TransactionReader::Binary(rdr) => rdr.next(),
+        }
+    }
+}
+
+/// The error for an input that can't be read any further, e.g. because it's
+/// truncated or doesn't decompress. Unlike a row that can't be parsed, this
+/// fails the whole command, as the rest of the input is lost
+fn read_error(file: &str, err: impl Display) -> Box<dyn std::error::Error> {
+    format!("failed to read '{}': {}", file, err).into()
+}
+
+/// Parse a single csv row with the columns type,client,tx,amount, in that
+/// order and without a header. The amount is left empty for transactions that
+/// don't take one, e.g. `dispute,1,1,`, the same as in a csv input
+pub fn parse_line(
+    line: &str,
+    precision: Precision,
+) -> (std::result::Result<Transaction, String>, Option<Rounding>) {
+    match read_line(line).and_then(|record| check_fields(&record, 0).map(|()| record)) {
+        Ok(record) => {
+            let headers = StringRecord::from(REQUIRED_COLUMNS.to_vec());
+            parse_record(&record, &headers, precision)
+        }
+        Err(reason) => (Err(reason), None),
+    }
+}
+
+/// Parse a single csv row like [`parse_line`], which can have a fifth column
+/// with the id of the batch the row belongs to
+pub fn parse_batch_line(
+    line: &str,
+    precision: Precision,
+) -> (
+    std::result::Result<Transaction, String>,
+    Option<Rounding>,
+    Option<String>,
+) {
+    let mut record = match read_line(line) {
+        Ok(record) => record,
+        Err(reason) => return (Err(reason), None, None),
+    };
+    let batch = record
+        .get(REQUIRED_COLUMNS.len())
+        .filter(|id| !id.is_empty())
+        .map(str::to_string);
+    // a row with the wrong number of fields still belongs to its batch, so
+    // that it rolls the batch back rather than ending it
+    if let Err(reason) = check_fields(&record, 1) {
+        return (Err(reason), None, batch);
+    }
+    record.truncate(REQUIRED_COLUMNS.len());
+    let headers = StringRecord::from(REQUIRED_COLUMNS.to_vec());
+    let (transaction, rounding) = parse_record(&record, &headers, precision);
+    (transaction, rounding, batch)
+}
+
+/// Group the rows of a single input into the batches they belong to, with
+/// each row outside of a batch as a group of its own. Batches never span more
+/// than one input, so every input is grouped separately
+pub fn batches(rows: impl Iterator<Item = Result<Row>>) -> impl Iterator<Item = Result<Vec<Row>>> {
+    let mut rows = rows.peekable();
+    std::iter::from_fn(move || {
+        let mut group = match rows.next()? {
+            Ok(row) => vec![row],
+            Err(e) => return Some(Err(e)),
+        };
+        while let Some(Ok(row)) = rows.next_if(
+            |next| matches!(next, Ok(next) if next.continues_batch(group[0].batch.as_deref())),
+        ) {
+            group.push(row);
+        }
+        Some(Ok(group))
+    })
+}
+
+/// Read a single csv row without a header
+fn read_line(line: &str) -> std::result::Result<StringRecord, String> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes());
+    let mut record = StringRecord::new();
+    match rdr.read_record(&mut record) {
+        Ok(true) => Ok(record),
+        Ok(false) => Err("empty row".to_string()),
+        Err(e) => Err(csv_reader::parse_reason(&e)),
+    }
+}
+
+/// Check that a row read with [`read_line`] has every required column, the
+/// same as a row in a csv input, along with up to `optional` more
+fn check_fields(record: &StringRecord, optional: usize) -> std::result::Result<(), String> {
+    let required = REQUIRED_COLUMNS.len();
+    let expected = if record.len() < required {
+        required
+    } else if record.len() > required + optional {
+        required + optional
+    } else {
+        return Ok(());
+    };
+    Err(format!(
+        "found {} fields, expected {}",
+        record.len(),
+        expected
+    ))
+}
+
+/// Deserialize a transaction from a csv record, which every text format is
+/// read through so that they all parse transactions the same way
+fn parse_record(
+    record: &StringRecord,
+    headers: &StringRecord,
+    precision: Precision,
+) -> (std::result::Result<Transaction, String>, Option<Rounding>) {
+    let parsed = record
+        .deserialize::<TransactionRecord>(Some(headers))
+        .map_err(|e| csv_reader::parse_reason(&e))
+        .and_then(|record| {
+            record
+                .into_transaction(precision)
+                .map_err(|e| e.to_string())
+        });
+    match parsed {
+        Ok((tx, rounding)) => (Ok(tx), rounding),
+        Err(reason) => (Err(reason), None),
+    }
+}
+
+impl InputFormat {
+    /// Pick the format from the extension of `path`, looking past the
+    /// extension of any compression, e.g. `transactions.jsonl.gz`
+    fn from_path(path: &Path) -> Self {
+        let path = match (Compression::from_path(path), path.file_stem()) {
+            (Some(_), Some(stem)) => Path::new(stem),
+            _ => path,
+        };
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl" | "ndjson") => InputFormat::Jsonl,
+            Some("txb") => InputFormat::Binary,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+/// The compression formats inputs can be read in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &'static [u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(Self::GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(Self::ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Wrap `rdr` in a decoder for its compression format, which is `compression`
+/// if known (e.g. from the file extension), otherwise sniffed from the first
+/// bytes of the input. Uncompressed inputs are passed through as they are
+fn decompress<R: BufRead + 'static>(
+    mut rdr: R,
+    compression: Option<Compression>,
+) -> io::Result<Box<dyn Read>> {
+    let compression = match compression {
+        Some(compression) => Some(compression),
+        None => Compression::from_magic(rdr.fill_buf()?),
+    };
+    Ok(match compression {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(rdr)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::with_buffer(rdr)?),
+        None => Box::new(rdr),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_from_magic() {
+        assert_eq!(
+            Compression::from_magic(&[0x1f, 0x8b, 0x08]),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_magic(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_magic(b"type,client,tx,amount"), None);
+        assert_eq!(Compression::from_magic(&[0x1f]), None);
+    }
+
+    #[test]
+    fn test_batches() {
+        let input = "type,client,tx,amount,batch\n\
+                     deposit,1,1,1,\n\
+                     deposit,1,2,1,\n\
+                     deposit,1,3,1,a\n\
+                     deposit,1,4,x,a\n\
+                     deposit,1,5,1,b\n\
+                     deposit,1,6,1,a\n";
+        let rdr = CsvReader::new(input.as_bytes(), "input.csv", Precision::Reject).unwrap();
+        let groups: Vec<Vec<u64>> = batches(rdr)
+            .map(|group| group.unwrap().iter().map(|row| row.line).collect())
+            .collect();
+        assert_eq!(groups, [vec![2], vec![3], vec![4, 5], vec![6], vec![7]]);
+    }
+
+    #[test]
+    fn test_input_format_from_path() {
+        for (path, format) in [
+            ("transactions.csv", InputFormat::Csv),
+            ("transactions.jsonl", InputFormat::Jsonl),
+            ("transactions.ndjson", InputFormat::Jsonl),
+            ("transactions.jsonl.gz", InputFormat::Jsonl),
+            ("transactions.csv.zst", InputFormat::Csv),
+            ("transactions.txb", InputFormat::Binary),
+            ("transactions", InputFormat::Csv),
+        ] {
+            assert_eq!(InputFormat::from_path(Path::new(path)), format, "{}", path);
+        }
+    }
+}
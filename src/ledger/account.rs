@@ -1,24 +1,23 @@
-use rust_decimal::Decimal;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-const CURRENCY_DECIMAL_SCALE: u32 = 4;
+use super::{Amount, ClientId};
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Account {
-    pub(crate) client: u16,
-    pub(crate) available: Decimal,
-    pub(crate) held: Decimal,
-    pub(crate) total: Decimal,
+    pub(crate) client: ClientId,
+    pub(crate) available: Amount,
+    pub(crate) held: Amount,
+    pub(crate) total: Amount,
     pub(crate) locked: bool,
 }
 
 impl Account {
-    pub fn new(client: u16) -> Self {
+    pub fn new(client: ClientId) -> Self {
         Self {
             client,
-            available: Decimal::new(0, CURRENCY_DECIMAL_SCALE),
-            held: Decimal::new(0, CURRENCY_DECIMAL_SCALE),
-            total: Decimal::new(0, CURRENCY_DECIMAL_SCALE),
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            total: Amount::ZERO,
             locked: false,
         }
     }
@@ -0,0 +1,307 @@
+//! Anti-money laundering checks, run over the ledger once processing is done
+//! to find patterns of activity worth reporting. Unlike the fraud rules and
+//! limits they never reject or change anything, they only report.
+//!
+//! The patterns to look for are declared in a JSON file, any of which can be
+//! left out:
+//!
+//! ```json
+//! {
+//!     "structuring": {"threshold": 10000, "margin": 1000, "deposits": 3, "within": 10},
+//!     "rapid_cycle": {"amount": 1000, "percent": 90, "within": 3},
+//!     "dormant_account": {"idle_for": 1000, "amount": 5000}
+//! }
+//! ```
+//!
+//! Transactions don't have timestamps, so "within" counts the client's own
+//! transactions, and how long an account has been idle counts the
+//! transactions the whole ledger processed in the meantime. Everything is
+//! worked out from each client's history, so only accepted transactions count.
+
+use std::fmt::{self, Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+use super::amount::config_amount;
+use super::{Amount, ClientId, Entry, Ledger, Sequence, State, Transaction, TxId};
+
+/// The patterns to look for, none by default
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Patterns {
+    structuring: Option<Structuring>,
+    rapid_cycle: Option<RapidCycle>,
+    dormant_account: Option<DormantAccount>,
+}
+
+/// At least `deposits` deposits no more than `margin` under `threshold`, among
+/// `within` of a client's transactions in a row
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Structuring {
+    #[serde(deserialize_with = "config_amount")]
+    threshold: Amount,
+    #[serde(deserialize_with = "config_amount")]
+    margin: Amount,
+    deposits: usize,
+    within: usize,
+}
+
+/// A deposit of at least `amount`, of which at least `percent` is withdrawn
+/// again within the client's next `within` transactions
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RapidCycle {
+    #[serde(deserialize_with = "config_amount")]
+    amount: Amount,
+    percent: f64,
+    within: usize,
+}
+
+/// A deposit or withdrawal of at least `amount` on an account that had no
+/// activity while the ledger processed `idle_for` transactions
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DormantAccount {
+    idle_for: u64,
+    #[serde(deserialize_with = "config_amount")]
+    amount: Amount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    Structuring,
+    RapidCycle,
+    DormantAccount,
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Structuring => write!(f, "structuring"),
+            Pattern::RapidCycle => write!(f, "rapid_cycle"),
+            Pattern::DormantAccount => write!(f, "dormant_account"),
+        }
+    }
+}
+
+/// A pattern found in a client's activity, along with the transactions that
+/// make it up
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Suspicion {
+    pub pattern: Pattern,
+    pub client: ClientId,
+    /// What was found, e.g. `3 deposits totalling 28500.0000, each under 10000.0000`
+    pub reason: String,
+    pub transactions: Vec<Involved>,
+}
+
+/// A transaction that's part of a suspicious pattern
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Involved {
+    pub sequence: Sequence,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub tx: TxId,
+    pub amount: Amount,
+    /// The state the transaction is in now, e.g. `ChargedBack` for a deposit
+    /// that was charged back since
+    pub state: Option<State>,
+}
+
+impl Patterns {
+    /// Check every pattern could actually match something
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(structuring) = &self.structuring {
+            if structuring.deposits == 0 || structuring.within < structuring.deposits {
+                return Err(
+                    "structuring needs at least one deposit, within at least as many transactions"
+                        .to_string(),
+                );
+            }
+        }
+        if let Some(cycle) = &self.rapid_cycle {
+            if cycle.within == 0 {
+                return Err("rapid_cycle needs to look at least one transaction ahead".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a pattern found: the reason, and the history entries of the
+/// transactions involved
+type Found<'a> = Vec<(String, Vec<&'a Entry>)>;
+
+impl Structuring {
+    fn find<'a>(&self, history: &'a [Entry]) -> Found<'a> {
+        let floor = self
+            .threshold
+            .checked_sub(self.margin)
+            .unwrap_or(Amount::ZERO);
+        // the deposits just under the threshold, with their position in the history
+        let near: Vec<_> = history
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| match &entry.transaction {
+                Some(deposit @ Transaction::Deposit(_)) => deposit
+                    .amount()
+                    .is_some_and(|a| a >= floor && a < self.threshold),
+                _ => false,
+            })
+            .collect();
+
+        let mut found = Vec::new();
+        let mut i = 0;
+        while i < near.len() {
+            // how many of them fall in the window starting at this one
+            let count = near[i..]
+                .iter()
+                .take_while(|(position, _)| position - near[i].0 < self.within)
+                .count();
+            if count < self.deposits {
+                i += 1;
+                continue;
+            }
+            let entries: Vec<_> = near[i..i + count].iter().map(|(_, e)| *e).collect();
+            let reason = format!(
+                "{} deposits totalling {}, each under {}",
+                count,
+                total(&entries),
+                self.threshold
+            );
+            found.push((reason, entries));
+            i += count;
+        }
+        found
+    }
+}
+
+impl RapidCycle {
+    fn find<'a>(&self, history: &'a [Entry]) -> Found<'a> {
+        let mut found = Vec::new();
+        let mut i = 0;
+        while i < history.len() {
+            let deposited = match &history[i].transaction {
+                Some(deposit @ Transaction::Deposit(_)) => deposit.amount(),
+                _ => None,
+            };
+            let Some(deposited) = deposited.filter(|a| *a >= self.amount) else {
+                i += 1;
+                continue;
+            };
+            let window = &history[i + 1..history.len().min(i + 1 + self.within)];
+            let withdrawals: Vec<_> = window
+                .iter()
+                .filter(|entry| matches!(entry.transaction, Some(Transaction::Withdrawal(_))))
+                .collect();
+            // the first withdrawal that takes the total withdrawn past the percentage
+            let cycled = (1..=withdrawals.len()).find(|n| {
+                total(&withdrawals[..*n]).units() as f64 * 100.0
+                    >= self.percent * deposited.units() as f64
+            });
+            let Some(n) = cycled else {
+                i += 1;
+                continue;
+            };
+            let reason = format!(
+                "deposit of {} followed by {} withdrawn within {} transactions",
+                deposited,
+                total(&withdrawals[..n]),
+                self.within
+            );
+            let last = withdrawals[n - 1].sequence;
+            let entries = std::iter::once(&history[i])
+                .chain(withdrawals[..n].iter().copied())
+                .collect();
+            found.push((reason, entries));
+            // carry on after the withdrawals, so they aren't reported twice
+            i = history.partition_point(|entry| entry.sequence <= last);
+        }
+        found
+    }
+}
+
+impl DormantAccount {
+    fn find<'a>(&self, history: &'a [Entry]) -> Found<'a> {
+        history
+            .windows(2)
+            .filter_map(|pair| {
+                let idle = pair[1].sequence.saturating_sub(pair[0].sequence + 1);
+                let moved = match &pair[1].transaction {
+                    Some(tx @ (Transaction::Deposit(_) | Transaction::Withdrawal(_))) => {
+                        tx.amount()
+                    }
+                    _ => None,
+                };
+                let moved = moved.filter(|a| *a >= self.amount && idle >= self.idle_for)?;
+                let reason = format!(
+                    "{} moved after {} transactions without activity",
+                    moved, idle
+                );
+                Some((reason, vec![&pair[1]]))
+            })
+            .collect()
+    }
+}
+
+impl Ledger {
+    /// Look for the patterns in every client's activity, in ascending order
+    /// of client id
+    pub fn analyze(&self, patterns: &Patterns) -> Vec<Suspicion> {
+        let mut clients: Vec<_> = self.history.keys().copied().collect();
+        clients.sort_unstable();
+
+        let mut suspicions = Vec::new();
+        for client in clients {
+            let history = self.history(client);
+            let found = [
+                (
+                    Pattern::Structuring,
+                    patterns.structuring.as_ref().map(|p| p.find(history)),
+                ),
+                (
+                    Pattern::RapidCycle,
+                    patterns.rapid_cycle.as_ref().map(|p| p.find(history)),
+                ),
+                (
+                    Pattern::DormantAccount,
+                    patterns.dormant_account.as_ref().map(|p| p.find(history)),
+                ),
+            ];
+            for (pattern, found) in found {
+                for (reason, entries) in found.into_iter().flatten() {
+                    suspicions.push(Suspicion {
+                        pattern,
+                        client,
+                        reason,
+                        transactions: entries.iter().filter_map(|e| self.involved(e)).collect(),
+                    });
+                }
+            }
+        }
+        suspicions
+    }
+
+    fn involved(&self, entry: &Entry) -> Option<Involved> {
+        let tx = entry.transaction.as_ref()?;
+        Some(Involved {
+            sequence: entry.sequence,
+            kind: tx.kind(),
+            tx: tx.tx_id().clone(),
+            amount: tx.amount()?,
+            state: self.transaction(tx.tx_id()).map(|logged| logged.state),
+        })
+    }
+}
+
+/// The total amount of the transactions making the entries
+fn total(entries: &[&Entry]) -> Amount {
+    let units: i128 = entries
+        .iter()
+        .filter_map(|entry| entry.transaction.as_ref()?.amount())
+        .map(|amount| amount.units() as i128)
+        .sum();
+    Amount::from_units(units.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+}
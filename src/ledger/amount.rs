@@ -0,0 +1,387 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The number of decimal places every currency amount is stored with
+pub const CURRENCY_DECIMAL_SCALE: u32 = 4;
+
+/// The number of raw units that make up a single whole unit of currency
+const UNITS_PER_WHOLE: i64 = 10_i64.pow(CURRENCY_DECIMAL_SCALE);
+
+/// A fixed-point currency amount, stored as a signed count of the smallest
+/// representable unit (`10^-CURRENCY_DECIMAL_SCALE`).
+///
+/// All arithmetic on an `Amount` is checked, callers decide what to do when a
+/// balance would overflow instead of the program panicking. `Decimal` is only
+/// used when converting to or from the outside world.
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Create an amount from a raw count of the smallest currency unit
+    /// e.g. `Amount::from_units(15)` is `0.0015`
+    pub const fn from_units(units: i64) -> Self {
+        Amount(units)
+    }
+
+    /// The raw count of the smallest currency unit making up this amount
+    pub const fn units(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    /// Parse an amount, using `precision` to decide what happens when it has
+    /// more than `CURRENCY_DECIMAL_SCALE` decimal places. Also returns the
+    /// rounding that was applied, if the amount had to be changed to fit
+    pub fn parse_with(
+        s: &str,
+        precision: Precision,
+    ) -> Result<(Amount, Option<Rounding>), AmountError> {
+        let original = Decimal::from_str(s).map_err(|_| AmountError::Invalid)?;
+        let strategy = match precision {
+            _ if original.scale() <= CURRENCY_DECIMAL_SCALE => {
+                return Ok((original.try_into()?, None))
+            }
+            Precision::Reject => {
+                return Err(AmountError::TooPrecise {
+                    scale: original.scale(),
+                })
+            }
+            Precision::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Precision::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Precision::Truncate => RoundingStrategy::ToZero,
+        };
+
+        let rounded = original.round_dp_with_strategy(CURRENCY_DECIMAL_SCALE, strategy);
+        let amount = Amount::try_from(rounded)?;
+        // extra decimal places that are all zeros don't need any rounding
+        let rounding = (rounded != original).then_some(Rounding {
+            original,
+            rounded: amount,
+            precision,
+        });
+        Ok((amount, rounding))
+    }
+}
+
+/// What to do with amounts that have more than `CURRENCY_DECIMAL_SCALE`
+/// decimal places
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    /// Reject the amount, along with its transaction
+    #[default]
+    Reject,
+    /// Round to the nearest amount, with ties going to the even digit
+    HalfEven,
+    /// Round to the nearest amount, with ties going away from zero
+    HalfUp,
+    /// Drop the extra decimal places, rounding towards zero
+    Truncate,
+}
+
+impl Display for Precision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Precision::Reject => "reject",
+            Precision::HalfEven => "half-even",
+            Precision::HalfUp => "half-up",
+            Precision::Truncate => "truncate",
+        })
+    }
+}
+
+/// A record of an amount that had too many decimal places and was rounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rounding {
+    /// The amount as it was written
+    pub original: Decimal,
+    /// The amount it was rounded to
+    pub rounded: Amount,
+    /// The policy used to round it
+    pub precision: Precision,
+}
+
+impl Display for Rounding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "amount {} was rounded to {} ({})",
+            self.original, self.rounded, self.precision
+        )
+    }
+}
+
+/// Errors for when a value cannot be represented as an `Amount`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AmountError {
+    /// The value was not a valid decimal number
+    Invalid,
+    /// The value had more than `CURRENCY_DECIMAL_SCALE` decimal places
+    TooPrecise { scale: u32 },
+    /// The value doesn't fit in an `Amount`
+    OutOfRange,
+}
+
+impl std::error::Error for AmountError {}
+
+impl Display for AmountError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmountError::Invalid => write!(f, "invalid amount"),
+            AmountError::TooPrecise { scale } => write!(
+                f,
+                "amount has {} decimal places, at most {} are allowed",
+                scale, CURRENCY_DECIMAL_SCALE
+            ),
+            AmountError::OutOfRange => write!(f, "amount is out of range"),
+        }
+    }
+}
+
+impl TryFrom<Decimal> for Amount {
+    type Error = AmountError;
+
+    fn try_from(value: Decimal) -> Result<Self, Self::Error> {
+        if value.scale() > CURRENCY_DECIMAL_SCALE {
+            return Err(AmountError::TooPrecise {
+                scale: value.scale(),
+            });
+        }
+
+        let factor = 10_i128.pow(CURRENCY_DECIMAL_SCALE - value.scale());
+        value
+            .mantissa()
+            .checked_mul(factor)
+            .and_then(|units| i64::try_from(units).ok())
+            .map(Amount)
+            .ok_or(AmountError::OutOfRange)
+    }
+}
+
+impl From<Amount> for Decimal {
+    fn from(amount: Amount) -> Self {
+        Decimal::new(amount.0, CURRENCY_DECIMAL_SCALE)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let decimal = Decimal::from_str(s).map_err(|_| AmountError::Invalid)?;
+        Amount::try_from(decimal)
+    }
+}
+
+// Amounts are always printed with exactly `CURRENCY_DECIMAL_SCALE` decimal
+// places, e.g. `1.5000`, `0.0015` or `6.0000`, however they were written in the
+// input
+impl Display for Amount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            units / UNITS_PER_WHOLE as u64,
+            units % UNITS_PER_WHOLE as u64,
+            width = CURRENCY_DECIMAL_SCALE as usize
+        )
+    }
+}
+
+// Debug output is used when logging failed transactions, so print the amount
+// the same way it was read in rather than the raw units
+impl Debug for Amount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AmountVisitor;
+
+        impl<'de> Visitor<'de> for AmountVisitor {
+            type Value = Amount;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                write!(
+                    f,
+                    "a decimal number with at most {} decimal places",
+                    CURRENCY_DECIMAL_SCALE
+                )
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                v.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Amount, E> {
+                v.checked_mul(UNITS_PER_WHOLE)
+                    .map(Amount)
+                    .ok_or_else(|| E::custom(AmountError::OutOfRange))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Amount, E> {
+                i64::try_from(v)
+                    .map_err(|_| E::custom(AmountError::OutOfRange))
+                    .and_then(|v| self.visit_i64(v))
+            }
+        }
+
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
+
+/// Read an amount in a configuration file, written as either a string or a
+/// number. Unlike the `Deserialize` impl this accepts JSON numbers even when
+/// they've been buffered, e.g. to find the tag of an enum, as they're read as a
+/// `Value` first. Works for `Option<Amount>` fields too
+pub(super) fn config_amount<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: From<Amount>,
+{
+    let amount: Result<Amount, AmountError> = match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::String(s) => s.parse(),
+        serde_json::Value::Number(n) => n.to_string().parse(),
+        other => {
+            return Err(de::Error::custom(format!(
+                "expected an amount, found {}",
+                other
+            )))
+        }
+    };
+    amount.map(T::from).map_err(de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("1".parse(), Ok(Amount::from_units(10000)));
+        assert_eq!("1.5".parse(), Ok(Amount::from_units(15000)));
+        assert_eq!("0.0001".parse(), Ok(Amount::from_units(1)));
+        assert_eq!("-2.25".parse(), Ok(Amount::from_units(-22500)));
+        assert_eq!(
+            "0.00051".parse::<Amount>(),
+            Err(AmountError::TooPrecise { scale: 5 })
+        );
+        assert_eq!("blah".parse::<Amount>(), Err(AmountError::Invalid));
+        assert_eq!(
+            "99999999999999999".parse::<Amount>(),
+            Err(AmountError::OutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_parse_with_precision() {
+        let parse =
+            |s, precision| Amount::parse_with(s, precision).map(|(a, r)| (a.units(), r.is_some()));
+
+        // amounts that fit are never rounded
+        assert_eq!(parse("1.5", Precision::Reject), Ok((15000, false)));
+        assert_eq!(parse("1.5", Precision::HalfEven), Ok((15000, false)));
+        // extra zeros are still too precise to accept, but don't change the
+        // value when rounded
+        assert_eq!(
+            parse("1.50000", Precision::Reject),
+            Err(AmountError::TooPrecise { scale: 5 })
+        );
+        assert_eq!(parse("1.50000", Precision::Truncate), Ok((15000, false)));
+
+        assert_eq!(
+            parse("0.00051", Precision::Reject),
+            Err(AmountError::TooPrecise { scale: 5 })
+        );
+        assert_eq!(parse("0.00051", Precision::HalfEven), Ok((5, true)));
+        assert_eq!(parse("0.00051", Precision::Truncate), Ok((5, true)));
+        assert_eq!(parse("0.00056", Precision::Truncate), Ok((5, true)));
+        assert_eq!(parse("0.00056", Precision::HalfUp), Ok((6, true)));
+
+        // ties
+        assert_eq!(parse("0.00045", Precision::HalfEven), Ok((4, true)));
+        assert_eq!(parse("0.00055", Precision::HalfEven), Ok((6, true)));
+        assert_eq!(parse("0.00045", Precision::HalfUp), Ok((5, true)));
+        assert_eq!(parse("-0.00045", Precision::HalfUp), Ok((-5, true)));
+        assert_eq!(parse("-0.00049", Precision::Truncate), Ok((-4, true)));
+
+        assert_eq!(
+            Amount::parse_with("0.00051", Precision::HalfEven)
+                .unwrap()
+                .1
+                .unwrap()
+                .to_string(),
+            "amount 0.00051 was rounded to 0.0005 (half-even)"
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Amount::ZERO.to_string(), "0.0000");
+        assert_eq!(Amount::from_units(60000).to_string(), "6.0000");
+        assert_eq!(Amount::from_units(5000).to_string(), "0.5000");
+        assert_eq!(Amount::from_units(19950).to_string(), "1.9950");
+        assert_eq!(Amount::from_units(15).to_string(), "0.0015");
+        assert_eq!(Amount::from_units(-5000).to_string(), "-0.5000");
+        assert_eq!(
+            Amount::from_units(i64::MIN).to_string(),
+            "-922337203685477.5808"
+        );
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let max = Amount::from_units(i64::MAX);
+        let min = Amount::from_units(i64::MIN);
+        let one = Amount::from_units(1);
+        assert_eq!(max.checked_add(one), None);
+        assert_eq!(min.checked_sub(one), None);
+        assert_eq!(one.checked_add(one), Some(Amount::from_units(2)));
+        assert_eq!(one.checked_sub(one), Some(Amount::ZERO));
+    }
+
+    #[test]
+    fn test_decimal_conversion() {
+        assert_eq!(Amount::try_from(dec!(1.25)), Ok(Amount::from_units(12500)));
+        assert_eq!(
+            Amount::try_from(dec!(1.25000)),
+            Err(AmountError::TooPrecise { scale: 5 })
+        );
+        assert_eq!(Decimal::from(Amount::from_units(12500)), dec!(1.2500));
+    }
+}
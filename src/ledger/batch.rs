@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use tracing::debug;
+
+use super::{Account, ClientId, Ledger, LoggedTransaction, Sequence, TxId};
+
+/// The original value of every account and logged transaction changed since a
+/// batch was opened, `None` for ones that didn't exist yet. Only the first
+/// change to each is recorded, since that's the value to go back to.
+#[derive(Debug)]
+pub(super) struct Journal {
+    /// The sequence number of the last transaction processed before the batch
+    sequence: Sequence,
+    accounts: HashMap<ClientId, Option<Account>>,
+    log: HashMap<TxId, Option<LoggedTransaction>>,
+}
+
+// Batches let a group of transactions be applied atomically: every change made
+// after `begin` is either kept with `commit` or undone with `rollback`. Batches
+// can be nested, each inner batch acting as a savepoint within the outer one.
+impl Ledger {
+    /// Open a new batch, nested inside the currently open batch if there is one
+    pub fn begin(&mut self) {
+        self.journals.push(Journal {
+            sequence: self.sequence,
+            accounts: HashMap::new(),
+            log: HashMap::new(),
+        });
+    }
+
+    /// Keep every change made in the innermost open batch. If it was nested,
+    /// the changes become part of the outer batch and can still be rolled back
+    /// with it.
+    ///
+    /// Panics if there is no open batch
+    pub fn commit(&mut self) {
+        let journal = self.journals.pop().expect("commit without an open batch");
+        if let Some(outer) = self.journals.last_mut() {
+            for (id, original) in journal.accounts {
+                outer.accounts.entry(id).or_insert(original);
+            }
+            for (id, original) in journal.log {
+                outer.log.entry(id).or_insert(original);
+            }
+        }
+    }
+
+    /// Keep every change made in the outermost open batch, so it can no longer
+    /// be rolled back. The batches nested inside it stay open, and rolling
+    /// them back still only undoes their own changes
+    ///
+    /// Panics if there is no open batch
+    pub fn commit_outermost(&mut self) {
+        assert!(!self.journals.is_empty(), "commit without an open batch");
+        self.journals.remove(0);
+    }
+
+    /// Undo every change made in the innermost open batch
+    ///
+    /// Panics if there is no open batch
+    pub fn rollback(&mut self) {
+        let journal = self.journals.pop().expect("rollback without an open batch");
+        debug!(
+            after = journal.sequence,
+            accounts = journal.accounts.len(),
+            transactions = journal.log.len(),
+            "rolling back batch"
+        );
+        for (id, original) in journal.accounts {
+            match original {
+                Some(account) => self.accounts.insert(id, account),
+                None => self.accounts.remove(&id),
+            };
+            self.truncate_history(id, journal.sequence);
+        }
+        for (id, original) in journal.log {
+            match original {
+                Some(lt) => self.log.insert(id, lt),
+                None => self.log.remove(&id),
+            };
+        }
+    }
+
+    /// Record the current value of an account before it's handed out to be
+    /// changed, if it hasn't been already for the open batch
+    pub(super) fn journal_account(&mut self, id: ClientId) {
+        if let Some(journal) = self.journals.last_mut() {
+            let accounts = &self.accounts;
+            journal
+                .accounts
+                .entry(id)
+                .or_insert_with(|| accounts.get(&id).copied());
+        }
+    }
+
+    /// Record the current value of a logged transaction before it's handed out
+    /// to be changed, if it hasn't been already for the open batch
+    pub(super) fn journal_transaction(&mut self, id: &TxId) {
+        if let Some(journal) = self.journals.last_mut() {
+            if !journal.log.contains_key(id) {
+                journal.log.insert(id.clone(), self.log.get(id).cloned());
+            }
+        }
+    }
+}
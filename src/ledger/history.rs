@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Account, ClientId, Ledger, State, Transaction};
+
+/// The position of a transaction in the order the ledger processed them,
+/// starting at 1. Every transaction handed to the ledger gets the next
+/// sequence number, including ones that are declined or later rolled back, so
+/// sequence numbers are never reused
+pub type Sequence = u64;
+
+/// A change made to a client's account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub(crate) sequence: Sequence,
+    /// The transaction that made the change, or `None` for the value of an
+    /// account restored from a snapshot that was saved without its history
+    pub(crate) transaction: Option<Transaction>,
+    /// The state the transaction's logged transaction was left in, e.g.
+    /// `Disputed` for the deposit a dispute refers to
+    pub(crate) state: Option<State>,
+    /// The value of the account after the change
+    pub(crate) account: Account,
+}
+
+// Every time a transaction changes an account, an entry is added to that
+// client's history. This indexes everything a client did without scanning the
+// whole log, and as each client's history is in ascending order of sequence,
+// the value of an account at any point can be found with a binary search
+// instead of reprocessing every transaction up to that point.
+impl Ledger {
+    /// The sequence number of the last transaction processed
+    pub fn sequence(&self) -> Sequence {
+        self.sequence
+    }
+
+    /// Every change made to a client's account, oldest first
+    pub fn history(&self, client: ClientId) -> &[Entry] {
+        self.history.get(&client).map_or(&[], Vec::as_slice)
+    }
+
+    /// The value of a client's account once the transaction with sequence
+    /// number `sequence` had been processed, or `None` if the account didn't
+    /// exist yet
+    pub fn balance_at(&self, client: ClientId, sequence: Sequence) -> Option<&Account> {
+        let history = self.history(client);
+        let end = history.partition_point(|entry| entry.sequence <= sequence);
+        history[..end].last().map(|entry| &entry.account)
+    }
+
+    /// Every account as it was once the transaction with sequence number
+    /// `sequence` had been processed, in ascending order of client id
+    pub fn accounts_at(&self, sequence: Sequence) -> impl Iterator<Item = &Account> {
+        let mut accounts: Vec<_> = self
+            .history
+            .keys()
+            .filter_map(|client| self.balance_at(*client, sequence))
+            .collect();
+        accounts.sort_unstable_by_key(|account| account.client);
+        accounts.into_iter()
+    }
+
+    /// Give the next transaction its sequence number
+    pub(super) fn next_sequence(&mut self) {
+        self.sequence += 1;
+    }
+
+    /// Add the change the last transaction processed made to its client's
+    /// account to the client's history
+    pub(super) fn record_history(&mut self, tx: &Transaction) {
+        if !self.keep_history {
+            return;
+        }
+        // every kind of transaction only ever changes its own client's account,
+        // so that's the only history it needs to be recorded in
+        let client = tx.client_id();
+        if let Some(account) = self.accounts.get(&client) {
+            // refer to the id the log already keeps rather than a copy of it
+            let mut tx = tx.clone();
+            if let Some(id) = self.intern(tx.tx_id()) {
+                *tx.tx_id_mut() = id;
+            }
+            let entry = Entry {
+                sequence: self.sequence,
+                state: self.log.get(tx.tx_id()).map(|lt| lt.state),
+                transaction: Some(tx),
+                account: *account,
+            };
+            self.history.entry(client).or_default().push(entry);
+        }
+    }
+
+    /// Forget a client's history after `sequence`, when the changes made since
+    /// then are rolled back
+    pub(super) fn truncate_history(&mut self, client: ClientId, sequence: Sequence) {
+        if let Some(history) = self.history.get_mut(&client) {
+            let end = history.partition_point(|entry| entry.sequence <= sequence);
+            history.truncate(end);
+            if history.is_empty() {
+                self.history.remove(&client);
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// The width of each numeric id is picked with cargo features. Features are
+// additive, so when more than one width is enabled the widest one wins.
+
+/// The integer type backing a `ClientId`, `u16` unless widened by the
+/// `client-id-u32` or `client-id-u64` features
+#[cfg(not(any(feature = "client-id-u32", feature = "client-id-u64")))]
+pub type ClientIdRepr = u16;
+#[cfg(all(feature = "client-id-u32", not(feature = "client-id-u64")))]
+pub type ClientIdRepr = u32;
+#[cfg(feature = "client-id-u64")]
+pub type ClientIdRepr = u64;
+
+/// The integer type backing a numeric `TxId`, `u32` unless widened by the `tx-id-u64` feature
+#[cfg(not(feature = "tx-id-u64"))]
+pub type TxIdRepr = u32;
+#[cfg(feature = "tx-id-u64")]
+pub type TxIdRepr = u64;
+
+// Client and transaction ids are distinct types, so passing a tx id where a
+// client id is expected (or the other way around) is a compile error rather
+// than a silent lookup miss
+
+/// Identifies a client account in the ledger
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ClientId(ClientIdRepr);
+
+impl From<ClientIdRepr> for ClientId {
+    fn from(id: ClientIdRepr) -> Self {
+        ClientId(id)
+    }
+}
+
+impl From<ClientId> for ClientIdRepr {
+    fn from(id: ClientId) -> Self {
+        id.0
+    }
+}
+
+impl Display for ClientId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+// Print just the number, failed transactions are logged using Debug
+impl Debug for ClientId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+/// Identifies a transaction in the ledger log.
+///
+/// Upstream processors hand out anything from plain integers to UUIDs, so any
+/// string is a valid transaction id. Ids that are a plain integer (without a
+/// sign or leading zeros) are stored as that integer, everything else as a
+/// shared string that is freed once nothing refers to it. The ledger interns
+/// the ids it logs, so each one it keeps is only stored once no matter how
+/// many times it is referenced.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TxId(TxIdKind);
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum TxIdKind {
+    Numeric(TxIdRepr),
+    String(Arc<str>),
+}
+
+impl TxId {
+    /// The id for a transaction reference. The same string always produces an
+    /// equal id
+    pub fn new(id: &str) -> Self {
+        match id.parse::<TxIdRepr>() {
+            Ok(n) if n.to_string() == id => TxId(TxIdKind::Numeric(n)),
+            _ => TxId(TxIdKind::String(Arc::from(id))),
+        }
+    }
+
+    /// The integer value of the id, if it is numeric
+    pub fn as_numeric(&self) -> Option<TxIdRepr> {
+        match self.0 {
+            TxIdKind::Numeric(n) => Some(n),
+            TxIdKind::String(_) => None,
+        }
+    }
+
+    /// Whether two ids share the same string, rather than just being equal
+    #[cfg(test)]
+    pub(crate) fn shares(&self, other: &TxId) -> bool {
+        match (&self.0, &other.0) {
+            (TxIdKind::String(a), TxIdKind::String(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl From<TxIdRepr> for TxId {
+    fn from(id: TxIdRepr) -> Self {
+        TxId(TxIdKind::Numeric(id))
+    }
+}
+
+impl From<&str> for TxId {
+    fn from(id: &str) -> Self {
+        TxId::new(id)
+    }
+}
+
+impl Display for TxId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            TxIdKind::Numeric(n) => Display::fmt(n, f),
+            TxIdKind::String(s) => f.write_str(s),
+        }
+    }
+}
+
+impl Debug for TxId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+// Always serialized as a string, so the id reads back in exactly the same way
+// whichever format it was written to
+impl Serialize for TxId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TxId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TxIdVisitor;
+
+        impl<'de> Visitor<'de> for TxIdVisitor {
+            type Value = TxId;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                write!(f, "a transaction id")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<TxId, E> {
+                if v.is_empty() {
+                    return Err(E::invalid_length(0, &self));
+                }
+                Ok(TxId::new(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<TxId, E> {
+                Ok(TxId::new(&v.to_string()))
+            }
+        }
+
+        deserializer.deserialize_str(TxIdVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numeric_tx_id() {
+        assert_eq!(TxId::new("42"), TxId::from(42));
+        assert_eq!(TxId::new("42").to_string(), "42");
+        // not the canonical form of a number, so these are kept as strings
+        assert_ne!(TxId::new("042"), TxId::from(42));
+        assert_eq!(TxId::new("042").to_string(), "042");
+        assert_ne!(TxId::new("+42"), TxId::from(42));
+    }
+
+    #[test]
+    fn test_string_tx_id() {
+        let uuid = "4f1c8a2e-9b0d-4d6a-8e57-3c2b1a0f9e8d";
+        assert_eq!(TxId::new(uuid), TxId::new(uuid));
+        assert_eq!(TxId::new(uuid).to_string(), uuid);
+        assert_ne!(TxId::new("ref-a"), TxId::new("ref-b"));
+        // ids are compared exactly, including case
+        assert_ne!(TxId::new("ref-a"), TxId::new("REF-A"));
+    }
+}
@@ -0,0 +1,218 @@
+//! Limits on how much can be deposited or withdrawn, checked before every
+//! deposit and withdrawal the ledger processes.
+//!
+//! Limits are declared in a JSON file, with the `default` limits every client
+//! gets and named `tiers` that override some of them:
+//!
+//! ```json
+//! {
+//!     "default": {"deposit": 10000, "withdrawal": 5000, "withdrawal_count": 3, "within": 10},
+//!     "tiers": {"business": {"withdrawal": 50000, "withdrawal_count": 20}}
+//! }
+//! ```
+//!
+//! The rolling withdrawal limits cover a client's last `within` transactions,
+//! as transactions don't have timestamps to limit them per day. Like the fraud
+//! rules, they're worked out from each client's history, so only transactions
+//! that were accepted (and not rolled back) count.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use serde::Deserialize;
+
+use super::amount::config_amount;
+use super::{Amount, ClientId, Error, Ledger, Transaction};
+
+/// The limits a ledger enforces, none by default
+#[derive(Debug, Default, Deserialize)]
+pub struct Limits {
+    #[serde(default)]
+    default: LimitSet,
+    #[serde(default)]
+    tiers: HashMap<String, LimitSet>,
+    /// The limits for each client assigned to a tier, with the tier's
+    /// overrides applied to the defaults
+    #[serde(skip)]
+    clients: HashMap<ClientId, LimitSet>,
+}
+
+/// Every limit that can be set, any of which can be left out
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LimitSet {
+    /// The largest single deposit
+    #[serde(default, deserialize_with = "config_amount")]
+    deposit: Option<Amount>,
+    /// The largest single withdrawal
+    #[serde(default, deserialize_with = "config_amount")]
+    withdrawal: Option<Amount>,
+    /// The most withdrawals among a client's last `within` transactions
+    withdrawal_count: Option<usize>,
+    /// The most that can be withdrawn over a client's last `within`
+    /// transactions
+    #[serde(default, deserialize_with = "config_amount")]
+    withdrawal_volume: Option<Amount>,
+    /// How many of a client's transactions the rolling limits cover
+    within: Option<usize>,
+}
+
+/// A limit a transaction went over, along with what it allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Deposit(Amount),
+    Withdrawal(Amount),
+    WithdrawalCount { count: usize, within: usize },
+    WithdrawalVolume { volume: Amount, within: usize },
+}
+
+impl Limit {
+    /// A stable name for the limit, the same as its key in the limits file
+    pub fn name(&self) -> &'static str {
+        match self {
+            Limit::Deposit(_) => "deposit",
+            Limit::Withdrawal(_) => "withdrawal",
+            Limit::WithdrawalCount { .. } => "withdrawal_count",
+            Limit::WithdrawalVolume { .. } => "withdrawal_volume",
+        }
+    }
+
+    /// What the limit allows, an amount or a number of withdrawals
+    pub fn allowed(&self) -> String {
+        match self {
+            Limit::Deposit(max) | Limit::Withdrawal(max) => max.to_string(),
+            Limit::WithdrawalCount { count, .. } => count.to_string(),
+            Limit::WithdrawalVolume { volume, .. } => volume.to_string(),
+        }
+    }
+
+    /// How many of the client's transactions a rolling limit covers
+    pub fn within(&self) -> Option<usize> {
+        match self {
+            Limit::Deposit(_) | Limit::Withdrawal(_) => None,
+            Limit::WithdrawalCount { within, .. } | Limit::WithdrawalVolume { within, .. } => {
+                Some(*within)
+            }
+        }
+    }
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Deposit(max) => write!(f, "deposits of at most '{}'", max),
+            Limit::Withdrawal(max) => write!(f, "withdrawals of at most '{}'", max),
+            Limit::WithdrawalCount { count, within } => write!(
+                f,
+                "at most {} withdrawals in {} transactions",
+                count, within
+            ),
+            Limit::WithdrawalVolume { volume, within } => write!(
+                f,
+                "withdrawing at most '{}' in {} transactions",
+                volume, within
+            ),
+        }
+    }
+}
+
+impl LimitSet {
+    /// These limits, with any set in `overrides` taking their place
+    fn with(self, overrides: LimitSet) -> LimitSet {
+        LimitSet {
+            deposit: overrides.deposit.or(self.deposit),
+            withdrawal: overrides.withdrawal.or(self.withdrawal),
+            withdrawal_count: overrides.withdrawal_count.or(self.withdrawal_count),
+            withdrawal_volume: overrides.withdrawal_volume.or(self.withdrawal_volume),
+            within: overrides.within.or(self.within),
+        }
+    }
+}
+
+impl Limits {
+    /// Put a client in one of the tiers, so it gets that tier's limits
+    pub fn assign(&mut self, client: ClientId, tier: &str) -> Result<(), String> {
+        let overrides = self
+            .tiers
+            .get(tier)
+            .ok_or_else(|| format!("client {} is assigned to unknown tier '{}'", client, tier))?;
+        self.clients.insert(client, self.default.with(*overrides));
+        Ok(())
+    }
+
+    /// Check that every rolling limit says how many transactions it covers
+    pub fn validate(&self) -> Result<(), String> {
+        let mut tiers: Vec<_> = self.tiers.iter().collect();
+        tiers.sort_unstable_by_key(|(name, _)| *name);
+        for (name, limits) in [("default", &self.default)].into_iter().chain(
+            tiers
+                .into_iter()
+                .map(|(name, limits)| (name.as_str(), limits)),
+        ) {
+            let limits = self.default.with(*limits);
+            let rolling = limits.withdrawal_count.is_some() || limits.withdrawal_volume.is_some();
+            if rolling && limits.within.is_none() {
+                return Err(format!(
+                    "the {} limits need `within` for their rolling withdrawal limits",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn for_client(&self, client: ClientId) -> LimitSet {
+        self.clients.get(&client).copied().unwrap_or(self.default)
+    }
+}
+
+impl Ledger {
+    /// Enforce these limits on every transaction processed from now on
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Reject a deposit or withdrawal that would go over one of its client's
+    /// limits
+    pub(super) fn check_limits(&self, tx: &Transaction) -> Result<(), Error> {
+        let limits = self.limits.for_client(tx.client_id());
+        let exceeded = |limit, attempted| Err(Error::LimitExceeded { limit, attempted });
+        let amount = match tx {
+            Transaction::Deposit(_) | Transaction::Withdrawal(_) => tx.amount().unwrap_or_default(),
+            _ => return Ok(()),
+        };
+
+        if let Transaction::Deposit(_) = tx {
+            return match limits.deposit {
+                Some(max) if amount > max => exceeded(Limit::Deposit(max), amount),
+                _ => Ok(()),
+            };
+        }
+        if let Some(max) = limits.withdrawal.filter(|max| amount > *max) {
+            return exceeded(Limit::Withdrawal(max), amount);
+        }
+
+        let within = limits.within.unwrap_or_default();
+        let history = self.history(tx.client_id());
+        let mut withdrawals = history[history.len().saturating_sub(within)..]
+            .iter()
+            .filter_map(|entry| match &entry.transaction {
+                Some(withdrawal @ Transaction::Withdrawal(_)) => withdrawal.amount(),
+                _ => None,
+            });
+        if let Some(count) = limits.withdrawal_count {
+            if withdrawals.clone().count() >= count {
+                return exceeded(Limit::WithdrawalCount { count, within }, amount);
+            }
+        }
+        if let Some(volume) = limits.withdrawal_volume {
+            let withdrawn =
+                withdrawals.try_fold(amount, |total, withdrawal| total.checked_add(withdrawal));
+            // a total too big to add up is over any volume
+            if !matches!(withdrawn, Some(withdrawn) if withdrawn <= volume) {
+                return exceeded(Limit::WithdrawalVolume { volume, within }, amount);
+            }
+        }
+        Ok(())
+    }
+}
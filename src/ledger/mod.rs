@@ -1,18 +1,56 @@
 use std::collections::HashMap;
 
 pub use account::Account;
-pub use transaction::{LoggedTransaction, State, Transaction};
+pub use aml::{Patterns, Suspicion};
+pub use amount::{Amount, AmountError, Precision, Rounding, CURRENCY_DECIMAL_SCALE};
+pub use history::{Entry, Sequence};
+pub use id::{ClientId, ClientIdRepr, TxId, TxIdRepr};
+pub use limits::{Limit, Limits};
+pub use rules::{Alert, Rules};
+pub use snapshot::Snapshot;
+pub use transaction::{Error, LoggedTransaction, State, Transaction, TransactionRecord};
+
+use batch::Journal;
 
 mod account;
+mod aml;
+mod amount;
+mod batch;
+mod history;
+mod id;
+mod limits;
+mod rules;
+mod snapshot;
+mod statement;
 mod transaction;
 
 /// A ledger represents a store of financial accuonts along with the transactions for each account
 #[derive(Debug)]
 pub struct Ledger {
-    accounts: HashMap<u16, Account>,
-    log: HashMap<u32, LoggedTransaction>,
+    accounts: HashMap<ClientId, Account>,
+    log: HashMap<TxId, LoggedTransaction>,
+    /// Undo information for each open batch, innermost last
+    journals: Vec<Journal>,
+    /// The sequence number of the last transaction processed
+    sequence: Sequence,
+    /// Every change made to each client's account, in ascending order of sequence
+    history: HashMap<ClientId, Vec<Entry>>,
+    /// Whether accepted transactions are added to `history`
+    keep_history: bool,
+    /// The limits checked before every deposit and withdrawal
+    limits: Limits,
+    /// The fraud rules checked alongside every transaction
+    rules: Rules,
+    /// Alerts raised by the rules that haven't been taken yet
+    alerts: Vec<Alert>,
+    /// The most times a transaction can be disputed
+    max_disputes: usize,
 }
 
+/// How many times a transaction can be disputed unless the ledger is told
+/// otherwise, so resolving a dispute is final
+pub const DEFAULT_MAX_DISPUTES: usize = 1;
+
 pub trait Process {
     type Output;
     fn process(self, ledger: &mut Ledger) -> Self::Output;
@@ -24,6 +62,14 @@ impl Ledger {
         Ledger {
             accounts: HashMap::new(),
             log: HashMap::new(),
+            journals: Vec::new(),
+            sequence: 0,
+            history: HashMap::new(),
+            keep_history: true,
+            limits: Limits::default(),
+            rules: Rules::default(),
+            alerts: Vec::new(),
+            max_disputes: DEFAULT_MAX_DISPUTES,
         }
     }
 
@@ -32,29 +78,73 @@ impl Ledger {
     /// struct fields
     pub fn find_account_and_transaction(
         &mut self,
-        client_id: u16,
-        tx_id: u32,
+        client_id: ClientId,
+        tx_id: &TxId,
     ) -> (Option<&mut Account>, Option<&mut LoggedTransaction>) {
-        (self.accounts.get_mut(&client_id), self.log.get_mut(&tx_id))
+        self.journal_account(client_id);
+        self.journal_transaction(tx_id);
+        (self.accounts.get_mut(&client_id), self.log.get_mut(tx_id))
     }
 
     /// Find an account in the ledger, returning a mutable reference if an account is found, otherwise `None`
-    pub fn find_account(&mut self, id: u16) -> Option<&mut Account> {
+    pub fn find_account(&mut self, id: ClientId) -> Option<&mut Account> {
+        self.journal_account(id);
         self.accounts.get_mut(&id)
     }
 
     /// Find an account or create a new one if it doesn't exist
-    pub fn find_or_create_account(&mut self, id: u16) -> &mut Account {
+    pub fn find_or_create_account(&mut self, id: ClientId) -> &mut Account {
+        self.journal_account(id);
         self.accounts.entry(id).or_insert_with(|| Account::new(id))
     }
 
     /// Log a transaction in the ledger as had being completed
-    pub fn log_transaction(&mut self, id: u32, tx: Transaction) {
-        self.log.insert(id, LoggedTransaction::new(tx));
+    pub fn log_transaction(&mut self, id: TxId, tx: Transaction) {
+        self.journal_transaction(&id);
+        self.log
+            .insert(id, LoggedTransaction::new(tx, self.sequence));
+    }
+
+    /// Look up an account without changing it
+    pub fn account(&self, id: ClientId) -> Option<&Account> {
+        self.accounts.get(&id)
     }
 
+    /// Look up a logged transaction without changing it
+    pub fn transaction(&self, id: &TxId) -> Option<&LoggedTransaction> {
+        self.log.get(id)
+    }
+
+    /// The id of a logged transaction equal to `id`, if there is one, so that
+    /// each distinct id the ledger keeps is only stored once however many
+    /// transactions refer to it
+    fn intern(&self, id: &TxId) -> Option<TxId> {
+        self.log.get_key_value(id).map(|(id, _)| id.clone())
+    }
+
+    /// Every account in the ledger, in ascending order of client id
     pub fn accounts(&self) -> impl Iterator<Item = &Account> {
-        self.accounts.values()
+        let mut accounts: Vec<_> = self.accounts.values().collect();
+        accounts.sort_unstable_by_key(|account| account.client);
+        accounts.into_iter()
+    }
+
+    /// Allow each transaction to be disputed `max` times rather than once,
+    /// after which resolving its last dispute is final
+    pub fn set_max_disputes(&mut self, max: usize) {
+        self.max_disputes = max;
+    }
+
+    /// Stop adding accepted transactions to each client's history, and forget
+    /// what has been recorded so far, or start recording them again. History
+    /// keeps a copy of the account for every transaction, so it's worth
+    /// turning off when nothing needs statements, point-in-time balances,
+    /// limits, rules or AML checks
+    pub fn set_keep_history(&mut self, keep: bool) {
+        self.keep_history = keep;
+        if !keep {
+            self.history = HashMap::new();
+        }
     }
 
     pub fn process<P: Process>(&mut self, p: P) -> P::Output {
@@ -0,0 +1,298 @@
+//! Fraud rules, checked alongside every transaction the ledger processes.
+//!
+//! Rules are declared in a JSON file, each with a `name`, a `type` and the
+//! settings for that type, and optionally the `action` to take:
+//!
+//! ```json
+//! {"rules": [
+//!     {"name": "repeat-disputes", "type": "repeated_disputes", "disputes": 3, "within": 10},
+//!     {"name": "dispute-ratio", "type": "dispute_ratio", "percent": 50, "min_deposits": 4},
+//!     {"name": "fresh-deposit", "type": "unsettled_deposit", "amount": "1000", "settles_after": 3},
+//!     {"name": "overdrawn", "type": "negative_balance"}
+//! ]}
+//! ```
+//!
+//! Rules only look at a client's history, so a transaction rolled back with
+//! its batch stops counting towards them, and any lock a rule applied is
+//! rolled back along with it. The alert a rule raised is still kept, since it
+//! did trigger at the time.
+
+use std::fmt::{self, Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::amount::config_amount;
+use super::{Amount, ClientId, Entry, Error, Ledger, Sequence, Transaction, TxId};
+
+/// The fraud rules a ledger enforces, none by default
+#[derive(Debug, Default, Deserialize)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Rule {
+    name: String,
+    #[serde(flatten)]
+    check: Check,
+    /// What to do when the rule triggers, see `Check::default_action`
+    action: Option<Action>,
+}
+
+/// What a rule looks for
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Check {
+    /// At least `disputes` disputes among a client's last `within`
+    /// transactions
+    RepeatedDisputes { disputes: usize, within: usize },
+    /// More than `percent` disputes per deposit, once a client has made at
+    /// least `min_deposits` deposits
+    DisputeRatio {
+        percent: f64,
+        #[serde(default)]
+        min_deposits: usize,
+    },
+    /// A withdrawal within `settles_after` transactions of a deposit of at
+    /// least `amount`, which hasn't settled yet
+    UnsettledDeposit {
+        #[serde(deserialize_with = "config_amount")]
+        amount: Amount,
+        settles_after: usize,
+    },
+    /// An account whose available or total balance has gone below zero
+    NegativeBalance,
+}
+
+/// What happens when a rule triggers. Every action raises an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Only raise the alert
+    Flag,
+    /// Lock the account, once the transaction has gone through
+    Lock,
+    /// Reject the transaction
+    Block,
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Flag => write!(f, "flag"),
+            Action::Lock => write!(f, "lock"),
+            Action::Block => write!(f, "block"),
+        }
+    }
+}
+
+/// An auditable record of a rule triggering
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    /// The sequence number of the transaction that triggered the rule
+    pub sequence: Sequence,
+    pub rule: String,
+    pub action: Action,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub client: ClientId,
+    pub tx: TxId,
+    /// What the rule found, e.g. `3 disputes in the last 10 transactions`
+    pub reason: String,
+}
+
+impl Check {
+    fn default_action(&self) -> Action {
+        match self {
+            Check::RepeatedDisputes { .. } | Check::NegativeBalance => Action::Lock,
+            Check::DisputeRatio { .. } => Action::Flag,
+            Check::UnsettledDeposit { .. } => Action::Block,
+        }
+    }
+}
+
+impl Rule {
+    fn action(&self) -> Action {
+        self.action.unwrap_or_else(|| self.check.default_action())
+    }
+}
+
+impl Rules {
+    /// Check the rules make sense together, e.g. that only rules checked
+    /// before a transaction is applied can block it
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if self.rules[..i].iter().any(|other| other.name == rule.name) {
+                return Err(format!("rule '{}' is declared twice", rule.name));
+            }
+            match (&rule.check, rule.action()) {
+                (Check::UnsettledDeposit { .. }, Action::Lock) => {
+                    return Err(format!(
+                        "rule '{}' can only flag or block withdrawals",
+                        rule.name
+                    ))
+                }
+                (Check::UnsettledDeposit { .. }, _) => {}
+                (_, Action::Block) => {
+                    return Err(format!(
+                        "rule '{}' runs after the transaction, so it can't block it",
+                        rule.name
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Ledger {
+    /// Enforce these rules on every transaction processed from now on
+    pub fn set_rules(&mut self, rules: Rules) {
+        self.rules = rules;
+    }
+
+    /// Take the alerts raised since they were last taken
+    pub fn take_alerts(&mut self) -> Vec<Alert> {
+        std::mem::take(&mut self.alerts)
+    }
+
+    /// Check the rules that apply before a transaction, rejecting it if one
+    /// of them blocks it
+    pub(super) fn screen(&mut self, tx: &Transaction) -> Result<(), Error> {
+        let Transaction::Withdrawal(_) = tx else {
+            return Ok(());
+        };
+        let history = self
+            .history
+            .get(&tx.client_id())
+            .map_or(&[][..], Vec::as_slice);
+        for rule in &self.rules.rules {
+            let Check::UnsettledDeposit {
+                amount,
+                settles_after,
+            } = rule.check
+            else {
+                continue;
+            };
+            let unsettled = history
+                .iter()
+                .rev()
+                .take(settles_after)
+                .filter_map(|entry| entry.transaction.as_ref())
+                .find(|deposit| {
+                    matches!(deposit, Transaction::Deposit(_))
+                        && deposit.amount().is_some_and(|a| a >= amount)
+                });
+            if let Some(deposit) = unsettled {
+                let reason = format!(
+                    "deposit {} of {} hasn't settled yet",
+                    deposit.tx_id(),
+                    deposit.amount().unwrap_or_default()
+                );
+                self.alerts
+                    .push(alert(self.sequence, rule, rule.action(), tx, reason));
+                if rule.action() == Action::Block {
+                    return Err(Error::Blocked {
+                        rule: rule.name.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the rules that apply once a transaction has gone through,
+    /// locking the account if one of them says to
+    pub(super) fn enforce_rules(&mut self, tx: &Transaction) {
+        let client = tx.client_id();
+        let (Some(account), Some(history)) =
+            (self.accounts.get(&client), self.history.get(&client))
+        else {
+            return;
+        };
+        let is_dispute = matches!(tx, Transaction::Dispute(_));
+
+        let mut lock = false;
+        for rule in &self.rules.rules {
+            let reason = match rule.check {
+                Check::RepeatedDisputes { disputes, within } if is_dispute => {
+                    let recent = &history[history.len().saturating_sub(within)..];
+                    let found = count(recent, |tx| matches!(tx, Transaction::Dispute(_)));
+                    (found >= disputes)
+                        .then(|| format!("{} disputes in the last {} transactions", found, within))
+                }
+                Check::DisputeRatio {
+                    percent,
+                    min_deposits,
+                } if is_dispute => {
+                    let deposits = count(history, |tx| matches!(tx, Transaction::Deposit(_)));
+                    let disputes = count(history, |tx| matches!(tx, Transaction::Dispute(_)));
+                    let exceeded = deposits > 0
+                        && deposits >= min_deposits
+                        && disputes as f64 * 100.0 > percent * deposits as f64;
+                    exceeded.then(|| format!("{} disputes for {} deposits", disputes, deposits))
+                }
+                Check::NegativeBalance
+                    if account.available < Amount::ZERO || account.total < Amount::ZERO =>
+                {
+                    Some(format!(
+                        "available balance is {}, total is {}",
+                        account.available, account.total
+                    ))
+                }
+                _ => None,
+            };
+            let Some(reason) = reason else {
+                continue;
+            };
+            // the rule still triggered on an account that is already locked,
+            // it only doesn't need locking again
+            lock |= rule.action() == Action::Lock && !account.locked;
+            self.alerts
+                .push(alert(self.sequence, rule, rule.action(), tx, reason));
+        }
+
+        if lock {
+            if let Some(account) = self.find_account(client) {
+                account.locked = true;
+            }
+            // the history entry for this transaction should show the lock too
+            let account = self.accounts.get(&client).copied();
+            if let (Some(entry), Some(account)) = (
+                self.history.get_mut(&client).and_then(|h| h.last_mut()),
+                account,
+            ) {
+                entry.account = account;
+            }
+        }
+    }
+}
+
+/// How many of the entries were made by a transaction of the given kind
+fn count(entries: &[Entry], kind: fn(&Transaction) -> bool) -> usize {
+    entries
+        .iter()
+        .filter(|entry| entry.transaction.as_ref().is_some_and(kind))
+        .count()
+}
+
+fn alert(
+    sequence: Sequence,
+    rule: &Rule,
+    action: Action,
+    tx: &Transaction,
+    reason: String,
+) -> Alert {
+    warn!(rule = %rule.name, action = %action, reason = %reason, "rule triggered");
+    Alert {
+        sequence,
+        rule: rule.name.clone(),
+        action,
+        kind: tx.kind(),
+        client: tx.client_id(),
+        tx: tx.tx_id().clone(),
+        reason,
+    }
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Account, Entry, Ledger, LoggedTransaction, Sequence};
+
+/// A copy of everything held in a ledger, used to save a ledger and later pick
+/// up processing where it was left off
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    accounts: Vec<Account>,
+    transactions: Vec<LoggedTransaction>,
+    /// Missing from snapshots saved before sequence numbers were kept
+    #[serde(default)]
+    sequence: Sequence,
+    /// Every client's account history, one client after another
+    #[serde(default)]
+    history: Vec<Entry>,
+}
+
+impl Ledger {
+    /// Take a snapshot of the current accounts and logged transactions
+    pub fn snapshot(&self) -> Snapshot {
+        let mut clients: Vec<_> = self.history.keys().collect();
+        clients.sort_unstable();
+        Snapshot {
+            accounts: self.accounts().copied().collect(),
+            transactions: self.log.values().cloned().collect(),
+            sequence: self.sequence,
+            history: clients
+                .into_iter()
+                .flat_map(|client| self.history[client].iter().cloned())
+                .collect(),
+        }
+    }
+
+    /// Rebuild a ledger from a snapshot
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        let mut ledger = Ledger::new();
+        for account in snapshot.accounts {
+            ledger.accounts.insert(account.client, account);
+        }
+        for mut lt in snapshot.transactions {
+            lt.seed_transitions();
+            ledger.log.insert(lt.transaction.tx_id().clone(), lt);
+        }
+        ledger.sequence = snapshot.sequence;
+        for mut entry in snapshot.history {
+            if let Some(tx) = &mut entry.transaction {
+                if let Some(id) = ledger.intern(tx.tx_id()) {
+                    *tx.tx_id_mut() = id;
+                }
+            }
+            ledger
+                .history
+                .entry(entry.account.client)
+                .or_default()
+                .push(entry);
+        }
+        for history in ledger.history.values_mut() {
+            history.sort_by_key(|entry| entry.sequence);
+        }
+        // a snapshot without history only knows what the accounts were as of
+        // when it was taken
+        for (client, account) in &ledger.accounts {
+            ledger.history.entry(*client).or_insert_with(|| {
+                vec![Entry {
+                    sequence: snapshot.sequence,
+                    transaction: None,
+                    state: None,
+                    account: *account,
+                }]
+            });
+        }
+        ledger
+    }
+}
@@ -0,0 +1,61 @@
+use serde::Serialize;
+
+use super::{Amount, ClientId, Entry, Ledger, Sequence, State, TxId};
+
+/// A line in a client's statement, one for every change made to the account
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatementLine {
+    pub(crate) sequence: Sequence,
+    /// The type of the transaction, or `opening` for the balance an account
+    /// was restored with from a snapshot saved without its history
+    #[serde(rename = "type")]
+    pub(crate) kind: &'static str,
+    pub(crate) tx: Option<TxId>,
+    /// The amount moved, for disputes, resolutions and chargebacks the amount
+    /// of the deposit they refer to
+    pub(crate) amount: Option<Amount>,
+    /// The state the transaction was left in
+    pub(crate) state: Option<State>,
+    // the running balance
+    pub(crate) available: Amount,
+    pub(crate) held: Amount,
+    pub(crate) total: Amount,
+    pub(crate) locked: bool,
+}
+
+impl Ledger {
+    /// Every change made to a client's account, oldest first, with the balance
+    /// of the account after each one
+    pub fn statement(&self, client: ClientId) -> impl Iterator<Item = StatementLine> + '_ {
+        self.history(client)
+            .iter()
+            .map(|entry| self.statement_line(entry))
+    }
+
+    fn statement_line(&self, entry: &Entry) -> StatementLine {
+        let Entry {
+            sequence,
+            transaction,
+            state,
+            account,
+        } = entry;
+        let amount = transaction.as_ref().and_then(|tx| {
+            tx.amount().or_else(|| {
+                self.log
+                    .get(tx.tx_id())
+                    .and_then(|lt| lt.transaction.amount())
+            })
+        });
+        StatementLine {
+            sequence: *sequence,
+            kind: transaction.as_ref().map_or("opening", |tx| tx.kind()),
+            tx: transaction.as_ref().map(|tx| tx.tx_id().clone()),
+            amount,
+            state: *state,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+        }
+    }
+}
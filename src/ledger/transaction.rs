@@ -1,14 +1,17 @@
 use std::fmt::{Debug, Display, Formatter};
 
-use rust_decimal::Decimal;
-use rust_decimal_macros::dec;
-use serde::{Deserialize, Deserializer};
+use serde::de;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::{debug, info, info_span, trace};
 
-use crate::ledger::Account;
+use crate::ledger::{
+    Account, Amount, AmountError, ClientId, Limit, Precision, Rounding, Sequence, TxId,
+};
 
 use super::{Ledger, Process};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Transaction {
     Deposit(Deposit),
     Withdrawal(Withdrawal),
@@ -16,9 +19,9 @@ pub enum Transaction {
     Resolve(Resolve),
     Chargeback(Chargeback),
     Unknown {
-        client_id: u16,
-        tx_id: u32,
-        amount: Decimal,
+        client_id: ClientId,
+        tx_id: TxId,
+        amount: Amount,
     },
 }
 
@@ -29,29 +32,55 @@ impl Process for Transaction {
     type Output = Result<Receipt>;
 
     fn process(self, ledger: &mut Ledger) -> Result<Receipt> {
-        match self {
-            Transaction::Deposit(deposit) => deposit.process(ledger),
-            Transaction::Withdrawal(withdrawal) => withdrawal.process(ledger),
-            Transaction::Dispute(dispute) => dispute.process(ledger),
-            Transaction::Resolve(resolve) => resolve.process(ledger),
-            Transaction::Chargeback(chargeback) => chargeback.process(ledger),
-            Transaction::Unknown { .. } => Err(Error::UnknownTransactionType),
+        ledger.next_sequence();
+        let _span = info_span!(
+            "transaction",
+            sequence = ledger.sequence(),
+            kind = self.kind(),
+            client = %self.client_id(),
+            tx = %self.tx_id(),
+        )
+        .entered();
+        let result = ledger
+            .check_limits(&self)
+            .and_then(|()| ledger.screen(&self))
+            .and_then(|()| match self.clone() {
+                Transaction::Deposit(deposit) => deposit.process(ledger),
+                Transaction::Withdrawal(withdrawal) => withdrawal.process(ledger),
+                Transaction::Dispute(dispute) => dispute.process(ledger),
+                Transaction::Resolve(resolve) => resolve.process(ledger),
+                Transaction::Chargeback(chargeback) => chargeback.process(ledger),
+                Transaction::Unknown { .. } => Err(Error::UnknownTransactionType),
+            });
+        match &result {
+            Ok(()) => {
+                ledger.record_history(&self);
+                ledger.enforce_rules(&self);
+                debug!(outcome = "accepted", "transaction accepted");
+            }
+            Err(err) => info!(
+                outcome = "rejected",
+                code = err.code(),
+                error = %err,
+                "transaction rejected"
+            ),
         }
+        result
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Deposit {
-    client_id: u16,
-    tx_id: u32,
-    amount: Decimal,
+    client_id: ClientId,
+    tx_id: TxId,
+    amount: Amount,
 }
 
 impl Process for Deposit {
     type Output = Result<Receipt>;
 
     fn process(self, ledger: &mut Ledger) -> Result<Receipt> {
-        if self.amount <= dec!(0) {
+        if !self.amount.is_positive() {
             return Err(Error::InvalidAmount);
         }
 
@@ -60,25 +89,28 @@ impl Process for Deposit {
             return Err(Error::AccountLocked);
         }
 
-        account.available += self.amount;
-        account.total += self.amount;
-        ledger.log_transaction(self.tx_id, Transaction::Deposit(self));
+        let available = checked(account.available.checked_add(self.amount))?;
+        let total = checked(account.total.checked_add(self.amount))?;
+        account.available = available;
+        account.total = total;
+        trace!(amount = %self.amount, available = %available, total = %total, "credited");
+        ledger.log_transaction(self.tx_id.clone(), Transaction::Deposit(self));
         Ok(())
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Withdrawal {
-    pub client_id: u16,
-    pub tx_id: u32,
-    pub amount: Decimal,
+    pub client_id: ClientId,
+    pub tx_id: TxId,
+    pub amount: Amount,
 }
 
 impl Process for Withdrawal {
     type Output = Result<Receipt>;
 
     fn process(self, ledger: &mut Ledger) -> Result<Receipt> {
-        if self.amount <= dec!(0) {
+        if !self.amount.is_positive() {
             return Err(Error::InvalidAmount);
         }
 
@@ -95,38 +127,51 @@ impl Process for Withdrawal {
             });
         }
 
-        account.available -= self.amount;
-        account.total -= self.amount;
-        ledger.log_transaction(self.tx_id, Transaction::Withdrawal(self));
+        let available = checked(account.available.checked_sub(self.amount))?;
+        let total = checked(account.total.checked_sub(self.amount))?;
+        account.available = available;
+        account.total = total;
+        trace!(amount = %self.amount, available = %available, total = %total, "debited");
+        ledger.log_transaction(self.tx_id.clone(), Transaction::Withdrawal(self));
         Ok(())
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Dispute {
-    client_id: u16,
-    tx_id: u32,
+    client_id: ClientId,
+    tx_id: TxId,
 }
 
 impl Process for Dispute {
     type Output = Result<Receipt>;
 
     fn process(self, ledger: &mut Ledger) -> Result<Receipt> {
-        let (account, lt) = ledger.get_account_transaction_mut(self.client_id, self.tx_id)?;
+        let (sequence, max_disputes) = (ledger.sequence(), ledger.max_disputes);
+        let (account, lt) = ledger.get_account_transaction_mut(self.client_id, &self.tx_id)?;
 
         if lt.transaction.client_id() != self.client_id {
             return Err(Error::MismatchedClient);
         }
 
-        if lt.state != State::Processed {
+        // a resolved dispute can be disputed again, up to the cap
+        if !matches!(lt.state, State::Processed | State::Resolved) {
             return Err(Error::InvalidTransactionState { got: lt.state });
         }
+        if lt.disputes() >= max_disputes {
+            return Err(Error::DisputeLimitReached {
+                disputes: max_disputes,
+            });
+        }
 
         match lt.transaction {
-            Transaction::Deposit(deposit) => {
-                lt.state = State::Disputed;
-                account.available -= deposit.amount;
-                account.held += deposit.amount;
+            Transaction::Deposit(Deposit { amount, .. }) => {
+                let available = checked(account.available.checked_sub(amount))?;
+                let held = checked(account.held.checked_add(amount))?;
+                lt.transition(State::Disputed, sequence);
+                account.available = available;
+                account.held = held;
+                trace!(amount = %amount, available = %available, held = %held, "funds held");
                 Ok(())
             }
             _ => Err(Error::InvalidTransactionState { got: lt.state }),
@@ -134,17 +179,18 @@ impl Process for Dispute {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Resolve {
-    client_id: u16,
-    tx_id: u32,
+    client_id: ClientId,
+    tx_id: TxId,
 }
 
 impl Process for Resolve {
     type Output = Result<Receipt>;
 
     fn process(self, ledger: &mut Ledger) -> Result<Receipt> {
-        let (account, lt) = ledger.get_account_transaction_mut(self.client_id, self.tx_id)?;
+        let sequence = ledger.sequence();
+        let (account, lt) = ledger.get_account_transaction_mut(self.client_id, &self.tx_id)?;
 
         if lt.transaction.client_id() != self.client_id {
             return Err(Error::MismatchedClient);
@@ -154,10 +200,13 @@ impl Process for Resolve {
         }
 
         match lt.transaction {
-            Transaction::Deposit(deposit) => {
-                lt.state = State::Processed;
-                account.available += deposit.amount;
-                account.held -= deposit.amount;
+            Transaction::Deposit(Deposit { amount, .. }) => {
+                let available = checked(account.available.checked_add(amount))?;
+                let held = checked(account.held.checked_sub(amount))?;
+                lt.transition(State::Resolved, sequence);
+                account.available = available;
+                account.held = held;
+                trace!(amount = %amount, available = %available, held = %held, "funds released");
                 Ok(())
             }
             _ => Err(Error::InvalidTransactionState { got: lt.state }),
@@ -165,17 +214,18 @@ impl Process for Resolve {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Chargeback {
-    client_id: u16,
-    tx_id: u32,
+    client_id: ClientId,
+    tx_id: TxId,
 }
 
 impl Process for Chargeback {
     type Output = Result<Receipt>;
 
     fn process(self, ledger: &mut Ledger) -> Result<Receipt> {
-        let (account, lt) = ledger.get_account_transaction_mut(self.client_id, self.tx_id)?;
+        let sequence = ledger.sequence();
+        let (account, lt) = ledger.get_account_transaction_mut(self.client_id, &self.tx_id)?;
 
         if lt.transaction.client_id() != self.client_id {
             return Err(Error::MismatchedClient);
@@ -185,11 +235,14 @@ impl Process for Chargeback {
         }
 
         match lt.transaction {
-            Transaction::Deposit(deposit) => {
-                lt.state = State::Chargeback;
-                account.held -= deposit.amount;
-                account.total -= deposit.amount;
+            Transaction::Deposit(Deposit { amount, .. }) => {
+                let held = checked(account.held.checked_sub(amount))?;
+                let total = checked(account.total.checked_sub(amount))?;
+                lt.transition(State::ChargedBack, sequence);
+                account.held = held;
+                account.total = total;
                 account.locked = true;
+                trace!(amount = %amount, held = %held, total = %total, "charged back, account locked");
                 Ok(())
             }
             _ => Err(Error::InvalidTransactionState { got: lt.state }),
@@ -203,8 +256,8 @@ impl Ledger {
     /// account ids match correctly
     fn get_account_transaction_mut(
         &mut self,
-        client_id: u16,
-        tx_id: u32,
+        client_id: ClientId,
+        tx_id: &TxId,
     ) -> Result<(&mut Account, &mut LoggedTransaction)> {
         match self.find_account_and_transaction(client_id, tx_id) {
             (None, _) => Err(Error::AccountNotFound),
@@ -214,11 +267,17 @@ impl Ledger {
     }
 }
 
+/// Turn the result of a checked arithmetic operation on an account balance into
+/// an error, so the transaction is declined rather than corrupting the balance
+fn checked(amount: Option<Amount>) -> Result<Amount> {
+    amount.ok_or(Error::BalanceOverflow)
+}
+
 /// Error types for when a transaction could not be processed properly
 #[derive(Debug, PartialEq)]
 pub enum Error {
     /// When a withdraw is made but the client doesn't have enough available funds
-    InsufficientFunds { available: Decimal },
+    InsufficientFunds { available: Amount },
     /// When a dispute, resolution, or chargeback was made but the impacted transaction wasn't found
     TransactionNotFound,
     /// When a dispute, resolution, or chargeback was made on a transaction in the incorrect state
@@ -235,10 +294,45 @@ pub enum Error {
     AccountLocked,
     /// The type of the transaaction was unknown, we cannot process this
     UnknownTransactionType,
+    /// Applying the transaction would overflow or underflow an account balance
+    BalanceOverflow,
+    /// Another transaction in the same batch failed, so the whole batch was rolled back
+    BatchRolledBack,
+    /// A fraud rule blocked the transaction
+    Blocked { rule: String },
+    /// A deposit or withdrawal of `attempted` would go over one of the client's limits
+    LimitExceeded { limit: Limit, attempted: Amount },
+    /// A dispute on a transaction that has already been disputed as many times
+    /// as is allowed
+    DisputeLimitReached { disputes: usize },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// A stable, machine readable code for the error. These are part of the
+    /// output of the program, so an existing code must never change
+    pub fn code(&self) -> &'static str {
+        use Error::*;
+
+        match self {
+            InsufficientFunds { .. } => "insufficient_funds",
+            TransactionNotFound => "transaction_not_found",
+            InvalidTransactionState { .. } => "invalid_transaction_state",
+            AccountNotFound => "account_not_found",
+            InvalidAmount => "invalid_amount",
+            MismatchedClient => "mismatched_client",
+            AccountLocked => "account_locked",
+            UnknownTransactionType => "unknown_transaction_type",
+            BalanceOverflow => "balance_overflow",
+            BatchRolledBack => "batch_rolled_back",
+            Blocked { .. } => "blocked_by_rule",
+            LimitExceeded { .. } => "limit_exceeded",
+            DisputeLimitReached { .. } => "dispute_limit_reached",
+        }
+    }
+}
+
 impl std::error::Error for Error {}
 
 impl Display for Error {
@@ -260,42 +354,106 @@ impl Display for Error {
             }
             AccountLocked => write!(f, "the account is locked"),
             UnknownTransactionType => write!(f, "the transaction used an unknown transaction type"),
+            BalanceOverflow => write!(f, "the transaction would overflow the account balance"),
+            BatchRolledBack => write!(
+                f,
+                "another transaction in the batch failed, the batch was rolled back"
+            ),
+            Blocked { rule } => write!(f, "the transaction was blocked by rule '{}'", rule),
+            LimitExceeded { limit, attempted } => write!(
+                f,
+                "limit exceeded: the client is allowed {}, attempted '{}'",
+                limit, attempted
+            ),
+            DisputeLimitReached { disputes } => write!(
+                f,
+                "the transaction has already been disputed the most times allowed ({})",
+                disputes
+            ),
         }
     }
 }
 
 /// The possible states a transaction can be in when logged
-#[derive(Debug, PartialEq, Eq, Copy, Clone, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
 pub enum State {
     /// The transaction has been processed, and is valid
     Processed,
     /// The transaction has been disputed, funds are on hold
     Disputed,
-    /// The transaction has been charged back, funds were removed and account is locked
-    Chargeback,
+    /// The dispute was resolved and the funds released. The transaction can
+    /// only be disputed again if the ledger's cap on disputes allows it
+    Resolved,
+    /// The transaction has been charged back, funds were removed and account
+    /// is locked. Nothing can happen to it after this
+    #[serde(alias = "Chargeback")]
+    ChargedBack,
+}
+
+/// A state a logged transaction moved into, and the sequence number of the
+/// transaction that moved it there
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    pub sequence: Sequence,
+    pub state: State,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LoggedTransaction {
-    transaction: Transaction,
-    state: State,
+    pub(crate) transaction: Transaction,
+    pub(crate) state: State,
+    /// Every state the transaction has been in, oldest first. Missing from
+    /// snapshots saved before transitions were kept, see
+    /// [`LoggedTransaction::seed_transitions`]
+    #[serde(default)]
+    pub(crate) transitions: Vec<Transition>,
 }
 
 impl LoggedTransaction {
-    pub fn new(tx: Transaction) -> Self {
+    /// Log a transaction processed with the sequence number `sequence`
+    pub fn new(tx: Transaction, sequence: Sequence) -> Self {
         Self {
             transaction: tx,
             state: State::Processed,
+            transitions: vec![Transition {
+                sequence,
+                state: State::Processed,
+            }],
         }
     }
-}
 
-fn default_if_empty<'de, D, T>(de: D) -> std::result::Result<T, D::Error>
-where
-    D: serde::Deserializer<'de>,
-    T: serde::Deserialize<'de> + Default,
-{
-    Option::<T>::deserialize(de).map(|x| x.unwrap_or_default())
+    /// How many times the transaction has been disputed
+    pub fn disputes(&self) -> usize {
+        self.transitions
+            .iter()
+            .filter(|transition| transition.state == State::Disputed)
+            .count()
+    }
+
+    /// Fill in the transitions of a transaction loaded from a snapshot saved
+    /// before they were kept, so its disputes still count towards the cap.
+    /// Only the state it ended up in is known, so it's assumed to have taken
+    /// the shortest way there, and every transition has the sequence number 0
+    pub(crate) fn seed_transitions(&mut self) {
+        if !self.transitions.is_empty() {
+            return;
+        }
+        let states: &[State] = match self.state {
+            State::Processed => &[State::Processed],
+            State::Disputed => &[State::Processed, State::Disputed],
+            State::Resolved => &[State::Processed, State::Disputed, State::Resolved],
+            State::ChargedBack => &[State::Processed, State::Disputed, State::ChargedBack],
+        };
+        self.transitions = states
+            .iter()
+            .map(|&state| Transition { sequence: 0, state })
+            .collect();
+    }
+
+    fn transition(&mut self, state: State, sequence: Sequence) {
+        self.state = state;
+        self.transitions.push(Transition { sequence, state });
+    }
 }
 
 // This is to make printing our transactions a bit nicer due to our wrapped types
@@ -313,94 +471,213 @@ impl Display for Transaction {
 }
 
 impl Transaction {
-    fn client_id(self) -> u16 {
+    /// Build a transaction from its parts, where `kind` is the name used in the
+    /// `type` column. Transactions that don't take an amount ignore it, and an
+    /// unrecognised kind makes an `Unknown` transaction
+    pub(crate) fn from_parts(kind: &str, client_id: ClientId, tx_id: TxId, amount: Amount) -> Self {
+        match kind {
+            "deposit" => Transaction::Deposit(Deposit {
+                client_id,
+                tx_id,
+                amount,
+            }),
+            "withdrawal" => Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id,
+                amount,
+            }),
+            "dispute" => Transaction::Dispute(Dispute { client_id, tx_id }),
+            "resolve" => Transaction::Resolve(Resolve { client_id, tx_id }),
+            "chargeback" => Transaction::Chargeback(Chargeback { client_id, tx_id }),
+            _ => Transaction::Unknown {
+                client_id,
+                tx_id,
+                amount,
+            },
+        }
+    }
+
+    pub(crate) fn client_id(&self) -> ClientId {
         match self {
             Transaction::Deposit(d) => d.client_id,
             Transaction::Withdrawal(w) => w.client_id,
             Transaction::Dispute(d) => d.client_id,
             Transaction::Resolve(r) => r.client_id,
             Transaction::Chargeback(c) => c.client_id,
-            Transaction::Unknown { client_id, .. } => client_id,
+            Transaction::Unknown { client_id, .. } => *client_id,
+        }
+    }
+
+    pub(crate) fn tx_id(&self) -> &TxId {
+        match self {
+            Transaction::Deposit(d) => &d.tx_id,
+            Transaction::Withdrawal(w) => &w.tx_id,
+            Transaction::Dispute(d) => &d.tx_id,
+            Transaction::Resolve(r) => &r.tx_id,
+            Transaction::Chargeback(c) => &c.tx_id,
+            Transaction::Unknown { tx_id, .. } => tx_id,
+        }
+    }
+
+    pub(super) fn tx_id_mut(&mut self) -> &mut TxId {
+        match self {
+            Transaction::Deposit(d) => &mut d.tx_id,
+            Transaction::Withdrawal(w) => &mut w.tx_id,
+            Transaction::Dispute(d) => &mut d.tx_id,
+            Transaction::Resolve(r) => &mut r.tx_id,
+            Transaction::Chargeback(c) => &mut c.tx_id,
+            Transaction::Unknown { tx_id, .. } => tx_id,
+        }
+    }
+
+    /// The name of the transaction type, as used in the `type` column
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Transaction::Deposit(_) => "deposit",
+            Transaction::Withdrawal(_) => "withdrawal",
+            Transaction::Dispute(_) => "dispute",
+            Transaction::Resolve(_) => "resolve",
+            Transaction::Chargeback(_) => "chargeback",
+            Transaction::Unknown { .. } => "unknown",
         }
     }
+
+    pub(crate) fn amount(&self) -> Option<Amount> {
+        match self {
+            Transaction::Deposit(d) => Some(d.amount),
+            Transaction::Withdrawal(w) => Some(w.amount),
+            Transaction::Unknown { amount, .. } => Some(*amount),
+            _ => None,
+        }
+    }
+
+    /// Check everything about a transaction that doesn't depend on the state of
+    /// a ledger, i.e. whether it could ever be processed successfully
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Transaction::Unknown { .. } => Err(Error::UnknownTransactionType),
+            Transaction::Deposit(Deposit { amount, .. })
+            | Transaction::Withdrawal(Withdrawal { amount, .. })
+                if !amount.is_positive() =>
+            {
+                Err(Error::InvalidAmount)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Written out with the same columns that are read in by the `Deserialize` impl,
+// transactions without an amount leave it empty
+impl Serialize for Transaction {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("Transaction", 4)?;
+        s.serialize_field("type", self.kind())?;
+        s.serialize_field("client", &self.client_id())?;
+        s.serialize_field("tx", &self.tx_id())?;
+        s.serialize_field("amount", &self.amount())?;
+        s.end()
+    }
 }
 
-// This exists to create type safety in our transactions -- a Dispute only
-// cares about a client id and transaction id, so it doesn't make sense for an
-// amount to exist which may be read and used incorrectly. Note that this system
-// added a bit of bloat to the end product, check out the README for more info
+/// A transaction as it is read from an input, before its amount has been
+/// checked against `CURRENCY_DECIMAL_SCALE`. The amount is kept as text so that
+/// extra decimal places can be handled with a `Precision` policy
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransactionRecord {
+    #[serde(rename = "type")]
+    kind: TxKind,
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "tx")]
+    transaction_id: TxId,
+    #[serde(default)]
+    amount: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TxKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    #[serde(other)]
+    Unknown,
+}
+
+impl TransactionRecord {
+    /// Build the transaction, fitting its amount to `CURRENCY_DECIMAL_SCALE`
+    /// with `precision`. Also returns the rounding that was applied, if any
+    pub fn into_transaction(
+        self,
+        precision: Precision,
+    ) -> std::result::Result<(Transaction, Option<Rounding>), AmountError> {
+        let (amount, rounding) = match self.amount {
+            Some(amount) => Amount::parse_with(&amount, precision)?,
+            None => (Amount::ZERO, None),
+        };
+
+        // This exists to create type safety in our transactions -- a Dispute only
+        // cares about a client id and transaction id, so it doesn't make sense for an
+        // amount to exist which may be read and used incorrectly. Note that this system
+        // added a bit of bloat to the end product, check out the README for more info
+        let (client_id, tx_id) = (self.client_id, self.transaction_id);
+        let tx = match self.kind {
+            TxKind::Deposit => Transaction::Deposit(Deposit {
+                client_id,
+                tx_id,
+                amount,
+            }),
+            TxKind::Withdrawal => Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id,
+                amount,
+            }),
+            TxKind::Dispute => Transaction::Dispute(Dispute { client_id, tx_id }),
+            TxKind::Resolve => Transaction::Resolve(Resolve { client_id, tx_id }),
+            TxKind::Chargeback => Transaction::Chargeback(Chargeback { client_id, tx_id }),
+            TxKind::Unknown => Transaction::Unknown {
+                client_id,
+                tx_id,
+                amount,
+            },
+        };
+        Ok((tx, rounding))
+    }
+}
+
+// Amounts with too many decimal places are rejected, inputs that allow them
+// are read through `TransactionRecord` instead
 impl<'de> Deserialize<'de> for Transaction {
     fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        #[derive(Debug, Copy, Clone, Deserialize)]
-        pub struct TxIntermediate {
-            #[serde(rename = "type")]
-            pub kind: TxKind,
-            #[serde(rename = "client")]
-            pub client_id: u16,
-            #[serde(rename = "tx")]
-            pub transaction_id: u32,
-            #[serde(deserialize_with = "default_if_empty")]
-            pub amount: Decimal,
-        }
-
-        #[derive(Debug, PartialEq, Eq, Copy, Clone, Deserialize)]
-        #[serde(rename_all = "lowercase")]
-        pub enum TxKind {
-            Deposit,
-            Withdrawal,
-            Dispute,
-            Resolve,
-            Chargeback,
-            #[serde(other)]
-            Unknown,
-        }
-
-        let i = TxIntermediate::deserialize(deserializer)?;
-        match i.kind {
-            TxKind::Deposit => Ok(Transaction::Deposit(Deposit {
-                client_id: i.client_id,
-                tx_id: i.transaction_id,
-                amount: i.amount,
-            })),
-            TxKind::Withdrawal => Ok(Transaction::Withdrawal(Withdrawal {
-                client_id: i.client_id,
-                tx_id: i.transaction_id,
-                amount: i.amount,
-            })),
-            TxKind::Dispute => Ok(Transaction::Dispute(Dispute {
-                client_id: i.client_id,
-                tx_id: i.transaction_id,
-            })),
-            TxKind::Resolve => Ok(Transaction::Resolve(Resolve {
-                client_id: i.client_id,
-                tx_id: i.transaction_id,
-            })),
-            TxKind::Chargeback => Ok(Transaction::Chargeback(Chargeback {
-                client_id: i.client_id,
-                tx_id: i.transaction_id,
-            })),
-            TxKind::Unknown => Ok(Transaction::Unknown {
-                client_id: i.client_id,
-                tx_id: i.transaction_id,
-                amount: i.amount,
-            }),
-        }
+        let (tx, _) = TransactionRecord::deserialize(deserializer)?
+            .into_transaction(Precision::Reject)
+            .map_err(de::Error::custom)?;
+        Ok(tx)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use rust_decimal::prelude::*;
+    use rust_decimal::Decimal;
     use rust_decimal_macros::dec;
 
     use super::*;
+    use crate::ledger::id::{ClientIdRepr, TxIdRepr};
+    use crate::ledger::rules::Action;
+    use crate::ledger::{Alert, Limits, Patterns, Rules};
 
     fn build_ledger() -> Ledger {
         let mut ledger = Ledger::new();
-        let tx_result = ledger.process(deposit(1, 1, 18.into()));
+        let tx_result = ledger.process(deposit(1, 1, amount(dec!(18))));
         assert!(tx_result.is_ok());
         ledger
     }
@@ -408,18 +685,18 @@ mod tests {
     #[test]
     fn test_deposit_new_client() {
         let mut ledger = build_ledger();
-        let deposit = ledger.process(deposit(13, 4, Decimal::new(51234, 4)));
+        let deposit = ledger.process(deposit(13, 4, Amount::from_units(51234)));
         assert!(deposit.is_ok());
 
-        let account = ledger.find_account(13);
+        let account = ledger.find_account(ClientId::from(13));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 13,
-                available: Decimal::new(51234, 4),
-                held: 0.into(),
-                total: Decimal::new(51234, 4),
+                client: ClientId::from(13),
+                available: Amount::from_units(51234),
+                held: amount(dec!(0)),
+                total: Amount::from_units(51234),
                 locked: false,
             }
         );
@@ -428,18 +705,18 @@ mod tests {
     #[test]
     fn test_deposit_existing_client() {
         let mut ledger = build_ledger();
-        let deposit = ledger.process(deposit(1, 4, Decimal::new(51234, 4)));
+        let deposit = ledger.process(deposit(1, 4, Amount::from_units(51234)));
         assert!(deposit.is_ok());
 
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: Decimal::new(231234, 4),
-                held: 0.into(),
-                total: Decimal::new(231234, 4),
+                client: ClientId::from(1),
+                available: Amount::from_units(231234),
+                held: amount(dec!(0)),
+                total: Amount::from_units(231234),
                 locked: false,
             }
         );
@@ -450,23 +727,23 @@ mod tests {
         let mut ledger = build_ledger();
 
         // Depositing nothing
-        let result = ledger.process(deposit(1, 3, dec!(0)));
+        let result = ledger.process(deposit(1, 3, amount(dec!(0))));
         assert_eq!(result, Err(Error::InvalidAmount));
 
         // Depositing negative
-        let result = ledger.process(deposit(1, 3, dec!(-1.5)));
+        let result = ledger.process(deposit(1, 3, amount(dec!(-1.5))));
         assert_eq!(result, Err(Error::InvalidAmount));
 
         // make sure the account hasn't changed
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: dec!(18),
-                held: 0.into(),
-                total: dec!(18),
+                client: ClientId::from(1),
+                available: amount(dec!(18)),
+                held: amount(dec!(0)),
+                total: amount(dec!(18)),
                 locked: false,
             }
         );
@@ -475,18 +752,18 @@ mod tests {
     #[test]
     fn test_withdraw_success() {
         let mut ledger = build_ledger();
-        let withdraw = ledger.process(withdraw(1, 4, dec!(12.5111)));
+        let withdraw = ledger.process(withdraw(1, 4, amount(dec!(12.5111))));
         assert!(withdraw.is_ok());
 
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: dec!(5.4889),
-                held: 0.into(),
-                total: dec!(5.4889),
+                client: ClientId::from(1),
+                available: amount(dec!(5.4889)),
+                held: amount(dec!(0)),
+                total: amount(dec!(5.4889)),
                 locked: false,
             }
         );
@@ -495,31 +772,31 @@ mod tests {
     #[test]
     fn test_withdraw_account_not_found() {
         let mut ledger = build_ledger();
-        let result = ledger.process(withdraw(2, 4, dec!(2)));
+        let result = ledger.process(withdraw(2, 4, amount(dec!(2))));
         assert_eq!(result, Err(Error::AccountNotFound));
     }
 
     #[test]
     fn test_withdraw_insufficient_funds() {
         let mut ledger = build_ledger();
-        let result = ledger.process(withdraw(1, 4, dec!(18.5111)));
+        let result = ledger.process(withdraw(1, 4, amount(dec!(18.5111))));
         assert_eq!(
             result,
             Err(Error::InsufficientFunds {
-                available: dec!(18)
+                available: amount(dec!(18))
             })
         );
 
         // make sure the account hasn't changed
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: dec!(18),
-                held: 0.into(),
-                total: dec!(18),
+                client: ClientId::from(1),
+                available: amount(dec!(18)),
+                held: amount(dec!(0)),
+                total: amount(dec!(18)),
                 locked: false,
             }
         );
@@ -530,23 +807,23 @@ mod tests {
         let mut ledger = build_ledger();
 
         // Depositing nothing
-        let result = ledger.process(withdraw(1, 3, dec!(0)));
+        let result = ledger.process(withdraw(1, 3, amount(dec!(0))));
         assert_eq!(result, Err(Error::InvalidAmount));
 
         // Depositing negative
-        let result = ledger.process(withdraw(1, 3, dec!(-1.5)));
+        let result = ledger.process(withdraw(1, 3, amount(dec!(-1.5))));
         assert_eq!(result, Err(Error::InvalidAmount));
 
         // make sure the account hasn't changed
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: dec!(18),
-                held: 0.into(),
-                total: dec!(18),
+                client: ClientId::from(1),
+                available: amount(dec!(18)),
+                held: amount(dec!(0)),
+                total: amount(dec!(18)),
                 locked: false,
             }
         );
@@ -557,17 +834,20 @@ mod tests {
         let mut ledger = build_ledger();
         let dispute = ledger.process(dispute(1, 1));
         assert!(dispute.is_ok());
-        assert_eq!(ledger.log.get(&1).unwrap().state, State::Disputed);
+        assert_eq!(
+            ledger.log.get(&TxId::from(1)).unwrap().state,
+            State::Disputed
+        );
 
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: 0.into(),
-                held: 18.into(),
-                total: 18.into(),
+                client: ClientId::from(1),
+                available: amount(dec!(0)),
+                held: amount(dec!(18)),
+                total: amount(dec!(18)),
                 locked: false,
             }
         );
@@ -576,7 +856,7 @@ mod tests {
     #[test]
     fn test_dispute_invalid_transaction() {
         let mut ledger = build_ledger();
-        let withdrawal = ledger.process(withdraw(1, 2, dec!(10)));
+        let withdrawal = ledger.process(withdraw(1, 2, amount(dec!(10))));
         assert!(withdrawal.is_ok());
         let dispute1 = ledger.process(dispute(1, 1));
         assert!(dispute1.is_ok());
@@ -594,15 +874,15 @@ mod tests {
         }
 
         // verify the state of the account
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: dec!(-10),
-                held: dec!(18),
-                total: dec!(8),
+                client: ClientId::from(1),
+                available: amount(dec!(-10)),
+                held: amount(dec!(18)),
+                total: amount(dec!(8)),
                 locked: false,
             }
         );
@@ -625,7 +905,7 @@ mod tests {
     #[test]
     fn test_dispute_mismatched_client() {
         let mut ledger = build_ledger();
-        let deposit2 = ledger.process(deposit(2, 2, 10.into()));
+        let deposit2 = ledger.process(deposit(2, 2, amount(dec!(10))));
         assert!(deposit2.is_ok());
         let result = ledger.process(dispute(2, 1));
         assert_eq!(result, Err(Error::MismatchedClient));
@@ -639,18 +919,21 @@ mod tests {
         let resolve = ledger.process(resolve(1, 1));
         assert!(resolve.is_ok());
 
-        // make sure the transaction went back to the processed state
-        assert_eq!(ledger.log.get(&1).unwrap().state, State::Processed);
+        // make sure the transaction moved on to the resolved state
+        assert_eq!(
+            ledger.log.get(&TxId::from(1)).unwrap().state,
+            State::Resolved
+        );
 
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: dec!(18),
-                held: 0.into(),
-                total: dec!(18),
+                client: ClientId::from(1),
+                available: amount(dec!(18)),
+                held: amount(dec!(0)),
+                total: amount(dec!(18)),
                 locked: false,
             }
         );
@@ -659,7 +942,7 @@ mod tests {
     #[test]
     fn test_resolve_invalid_transaction() {
         let mut ledger = build_ledger();
-        let withdrawal = ledger.process(withdraw(1, 2, dec!(10)));
+        let withdrawal = ledger.process(withdraw(1, 2, amount(dec!(10))));
         assert!(withdrawal.is_ok());
 
         // resolve on a withdrawal
@@ -675,15 +958,15 @@ mod tests {
         }
 
         // verify the state of the account
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: dec!(8),
-                held: dec!(0),
-                total: dec!(8),
+                client: ClientId::from(1),
+                available: amount(dec!(8)),
+                held: amount(dec!(0)),
+                total: amount(dec!(8)),
                 locked: false,
             }
         );
@@ -706,7 +989,7 @@ mod tests {
     #[test]
     fn test_resolve_mismatched_client() {
         let mut ledger = build_ledger();
-        let deposit2 = ledger.process(deposit(2, 2, 10.into()));
+        let deposit2 = ledger.process(deposit(2, 2, amount(dec!(10))));
         assert!(deposit2.is_ok());
         let dispute = ledger.process(dispute(2, 2));
         assert!(dispute.is_ok());
@@ -723,17 +1006,20 @@ mod tests {
         assert!(chargeback.is_ok());
 
         // make sure the transaction went back to the chargebacked state
-        assert_eq!(ledger.log.get(&1).unwrap().state, State::Chargeback);
+        assert_eq!(
+            ledger.log.get(&TxId::from(1)).unwrap().state,
+            State::ChargedBack
+        );
 
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: 0.into(),
-                held: 0.into(),
-                total: 0.into(),
+                client: ClientId::from(1),
+                available: amount(dec!(0)),
+                held: amount(dec!(0)),
+                total: amount(dec!(0)),
                 locked: true,
             }
         );
@@ -742,7 +1028,7 @@ mod tests {
     #[test]
     fn test_chargeback_invalid_transaction() {
         let mut ledger = build_ledger();
-        let withdrawal = ledger.process(withdraw(1, 2, dec!(10)));
+        let withdrawal = ledger.process(withdraw(1, 2, amount(dec!(10))));
         assert!(withdrawal.is_ok());
 
         // chargeback on a withdrawal
@@ -758,15 +1044,15 @@ mod tests {
         }
 
         // verify the state of the account
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: dec!(8),
-                held: dec!(0),
-                total: dec!(8),
+                client: ClientId::from(1),
+                available: amount(dec!(8)),
+                held: amount(dec!(0)),
+                total: amount(dec!(8)),
                 locked: false,
             }
         );
@@ -789,7 +1075,7 @@ mod tests {
     #[test]
     fn test_chargeback_mismatched_client() {
         let mut ledger = build_ledger();
-        let deposit2 = ledger.process(deposit(2, 2, 10.into()));
+        let deposit2 = ledger.process(deposit(2, 2, amount(dec!(10))));
         assert!(deposit2.is_ok());
         let dispute = ledger.process(dispute(2, 2));
         assert!(dispute.is_ok());
@@ -797,12 +1083,114 @@ mod tests {
         assert_eq!(result, Err(Error::MismatchedClient));
     }
 
+    #[test]
+    fn test_dispute_lifecycle() {
+        let mut ledger = build_ledger();
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        assert!(ledger.process(resolve(1, 1)).is_ok());
+        // a resolved dispute can't be resolved or charged back again, nor
+        // disputed again by default
+        assert_eq!(
+            ledger.process(resolve(1, 1)),
+            Err(Error::InvalidTransactionState {
+                got: State::Resolved
+            })
+        );
+        assert_eq!(
+            ledger.process(chargeback(1, 1)),
+            Err(Error::InvalidTransactionState {
+                got: State::Resolved
+            })
+        );
+        assert_eq!(
+            ledger.process(dispute(1, 1)),
+            Err(Error::DisputeLimitReached { disputes: 1 })
+        );
+        ledger.set_max_disputes(2);
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        assert!(ledger.process(chargeback(1, 1)).is_ok());
+        assert_eq!(
+            ledger.process(dispute(1, 1)),
+            Err(Error::InvalidTransactionState {
+                got: State::ChargedBack
+            })
+        );
+
+        // declined transactions don't move the state, so they're not transitions
+        let lt = ledger.transaction(&TxId::from(1)).unwrap();
+        assert_eq!(lt.disputes(), 2);
+        assert_eq!(
+            lt.transitions,
+            [
+                (1, State::Processed),
+                (2, State::Disputed),
+                (3, State::Resolved),
+                (7, State::Disputed),
+                (8, State::ChargedBack),
+            ]
+            .map(|(sequence, state)| Transition { sequence, state })
+        );
+    }
+
+    #[test]
+    fn test_max_disputes() {
+        let mut ledger = build_ledger();
+        ledger.set_max_disputes(2);
+        for _ in 0..2 {
+            assert!(ledger.process(dispute(1, 1)).is_ok());
+            assert!(ledger.process(resolve(1, 1)).is_ok());
+        }
+        assert_eq!(
+            ledger.process(dispute(1, 1)),
+            Err(Error::DisputeLimitReached { disputes: 2 })
+        );
+        assert_eq!(
+            ledger.transaction(&TxId::from(1)).unwrap().state,
+            State::Resolved
+        );
+
+        // a dispute rolled back with its batch doesn't count towards the cap
+        let mut ledger = build_ledger();
+        ledger.begin();
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        ledger.rollback();
+        let lt = ledger.transaction(&TxId::from(1)).unwrap();
+        assert_eq!((lt.state, lt.transitions.len()), (State::Processed, 1));
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+    }
+
+    #[test]
+    fn test_old_snapshot_states() {
+        let json = r#"{
+            "accounts": [{"client": 1, "available": "0", "held": "0", "total": "0", "locked": true}],
+            "transactions": [
+                {"transaction": {"type": "deposit", "client": 1, "tx": "1", "amount": "5"}, "state": "Chargeback"},
+                {"transaction": {"type": "deposit", "client": 1, "tx": "2", "amount": "5"}, "state": "Resolved"}
+            ]
+        }"#;
+        let mut ledger = Ledger::from_snapshot(serde_json::from_str(json).unwrap());
+        let lt = ledger.transaction(&TxId::from(1)).unwrap();
+        assert_eq!(lt.state, State::ChargedBack);
+        assert_eq!(
+            lt.transitions,
+            [State::Processed, State::Disputed, State::ChargedBack]
+                .map(|state| Transition { sequence: 0, state })
+        );
+
+        // the dispute the transaction must have had still counts
+        assert_eq!(ledger.transaction(&TxId::from(2)).unwrap().disputes(), 1);
+        assert_eq!(
+            ledger.process(dispute(1, 2)),
+            Err(Error::DisputeLimitReached { disputes: 1 })
+        );
+    }
+
     #[test]
     fn test_locked_account() {
         let mut ledger = build_ledger();
-        let deposit2 = ledger.process(deposit(1, 2, 10.into()));
+        let deposit2 = ledger.process(deposit(1, 2, amount(dec!(10))));
         assert!(deposit2.is_ok());
-        let deposit3 = ledger.process(deposit(1, 3, 100.into()));
+        let deposit3 = ledger.process(deposit(1, 3, amount(dec!(100))));
         assert!(deposit3.is_ok());
         let dispute3 = ledger.process(dispute(1, 3));
         assert!(dispute3.is_ok());
@@ -814,11 +1202,11 @@ mod tests {
 
         // Deposits and withdrawals should be blocked
         assert_eq!(
-            ledger.process(deposit(1, 5, 13.into())),
+            ledger.process(deposit(1, 5, amount(dec!(13)))),
             Err(Error::AccountLocked)
         );
         assert_eq!(
-            ledger.process(withdraw(1, 2, 1.into())),
+            ledger.process(withdraw(1, 2, amount(dec!(1)))),
             Err(Error::AccountLocked)
         );
 
@@ -832,60 +1220,796 @@ mod tests {
         // 18 is disputed + charged back
         // 10 is disputed + charged back
         // 100 is disputed + resolved
-        let account = ledger.find_account(1);
+        let account = ledger.find_account(ClientId::from(1));
         assert!(account.is_some());
         assert_eq!(
             account.unwrap(),
             &Account {
-                client: 1,
-                available: 100.into(),
-                held: 0.into(),
-                total: 100.into(),
+                client: ClientId::from(1),
+                available: amount(dec!(100)),
+                held: amount(dec!(0)),
+                total: amount(dec!(100)),
                 locked: true,
             }
         );
     }
 
+    #[test]
+    fn test_dispute_string_tx_id() {
+        let mut ledger = build_ledger();
+        let uuid = "9a7d5c1e-2f4b-4e8a-b6c3-0d1e2f3a4b5c";
+        let deposit = Transaction::Deposit(Deposit {
+            client_id: ClientId::from(1),
+            tx_id: TxId::from(uuid),
+            amount: amount(dec!(2.5)),
+        });
+        assert!(ledger.process(deposit).is_ok());
+
+        let dispute = Transaction::Dispute(Dispute {
+            client_id: ClientId::from(1),
+            tx_id: TxId::from(uuid),
+        });
+        assert!(ledger.process(dispute).is_ok());
+        assert_eq!(
+            ledger.log.get(&TxId::from(uuid)).unwrap().state,
+            State::Disputed
+        );
+
+        // a different reference, even one that only differs by case, isn't found
+        let dispute = Transaction::Dispute(Dispute {
+            client_id: ClientId::from(1),
+            tx_id: TxId::from(uuid.to_uppercase().as_str()),
+        });
+        assert_eq!(ledger.process(dispute), Err(Error::TransactionNotFound));
+    }
+
+    #[test]
+    fn test_string_tx_id_stored_once() {
+        let mut ledger = Ledger::new();
+        let uuid = "9a7d5c1e-2f4b-4e8a-b6c3-0d1e2f3a4b5c";
+        let deposit = Transaction::Deposit(Deposit {
+            client_id: ClientId::from(1),
+            tx_id: TxId::from(uuid),
+            amount: amount(dec!(2.5)),
+        });
+        assert!(ledger.process(deposit).is_ok());
+        let dispute = Transaction::Dispute(Dispute {
+            client_id: ClientId::from(1),
+            tx_id: TxId::from(uuid),
+        });
+        assert!(ledger.process(dispute).is_ok());
+
+        // the dispute in the history refers to the id logged with the deposit,
+        // rather than keeping its own copy
+        let (logged, _) = ledger.log.get_key_value(&TxId::from(uuid)).unwrap();
+        let history = ledger.history(ClientId::from(1));
+        assert_eq!(history.len(), 2);
+        for entry in history {
+            assert!(entry.transaction.as_ref().unwrap().tx_id().shares(logged));
+        }
+
+        // and so does one restored from a snapshot
+        let ledger = Ledger::from_snapshot(ledger.snapshot());
+        let (logged, _) = ledger.log.get_key_value(&TxId::from(uuid)).unwrap();
+        for entry in ledger.history(ClientId::from(1)) {
+            assert!(entry.transaction.as_ref().unwrap().tx_id().shares(logged));
+        }
+    }
+
+    #[test]
+    fn test_batch_commit() {
+        let mut ledger = build_ledger();
+        ledger.begin();
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+        assert!(ledger.process(deposit(2, 3, amount(dec!(5)))).is_ok());
+        ledger.commit();
+
+        assert_eq!(
+            ledger.find_account(ClientId::from(1)).unwrap().total,
+            amount(dec!(20))
+        );
+        assert_eq!(
+            ledger.find_account(ClientId::from(2)).unwrap().total,
+            amount(dec!(5))
+        );
+        assert!(ledger.log.contains_key(&TxId::from(3)));
+    }
+
+    #[test]
+    fn test_batch_rollback() {
+        let mut ledger = build_ledger();
+        let before = *ledger.find_account(ClientId::from(1)).unwrap();
+
+        ledger.begin();
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        assert!(ledger.process(deposit(2, 3, amount(dec!(5)))).is_ok());
+        ledger.rollback();
+
+        // the account and the dispute on tx 1 are back to how they were, and
+        // everything created in the batch is gone
+        assert_eq!(
+            ledger.find_account(ClientId::from(1)),
+            Some(&mut before.clone())
+        );
+        assert_eq!(
+            ledger.log.get(&TxId::from(1)).unwrap().state,
+            State::Processed
+        );
+        assert!(ledger.find_account(ClientId::from(2)).is_none());
+        assert!(!ledger.log.contains_key(&TxId::from(2)));
+        assert!(!ledger.log.contains_key(&TxId::from(3)));
+    }
+
+    #[test]
+    fn test_nested_batch() {
+        let mut ledger = build_ledger();
+        ledger.begin();
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+
+        // rolling back the inner batch keeps the outer batch's changes
+        ledger.begin();
+        assert!(ledger.process(deposit(1, 3, amount(dec!(3)))).is_ok());
+        ledger.rollback();
+        assert_eq!(
+            ledger.find_account(ClientId::from(1)).unwrap().total,
+            amount(dec!(20))
+        );
+
+        // committing the inner batch still lets the outer batch undo it
+        ledger.begin();
+        assert!(ledger.process(deposit(1, 4, amount(dec!(4)))).is_ok());
+        ledger.commit();
+        ledger.rollback();
+        assert_eq!(
+            ledger.find_account(ClientId::from(1)).unwrap().total,
+            amount(dec!(18))
+        );
+        assert!(!ledger.log.contains_key(&TxId::from(2)));
+        assert!(!ledger.log.contains_key(&TxId::from(4)));
+    }
+
+    #[test]
+    fn test_commit_outermost_batch() {
+        let mut ledger = build_ledger();
+        ledger.begin();
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+        ledger.begin();
+        assert!(ledger.process(deposit(1, 3, amount(dec!(3)))).is_ok());
+
+        // the outer batch is kept, and the inner one can still be rolled back
+        ledger.commit_outermost();
+        ledger.rollback();
+        assert_eq!(
+            ledger.find_account(ClientId::from(1)).unwrap().total,
+            amount(dec!(20))
+        );
+        assert!(ledger.log.contains_key(&TxId::from(2)));
+        assert!(!ledger.log.contains_key(&TxId::from(3)));
+        assert!(ledger.journals.is_empty());
+    }
+
+    #[test]
+    fn test_balance_at() {
+        let mut ledger = Ledger::new();
+        assert!(ledger.process(deposit(1, 1, amount(dec!(10)))).is_ok());
+        assert!(ledger.process(deposit(2, 2, amount(dec!(5)))).is_ok());
+        // declined, but it still takes up sequence number 3
+        assert!(ledger.process(withdraw(1, 3, amount(dec!(50)))).is_err());
+        assert!(ledger.process(withdraw(1, 4, amount(dec!(4)))).is_ok());
+        assert!(ledger.process(dispute(2, 2)).is_ok());
+        assert_eq!(ledger.sequence(), 5);
+
+        let client = ClientId::from(1);
+        assert_eq!(ledger.balance_at(client, 0), None);
+        assert_eq!(
+            ledger.balance_at(client, 1).unwrap().total,
+            amount(dec!(10))
+        );
+        assert_eq!(
+            ledger.balance_at(client, 3).unwrap().total,
+            amount(dec!(10))
+        );
+        assert_eq!(ledger.balance_at(client, 4).unwrap().total, amount(dec!(6)));
+        assert_eq!(
+            ledger.balance_at(client, 100).unwrap().total,
+            amount(dec!(6))
+        );
+
+        let at = |sequence| {
+            ledger
+                .accounts_at(sequence)
+                .map(|a| (a.client, a.available, a.held))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            at(1),
+            vec![(ClientId::from(1), amount(dec!(10)), Amount::ZERO)]
+        );
+        assert_eq!(
+            at(4),
+            vec![
+                (ClientId::from(1), amount(dec!(6)), Amount::ZERO),
+                (ClientId::from(2), amount(dec!(5)), Amount::ZERO)
+            ]
+        );
+        assert_eq!(
+            at(5),
+            vec![
+                (ClientId::from(1), amount(dec!(6)), Amount::ZERO),
+                (ClientId::from(2), Amount::ZERO, amount(dec!(5)))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_balance_at_after_rollback() {
+        let mut ledger = Ledger::new();
+        assert!(ledger.process(deposit(1, 1, amount(dec!(10)))).is_ok());
+        ledger.begin();
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+        assert!(ledger.process(deposit(2, 3, amount(dec!(5)))).is_ok());
+        ledger.rollback();
+        assert!(ledger.process(deposit(1, 4, amount(dec!(1)))).is_ok());
+
+        // the rolled back transactions keep their sequence numbers, but their
+        // changes are gone from the history
+        let client = ClientId::from(1);
+        assert_eq!(
+            ledger.balance_at(client, 2).unwrap().total,
+            amount(dec!(10))
+        );
+        assert_eq!(
+            ledger.balance_at(client, 4).unwrap().total,
+            amount(dec!(11))
+        );
+        assert_eq!(ledger.balance_at(ClientId::from(2), 3), None);
+        assert_eq!(ledger.accounts_at(3).count(), 1);
+    }
+
+    #[test]
+    fn test_keep_history() {
+        let mut ledger = Ledger::new();
+        assert!(ledger.process(deposit(1, 1, amount(dec!(10)))).is_ok());
+        ledger.set_keep_history(false);
+        assert!(ledger.history(ClientId::from(1)).is_empty());
+
+        // the accounts still change, they just aren't recorded
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+        ledger.begin();
+        assert!(ledger.process(deposit(2, 3, amount(dec!(5)))).is_ok());
+        ledger.rollback();
+        assert!(ledger.history.is_empty());
+        assert_eq!(ledger.accounts[&ClientId::from(1)].total, amount(dec!(12)));
+        assert_eq!(ledger.accounts_at(2).count(), 0);
+
+        ledger.set_keep_history(true);
+        assert!(ledger.process(deposit(1, 4, amount(dec!(1)))).is_ok());
+        assert_eq!(
+            ledger.balance_at(ClientId::from(1), 4).unwrap().total,
+            amount(dec!(13))
+        );
+    }
+
+    #[test]
+    fn test_balance_at_from_snapshot() {
+        let mut ledger = Ledger::new();
+        assert!(ledger.process(deposit(1, 1, amount(dec!(10)))).is_ok());
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+
+        let json = serde_json::to_string(&ledger.snapshot()).unwrap();
+        let mut ledger = Ledger::from_snapshot(serde_json::from_str(&json).unwrap());
+        assert!(ledger.process(deposit(1, 3, amount(dec!(3)))).is_ok());
+
+        let client = ClientId::from(1);
+        assert_eq!(ledger.sequence(), 3);
+        assert_eq!(
+            ledger.balance_at(client, 1).unwrap().total,
+            amount(dec!(10))
+        );
+        assert_eq!(
+            ledger.balance_at(client, 3).unwrap().total,
+            amount(dec!(15))
+        );
+    }
+
+    #[test]
+    fn test_statement() {
+        let mut ledger = build_ledger();
+        assert!(ledger.process(deposit(2, 2, amount(dec!(5)))).is_ok());
+        assert!(ledger.process(withdraw(1, 3, amount(dec!(3)))).is_ok());
+        assert!(ledger.process(withdraw(1, 4, amount(dec!(100)))).is_err());
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        assert!(ledger.process(chargeback(1, 1)).is_ok());
+
+        let lines: Vec<_> = ledger
+            .statement(ClientId::from(1))
+            .map(|l| (l.sequence, l.kind, l.amount, l.state, l.available, l.held))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (
+                    1,
+                    "deposit",
+                    Some(amount(dec!(18))),
+                    Some(State::Processed),
+                    amount(dec!(18)),
+                    Amount::ZERO
+                ),
+                (
+                    3,
+                    "withdrawal",
+                    Some(amount(dec!(3))),
+                    Some(State::Processed),
+                    amount(dec!(15)),
+                    Amount::ZERO
+                ),
+                (
+                    5,
+                    "dispute",
+                    Some(amount(dec!(18))),
+                    Some(State::Disputed),
+                    amount(dec!(-3)),
+                    amount(dec!(18))
+                ),
+                (
+                    6,
+                    "chargeback",
+                    Some(amount(dec!(18))),
+                    Some(State::ChargedBack),
+                    amount(dec!(-3)),
+                    Amount::ZERO
+                ),
+            ]
+        );
+        assert_eq!(ledger.statement(ClientId::from(3)).count(), 0);
+    }
+
     #[test]
     fn test_unknown_transaction() {
         let mut ledger = build_ledger();
         let result = ledger.process(Transaction::Unknown {
-            client_id: 5,
-            tx_id: 10,
-            amount: dec!(1.5),
+            client_id: ClientId::from(5),
+            tx_id: TxId::from(10),
+            amount: amount(dec!(1.5)),
         });
         assert_eq!(result, Err(Error::UnknownTransactionType));
     }
 
+    /// Parses a rules, limits or patterns config, failing the test if it's invalid
+    fn config<T: serde::de::DeserializeOwned>(
+        json: &str,
+        validate: fn(&T) -> std::result::Result<(), String>,
+    ) -> T {
+        let config = serde_json::from_str(json).unwrap();
+        validate(&config).unwrap();
+        config
+    }
+
+    #[test]
+    fn test_rules_lock_after_repeated_disputes() {
+        let mut ledger = build_ledger();
+        ledger.set_rules(config(
+            r#"{"rules": [{"name": "disputes", "type": "repeated_disputes", "disputes": 2, "within": 3}]}"#,
+            Rules::validate,
+        ));
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        assert!(ledger.take_alerts().is_empty());
+
+        // the lock is undone along with the dispute that triggered it
+        ledger.begin();
+        assert!(ledger.process(dispute(1, 2)).is_ok());
+        assert!(ledger.find_account(ClientId::from(1)).unwrap().locked);
+        assert!(
+            ledger
+                .history(ClientId::from(1))
+                .last()
+                .unwrap()
+                .account
+                .locked
+        );
+        ledger.rollback();
+        assert!(!ledger.find_account(ClientId::from(1)).unwrap().locked);
+
+        assert!(ledger.process(dispute(1, 2)).is_ok());
+        assert!(ledger.find_account(ClientId::from(1)).unwrap().locked);
+        let alerts = ledger.take_alerts();
+        assert_eq!(alerts.len(), 2);
+        assert_eq!(
+            alerts[1],
+            Alert {
+                sequence: 5,
+                rule: "disputes".to_string(),
+                action: Action::Lock,
+                kind: "dispute",
+                client: ClientId::from(1),
+                tx: TxId::from(2),
+                reason: "2 disputes in the last 3 transactions".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_rules_alert_on_locked_account() {
+        let mut ledger = build_ledger();
+        ledger.set_rules(config(
+            r#"{"rules": [{"name": "disputes", "type": "repeated_disputes", "disputes": 1, "within": 5}]}"#,
+            Rules::validate,
+        ));
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        assert!(ledger.find_account(ClientId::from(1)).unwrap().locked);
+
+        // the account is already locked, but the rule triggering is still worth an alert
+        assert!(ledger.process(dispute(1, 2)).is_ok());
+        let alerts: Vec<_> = ledger
+            .take_alerts()
+            .into_iter()
+            .map(|alert| (alert.tx, alert.action))
+            .collect();
+        assert_eq!(
+            alerts,
+            [(TxId::from(1), Action::Lock), (TxId::from(2), Action::Lock)]
+        );
+    }
+
+    #[test]
+    fn test_rules_dispute_ratio_and_negative_balance() {
+        let mut ledger = build_ledger();
+        ledger.set_max_disputes(2);
+        ledger.set_rules(config(
+            r#"{"rules": [
+                {"name": "ratio", "type": "dispute_ratio", "percent": 50, "min_deposits": 2},
+                {"name": "negative", "type": "negative_balance", "action": "flag"}
+            ]}"#,
+            Rules::validate,
+        ));
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        assert!(ledger.take_alerts().is_empty());
+        assert!(ledger.process(resolve(1, 1)).is_ok());
+        assert!(ledger.process(deposit(1, 2, amount(dec!(2)))).is_ok());
+        assert!(ledger.process(withdraw(1, 3, amount(dec!(19)))).is_ok());
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+
+        let alerts: Vec<_> = ledger
+            .take_alerts()
+            .into_iter()
+            .map(|alert| (alert.rule, alert.reason))
+            .collect();
+        assert_eq!(
+            alerts,
+            [
+                ("ratio".to_string(), "2 disputes for 2 deposits".to_string()),
+                (
+                    "negative".to_string(),
+                    "available balance is -17.0000, total is 1.0000".to_string()
+                ),
+            ]
+        );
+        // flagging never locks the account
+        assert!(!ledger.find_account(ClientId::from(1)).unwrap().locked);
+    }
+
+    #[test]
+    fn test_rules_block_withdrawal_after_large_deposit() {
+        let mut ledger = build_ledger();
+        ledger.set_rules(config(
+            r#"{"rules": [{"name": "fresh", "type": "unsettled_deposit", "amount": 100, "settles_after": 2}]}"#,
+            Rules::validate,
+        ));
+        assert!(ledger.process(deposit(1, 2, amount(dec!(100)))).is_ok());
+        assert_eq!(
+            ledger.process(withdraw(1, 3, amount(dec!(1)))),
+            Err(Error::Blocked {
+                rule: "fresh".to_string()
+            })
+        );
+        assert!(ledger.process(deposit(1, 4, amount(dec!(1)))).is_ok());
+        assert_eq!(ledger.take_alerts().len(), 1);
+
+        // two more transactions later the deposit has settled
+        assert!(ledger.process(deposit(1, 5, amount(dec!(1)))).is_ok());
+        assert!(ledger.process(withdraw(1, 6, amount(dec!(1)))).is_ok());
+        assert!(ledger.take_alerts().is_empty());
+    }
+
+    #[test]
+    fn test_rules_validate() {
+        for (json, error) in [
+            (
+                r#"{"rules": [{"name": "a", "type": "negative_balance", "action": "block"}]}"#,
+                "rule 'a' runs after the transaction, so it can't block it",
+            ),
+            (
+                r#"{"rules": [{"name": "a", "type": "unsettled_deposit", "amount": "1", "settles_after": 1, "action": "lock"}]}"#,
+                "rule 'a' can only flag or block withdrawals",
+            ),
+            (
+                r#"{"rules": [{"name": "a", "type": "negative_balance"}, {"name": "a", "type": "negative_balance"}]}"#,
+                "rule 'a' is declared twice",
+            ),
+        ] {
+            let rules: Rules = serde_json::from_str(json).unwrap();
+            assert_eq!(rules.validate(), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_limits_per_transaction() {
+        let mut ledger = build_ledger();
+        ledger.set_limits(config(
+            r#"{"default": {"deposit": "10", "withdrawal": 5}}"#,
+            Limits::validate,
+        ));
+        assert_eq!(
+            ledger.process(deposit(1, 2, amount(dec!(10.0001)))),
+            Err(Error::LimitExceeded {
+                limit: Limit::Deposit(amount(dec!(10))),
+                attempted: amount(dec!(10.0001)),
+            })
+        );
+        assert!(ledger.process(deposit(1, 2, amount(dec!(10)))).is_ok());
+        assert_eq!(
+            ledger.process(withdraw(1, 3, amount(dec!(6)))),
+            Err(Error::LimitExceeded {
+                limit: Limit::Withdrawal(amount(dec!(5))),
+                attempted: amount(dec!(6)),
+            })
+        );
+        assert!(ledger.process(withdraw(1, 3, amount(dec!(5)))).is_ok());
+        // disputes aren't limited, even on deposits over the limit
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        assert_eq!(
+            ledger.find_account(ClientId::from(1)).unwrap().available,
+            amount(dec!(5))
+        );
+    }
+
+    #[test]
+    fn test_limits_rolling_withdrawals() {
+        let mut ledger = build_ledger();
+        ledger.set_limits(config(
+            r#"{"default": {"withdrawal_count": 2, "withdrawal_volume": 10, "within": 3}}"#,
+            Limits::validate,
+        ));
+        assert!(ledger.process(withdraw(1, 2, amount(dec!(4)))).is_ok());
+        assert_eq!(
+            ledger.process(withdraw(1, 3, amount(dec!(7)))),
+            Err(Error::LimitExceeded {
+                limit: Limit::WithdrawalVolume {
+                    volume: amount(dec!(10)),
+                    within: 3,
+                },
+                attempted: amount(dec!(7)),
+            })
+        );
+        assert!(ledger.process(withdraw(1, 3, amount(dec!(6)))).is_ok());
+        assert_eq!(
+            ledger.process(withdraw(1, 4, amount(dec!(1)))),
+            Err(Error::LimitExceeded {
+                limit: Limit::WithdrawalCount {
+                    count: 2,
+                    within: 3,
+                },
+                attempted: amount(dec!(1)),
+            })
+        );
+
+        // a rolled back withdrawal doesn't count towards the limits
+        ledger.begin();
+        assert!(ledger.process(deposit(1, 5, amount(dec!(1)))).is_ok());
+        ledger.rollback();
+        assert!(ledger.process(deposit(1, 5, amount(dec!(1)))).is_ok());
+        assert!(ledger.process(deposit(1, 6, amount(dec!(1)))).is_ok());
+        // only the withdrawal of 6 is among the last 3 transactions now
+        assert!(ledger.process(withdraw(1, 7, amount(dec!(4)))).is_ok());
+    }
+
+    #[test]
+    fn test_limits_tiers() {
+        let mut ledger = build_ledger();
+        let mut limits = config(
+            r#"{
+                "default": {"deposit": 10, "withdrawal": 5},
+                "tiers": {"business": {"withdrawal": 50}}
+            }"#,
+            Limits::validate,
+        );
+        limits.assign(ClientId::from(1), "business").unwrap();
+        assert_eq!(
+            limits.assign(ClientId::from(2), "gold"),
+            Err("client 2 is assigned to unknown tier 'gold'".to_string())
+        );
+        ledger.set_limits(limits);
+
+        assert!(ledger.process(withdraw(1, 2, amount(dec!(18)))).is_ok());
+        assert!(matches!(
+            ledger.process(deposit(1, 3, amount(dec!(11)))),
+            Err(Error::LimitExceeded { .. })
+        ));
+        assert!(ledger.process(deposit(2, 4, amount(dec!(10)))).is_ok());
+        assert!(matches!(
+            ledger.process(withdraw(2, 5, amount(dec!(6)))),
+            Err(Error::LimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_limits_validate() {
+        for (json, error) in [
+            (
+                r#"{"default": {"withdrawal_count": 2}}"#,
+                "the default limits need `within` for their rolling withdrawal limits",
+            ),
+            (
+                r#"{"tiers": {"a": {"within": 2}, "b": {"withdrawal_volume": 2}}}"#,
+                "the b limits need `within` for their rolling withdrawal limits",
+            ),
+        ] {
+            let limits: Limits = serde_json::from_str(json).unwrap();
+            assert_eq!(limits.validate(), Err(error.to_string()));
+        }
+        assert!(serde_json::from_str::<Limits>(r#"{"default": {"deposits": 1}}"#).is_err());
+    }
+
+    /// The pattern, client and tx ids of everything the analysis found
+    fn analyze(ledger: &Ledger, json: &str) -> Vec<(String, ClientId, Vec<TxId>)> {
+        ledger
+            .analyze(&config(json, Patterns::validate))
+            .into_iter()
+            .map(|found| {
+                let txs = found.transactions.iter().map(|tx| tx.tx.clone()).collect();
+                (found.pattern.to_string(), found.client, txs)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_aml_structuring() {
+        let mut ledger = build_ledger();
+        for (tx, value) in [
+            (2, dec!(95)),
+            (3, dec!(10)),
+            (4, dec!(99.9999)),
+            (5, dec!(100)),
+        ] {
+            assert!(ledger.process(deposit(1, tx, amount(value))).is_ok());
+        }
+        // only accepted transactions count
+        assert!(ledger.process(withdraw(1, 6, amount(dec!(1000)))).is_err());
+        assert!(ledger.process(deposit(1, 7, amount(dec!(90)))).is_ok());
+        let json =
+            r#"{"structuring": {"threshold": 100, "margin": 10, "deposits": 3, "within": 5}}"#;
+        assert_eq!(
+            analyze(&ledger, json),
+            [(
+                "structuring".to_string(),
+                ClientId::from(1),
+                vec![TxId::from(2), TxId::from(4), TxId::from(7)]
+            )]
+        );
+
+        // the deposits have to be close enough together
+        let json =
+            r#"{"structuring": {"threshold": 100, "margin": 10, "deposits": 3, "within": 4}}"#;
+        assert!(analyze(&ledger, json).is_empty());
+
+        let found = ledger.analyze(&config(
+            r#"{"structuring": {"threshold": 100, "margin": 10, "deposits": 2, "within": 3}}"#,
+            Patterns::validate,
+        ));
+        assert_eq!(
+            found[0].reason,
+            "2 deposits totalling 194.9999, each under 100.0000"
+        );
+    }
+
+    #[test]
+    fn test_aml_rapid_cycle_and_dormant_account() {
+        let mut ledger = build_ledger();
+        assert!(ledger.process(withdraw(1, 2, amount(dec!(10)))).is_ok());
+        assert!(ledger.process(dispute(1, 1)).is_ok());
+        assert!(ledger.process(resolve(1, 1)).is_ok());
+        assert!(ledger.process(deposit(2, 3, amount(dec!(20)))).is_ok());
+        assert!(ledger.process(withdraw(2, 4, amount(dec!(9)))).is_ok());
+        assert!(ledger.process(deposit(3, 5, amount(dec!(1)))).is_ok());
+        assert!(ledger.process(withdraw(1, 6, amount(dec!(8)))).is_ok());
+
+        let json = r#"{"rapid_cycle": {"amount": 10, "percent": 50, "within": 1}}"#;
+        assert_eq!(
+            analyze(&ledger, json),
+            [(
+                "rapid_cycle".to_string(),
+                ClientId::from(1),
+                vec![TxId::from(1), TxId::from(2)]
+            )]
+        );
+        let json = r#"{"rapid_cycle": {"amount": 10, "percent": 90, "within": 4}}"#;
+        assert_eq!(
+            analyze(&ledger, json),
+            [(
+                "rapid_cycle".to_string(),
+                ClientId::from(1),
+                vec![TxId::from(1), TxId::from(2), TxId::from(6)]
+            )]
+        );
+
+        // client 1 was idle for 3 transactions before withdrawing 8
+        let json = r#"{"dormant_account": {"idle_for": 3, "amount": 5}}"#;
+        assert_eq!(
+            analyze(&ledger, json),
+            [(
+                "dormant_account".to_string(),
+                ClientId::from(1),
+                vec![TxId::from(6)]
+            )]
+        );
+        let json = r#"{"dormant_account": {"idle_for": 4, "amount": 5}}"#;
+        assert!(analyze(&ledger, json).is_empty());
+    }
+
+    #[test]
+    fn test_aml_validate() {
+        for (json, error) in [
+            (
+                r#"{"structuring": {"threshold": 1, "margin": 1, "deposits": 3, "within": 2}}"#,
+                "structuring needs at least one deposit, within at least as many transactions",
+            ),
+            (
+                r#"{"rapid_cycle": {"amount": 1, "percent": 1, "within": 0}}"#,
+                "rapid_cycle needs to look at least one transaction ahead",
+            ),
+        ] {
+            let patterns: Patterns = serde_json::from_str(json).unwrap();
+            assert_eq!(patterns.validate(), Err(error.to_string()));
+        }
+        assert!(serde_json::from_str::<Patterns>(r#"{"smurfing": {}}"#).is_err());
+    }
+
     // Helper functions to build transactions
     // Alternatively we could just create instances of Deposit, Withdrawal, etc
     // but we want to make sure we test the whole flow here, invoking the dynamic dispatch
     // through the Transaction enum
-    fn deposit(client_id: u16, tx_id: u32, amount: Decimal) -> Transaction {
+    fn amount(d: Decimal) -> Amount {
+        Amount::try_from(d).unwrap()
+    }
+
+    fn deposit(client_id: ClientIdRepr, tx_id: TxIdRepr, amount: Amount) -> Transaction {
         Transaction::Deposit(Deposit {
-            client_id,
-            tx_id,
+            client_id: client_id.into(),
+            tx_id: tx_id.into(),
             amount,
         })
     }
 
-    fn withdraw(client_id: u16, tx_id: u32, amount: Decimal) -> Transaction {
+    fn withdraw(client_id: ClientIdRepr, tx_id: TxIdRepr, amount: Amount) -> Transaction {
         Transaction::Withdrawal(Withdrawal {
-            client_id,
-            tx_id,
+            client_id: client_id.into(),
+            tx_id: tx_id.into(),
             amount,
         })
     }
 
-    fn dispute(client_id: u16, tx_id: u32) -> Transaction {
-        Transaction::Dispute(Dispute { client_id, tx_id })
+    fn dispute(client_id: ClientIdRepr, tx_id: TxIdRepr) -> Transaction {
+        Transaction::Dispute(Dispute {
+            client_id: client_id.into(),
+            tx_id: tx_id.into(),
+        })
     }
 
-    fn resolve(client_id: u16, tx_id: u32) -> Transaction {
-        Transaction::Resolve(Resolve { client_id, tx_id })
+    fn resolve(client_id: ClientIdRepr, tx_id: TxIdRepr) -> Transaction {
+        Transaction::Resolve(Resolve {
+            client_id: client_id.into(),
+            tx_id: tx_id.into(),
+        })
     }
 
-    fn chargeback(client_id: u16, tx_id: u32) -> Transaction {
-        Transaction::Chargeback(Chargeback { client_id, tx_id })
+    fn chargeback(client_id: ClientIdRepr, tx_id: TxIdRepr) -> Transaction {
+        Transaction::Chargeback(Chargeback {
+            client_id: client_id.into(),
+            tx_id: tx_id.into(),
+        })
     }
 }
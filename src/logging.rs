@@ -0,0 +1,35 @@
+//! Structured logs of what the ledger and servers are doing, written to stderr
+//! with `tracing`. Logging is off unless `--log-level` or `RUST_LOG` turns it
+//! on, so the regular output on stderr is unaffected by default.
+//!
+//! Every transaction handed to the ledger is processed inside a `transaction`
+//! span carrying its sequence number, kind, client and tx, so every event
+//! logged while processing it can be tied back to it:
+//!
+//! - `info` logs rejected transactions, and requests handled by the servers
+//! - `debug` adds accepted transactions and batches being rolled back
+//! - `trace` adds the change each transaction made to its account
+
+use std::io::{self, IsTerminal};
+
+use tracing_subscriber::EnvFilter;
+
+use crate::cli::{LogArgs, LogFormat};
+use crate::commands::Result;
+
+/// Start writing logs to stderr, as configured on the command line
+pub fn init(args: &LogArgs) -> Result<()> {
+    let filter = match &args.log_level {
+        Some(directives) => EnvFilter::try_new(directives)
+            .map_err(|e| format!("invalid log level '{}': {}", directives, e))?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("off")),
+    };
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr);
+    match args.log_format {
+        LogFormat::Text => logs.with_ansi(io::stderr().is_terminal()).init(),
+        LogFormat::Json => logs.json().with_span_list(false).init(),
+    }
+    Ok(())
+}
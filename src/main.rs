@@ -1,50 +1,49 @@
-use std::process::exit;
-use std::{env, io};
+use std::process::ExitCode;
 
-use crate::ledger::{Ledger, Transaction};
+use clap::Parser;
 
-mod ledger;
+use crate::cli::{Cli, Command};
 
-fn main() {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 2 {
-        print_usage();
-        exit(1);
+mod binary;
+mod cli;
+mod commands;
+mod input;
+mod ledger;
+mod logging;
+mod metrics;
+mod report;
+mod server;
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if let Err(err) = logging::init(&cli.log) {
+        eprintln!("error: {}", err);
+        return ExitCode::from(commands::EXIT_ERROR);
     }
-
-    let mut ledger = Ledger::new();
-
-    let mut rdr = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(&args[1])
-        .expect("a file");
-    for result in rdr.deserialize() {
-        let tx: Transaction = match result {
-            Ok(tx) => tx,
-            Err(e) => {
-                eprintln!("failed to parse csv line: {}", e);
-                continue;
-            }
-        };
-
-        match ledger.process(tx) {
-            Ok(_) => {}
-            Err(err) => {
-                eprintln!("{} failed: {}", tx, err)
-            }
+    let command = match (cli.command, cli.process) {
+        (Some(command), _) => command,
+        (None, Some(args)) => Command::Process(args),
+        (None, None) => unreachable!("clap requires either a subcommand or an input file"),
+    };
+
+    let result = match command {
+        Command::Process(args) => commands::process(args),
+        Command::Validate(args) => commands::validate(args),
+        Command::Replay(args) => commands::replay(args),
+        Command::Diff(args) => commands::diff(args),
+        Command::Convert(args) => commands::convert(args),
+        Command::Serve(args) => commands::serve(args),
+        Command::Listen(args) => commands::listen(args),
+        Command::Send(args) => commands::send(args),
+        Command::Repl(args) => commands::repl(args),
+    };
+
+    match result {
+        Ok(code) => code,
+        Err(err) => {
+            tracing::error!(error = %err, "command failed");
+            eprintln!("error: {}", err);
+            ExitCode::from(commands::EXIT_ERROR)
         }
     }
-    eprintln!("Done processing!");
-
-    let mut wtr = csv::Writer::from_writer(io::stdout());
-    for account in ledger.accounts() {
-        wtr.serialize(account).unwrap();
-    }
-    wtr.flush().unwrap();
-}
-
-fn print_usage() {
-    println!("Usage: transactions-rs <csv file>");
-    println!();
-    println!("The file must be a valid csv with the columns type,client,tx,amount");
 }
@@ -0,0 +1,262 @@
+//! Counters kept while transactions are read and processed, so a run (or a
+//! long running server) can report what it did.
+//!
+//! The counters only cover what was read and what was rejected, everything
+//! about the accounts themselves is read from the ledger when the metrics are
+//! written out, so it's always consistent with the ledger even after batches
+//! are rolled back.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+
+use crate::commands::Result;
+use crate::ledger::{Error, Ledger, Transaction, CURRENCY_DECIMAL_SCALE};
+
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    /// How many accounts the ledger started with, e.g. from a snapshot
+    initial_accounts: usize,
+    rows_read: u64,
+    parse_failures: u64,
+    /// Transactions read, by the name of their type
+    transactions: BTreeMap<&'static str, u64>,
+    /// Transactions rejected, by error code
+    errors: BTreeMap<&'static str, u64>,
+}
+
+/// The values read from the ledger itself when metrics are written
+struct Gauges {
+    accounts: usize,
+    locked: usize,
+    held: Decimal,
+}
+
+impl Metrics {
+    pub fn new(ledger: &Ledger) -> Self {
+        Metrics {
+            started: Instant::now(),
+            initial_accounts: ledger.accounts().count(),
+            rows_read: 0,
+            parse_failures: 0,
+            transactions: BTreeMap::new(),
+            errors: BTreeMap::new(),
+        }
+    }
+
+    /// Count a row that was read, whether or not it could be parsed
+    pub fn read(&mut self, transaction: &std::result::Result<Transaction, String>) {
+        self.rows_read += 1;
+        match transaction {
+            Ok(tx) => *self.transactions.entry(tx.kind()).or_default() += 1,
+            Err(_) => self.parse_failures += 1,
+        }
+    }
+
+    /// Count a transaction that was rejected. A transaction rolled back along
+    /// with its batch counts as rejected, even if it was applied at first
+    pub fn rejected(&mut self, err: &Error) {
+        *self.errors.entry(err.code()).or_default() += 1;
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Rows read per second since the metrics were started
+    fn throughput(&self) -> f64 {
+        let seconds = self.elapsed().as_secs_f64();
+        if seconds > 0.0 {
+            self.rows_read as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    fn gauges(&self, ledger: &Ledger) -> Gauges {
+        let mut gauges = Gauges {
+            accounts: 0,
+            locked: 0,
+            held: Decimal::ZERO,
+        };
+        // summed as units so the total can't overflow the way an `Amount` could
+        let mut held: i128 = 0;
+        for account in ledger.accounts() {
+            gauges.accounts += 1;
+            gauges.locked += account.locked as usize;
+            held += account.held.units() as i128;
+        }
+        gauges.held = Decimal::from_i128_with_scale(held, CURRENCY_DECIMAL_SCALE);
+        gauges
+    }
+
+    /// Write a short human readable summary
+    pub fn write_summary(&self, ledger: &Ledger, out: &mut impl Write) -> Result<()> {
+        let gauges = self.gauges(ledger);
+        let count = |counts: &BTreeMap<&str, u64>| {
+            let counts: Vec<_> = counts
+                .iter()
+                .map(|(name, count)| format!("{} {}", count, name))
+                .collect();
+            if counts.is_empty() {
+                "none".to_string()
+            } else {
+                counts.join(", ")
+            }
+        };
+
+        writeln!(
+            out,
+            "Read {} rows ({} failed to parse) in {:.3}s, {:.0} rows/s",
+            self.rows_read,
+            self.parse_failures,
+            self.elapsed().as_secs_f64(),
+            self.throughput()
+        )?;
+        writeln!(out, "Transactions: {}", count(&self.transactions))?;
+        writeln!(out, "Rejected: {}", count(&self.errors))?;
+        writeln!(
+            out,
+            "Accounts: {} created, {} locked, {} held in disputes",
+            gauges.accounts.saturating_sub(self.initial_accounts),
+            gauges.locked,
+            gauges.held
+        )?;
+        Ok(())
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    pub fn prometheus(&self, ledger: &Ledger) -> String {
+        let gauges = self.gauges(ledger);
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            // writing to a string never fails
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(text, "{}{} {}", name, labels, value);
+            }
+        };
+        let labelled = |label: &str, counts: &BTreeMap<&str, u64>| -> Vec<(String, String)> {
+            counts
+                .iter()
+                .map(|(name, count)| (format!("{{{}=\"{}\"}}", label, name), count.to_string()))
+                .collect()
+        };
+        let value = |value: String| [(String::new(), value)];
+
+        metric(
+            "ledger_rows_read_total",
+            "counter",
+            "Rows read, including ones that couldn't be parsed",
+            &value(self.rows_read.to_string()),
+        );
+        metric(
+            "ledger_parse_failures_total",
+            "counter",
+            "Rows that couldn't be parsed into a transaction",
+            &value(self.parse_failures.to_string()),
+        );
+        metric(
+            "ledger_transactions_total",
+            "counter",
+            "Transactions read, by type",
+            &labelled("type", &self.transactions),
+        );
+        metric(
+            "ledger_rejected_total",
+            "counter",
+            "Transactions rejected, by error code",
+            &labelled("code", &self.errors),
+        );
+        metric(
+            "ledger_accounts_created_total",
+            "counter",
+            "Accounts created since the start",
+            &value(
+                gauges
+                    .accounts
+                    .saturating_sub(self.initial_accounts)
+                    .to_string(),
+            ),
+        );
+        metric(
+            "ledger_accounts",
+            "gauge",
+            "Accounts in the ledger",
+            &value(gauges.accounts.to_string()),
+        );
+        metric(
+            "ledger_accounts_locked",
+            "gauge",
+            "Accounts that are locked",
+            &value(gauges.locked.to_string()),
+        );
+        metric(
+            "ledger_held_funds",
+            "gauge",
+            "Funds held by open disputes, across every account",
+            &value(gauges.held.to_string()),
+        );
+        metric(
+            "ledger_elapsed_seconds",
+            "gauge",
+            "Seconds since the start",
+            &value(format!("{:.3}", self.elapsed().as_secs_f64())),
+        );
+        metric(
+            "ledger_rows_per_second",
+            "gauge",
+            "Rows read per second since the start",
+            &value(format!("{:.3}", self.throughput())),
+        );
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::parse_line;
+    use crate::ledger::Precision;
+
+    #[test]
+    fn test_prometheus() {
+        let mut ledger = Ledger::new();
+        let mut metrics = Metrics::new(&ledger);
+        for line in [
+            "deposit,1,1,10",
+            "deposit,2,2,5",
+            "dispute,1,1,",
+            "withdrawal,2,3,50",
+            "deposit,1",
+        ] {
+            let (tx, _) = parse_line(line, Precision::Reject);
+            metrics.read(&tx);
+            if let Ok(tx) = tx {
+                if let Err(err) = ledger.process(tx) {
+                    metrics.rejected(&err);
+                }
+            }
+        }
+
+        let text = metrics.prometheus(&ledger);
+        for expected in [
+            "ledger_rows_read_total 5\n",
+            "ledger_parse_failures_total 1\n",
+            "ledger_transactions_total{type=\"deposit\"} 2\n",
+            "ledger_transactions_total{type=\"withdrawal\"} 1\n",
+            "ledger_rejected_total{code=\"insufficient_funds\"} 1\n",
+            "ledger_accounts_created_total 2\n",
+            "ledger_accounts_locked 0\n",
+            "ledger_held_funds 10.0000\n",
+            "# TYPE ledger_held_funds gauge\n",
+        ] {
+            assert!(text.contains(expected), "{} not in:\n{}", expected, text);
+        }
+    }
+}
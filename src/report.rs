@@ -0,0 +1,445 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+use crate::cli::ReportFormat;
+use crate::commands::Result;
+use crate::input::Row;
+use crate::ledger::{
+    Alert, Amount, ClientId, Error, Rounding, State, Suspicion, Transaction, TxId,
+};
+
+/// The code used for rows that couldn't be parsed into a transaction at all
+pub const PARSE_ERROR_CODE: &str = "parse_error";
+/// The code used for transactions whose amount was rounded. These aren't
+/// rejected, they're only reported so the rounding can be audited
+pub const AMOUNT_ROUNDED_CODE: &str = "amount_rounded";
+
+/// Writes out every row that was rejected, either because it couldn't be
+/// parsed or because the transaction failed to process, along with any
+/// amounts that had to be rounded
+pub struct RejectionReport {
+    writer: ReportWriter,
+}
+
+enum ReportWriter {
+    Text(Box<dyn Write>),
+    Csv(Box<csv::Writer<Box<dyn Write>>>),
+    Jsonl(Box<dyn Write>),
+}
+
+/// A single record in a machine readable report. Rows that couldn't be parsed
+/// leave the transaction fields empty, and the error specific fields are only
+/// filled in for the errors they apply to.
+#[derive(Debug)]
+struct Rejection<'a> {
+    file: &'a str,
+    line: u64,
+    raw: Cow<'a, str>,
+    kind: Option<&'static str>,
+    client: Option<ClientId>,
+    tx: Option<&'a TxId>,
+    amount: Option<Amount>,
+    code: &'static str,
+    message: String,
+    fields: ErrorFields,
+}
+
+// Every record has the same fields, empty where they don't apply, so that csv
+// reports have the same columns on every row
+impl Serialize for Rejection<'_> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("Rejection", 17)?;
+        s.serialize_field("file", self.file)?;
+        s.serialize_field("line", &self.line)?;
+        s.serialize_field("raw", &self.raw)?;
+        s.serialize_field("type", &self.kind)?;
+        s.serialize_field("client", &self.client)?;
+        s.serialize_field("tx", &self.tx)?;
+        s.serialize_field("amount", &self.amount)?;
+        s.serialize_field("code", self.code)?;
+        s.serialize_field("message", &self.message)?;
+        self.fields.serialize_into(&mut s, false)?;
+        s.end()
+    }
+}
+
+/// The data an error carries, shared by reports and the service so both
+/// describe each error the same way. An error only fills in the fields that
+/// apply to it
+#[derive(Debug, Default)]
+pub struct ErrorFields {
+    pub available: Option<Amount>,
+    pub state: Option<State>,
+    /// The name of the limit that was exceeded, and what it allows
+    pub limit: Option<&'static str>,
+    pub allowed: Option<String>,
+    pub within: Option<usize>,
+    pub attempted: Option<Amount>,
+    /// The name of the rule that blocked the transaction
+    pub rule: Option<String>,
+    /// How many times a transaction can be disputed
+    pub disputes: Option<usize>,
+}
+
+impl ErrorFields {
+    /// Add every field to a struct being serialized, leaving out the ones that
+    /// don't apply if `skip_none` is set
+    fn serialize_into<S: SerializeStruct>(
+        &self,
+        s: &mut S,
+        skip_none: bool,
+    ) -> std::result::Result<(), S::Error> {
+        fn field<S: SerializeStruct, T: Serialize>(
+            s: &mut S,
+            key: &'static str,
+            value: &Option<T>,
+            skip_none: bool,
+        ) -> std::result::Result<(), S::Error> {
+            match value {
+                None if skip_none => s.skip_field(key),
+                _ => s.serialize_field(key, value),
+            }
+        }
+
+        field(s, "available", &self.available, skip_none)?;
+        field(s, "state", &self.state, skip_none)?;
+        field(s, "limit", &self.limit, skip_none)?;
+        field(s, "allowed", &self.allowed, skip_none)?;
+        field(s, "within", &self.within, skip_none)?;
+        field(s, "attempted", &self.attempted, skip_none)?;
+        field(s, "rule", &self.rule, skip_none)?;
+        field(s, "disputes", &self.disputes, skip_none)
+    }
+}
+
+// On their own only the fields that apply are included, e.g. when flattened
+// into the service's error bodies
+impl Serialize for ErrorFields {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("ErrorFields", 8)?;
+        self.serialize_into(&mut s, true)?;
+        s.end()
+    }
+}
+
+impl From<&Error> for ErrorFields {
+    fn from(err: &Error) -> Self {
+        use Error::*;
+
+        match err {
+            InsufficientFunds { available } => ErrorFields {
+                available: Some(*available),
+                ..Default::default()
+            },
+            InvalidTransactionState { got } => ErrorFields {
+                state: Some(*got),
+                ..Default::default()
+            },
+            LimitExceeded { limit, attempted } => ErrorFields {
+                limit: Some(limit.name()),
+                allowed: Some(limit.allowed()),
+                within: limit.within(),
+                attempted: Some(*attempted),
+                ..Default::default()
+            },
+            Blocked { rule } => ErrorFields {
+                rule: Some(rule.clone()),
+                ..Default::default()
+            },
+            DisputeLimitReached { disputes } => ErrorFields {
+                disputes: Some(*disputes),
+                ..Default::default()
+            },
+            TransactionNotFound
+            | AccountNotFound
+            | InvalidAmount
+            | MismatchedClient
+            | AccountLocked
+            | UnknownTransactionType
+            | BalanceOverflow
+            | BatchRolledBack => ErrorFields::default(),
+        }
+    }
+}
+
+impl RejectionReport {
+    pub fn new(out: Box<dyn Write>, format: ReportFormat) -> Self {
+        let writer = match format {
+            ReportFormat::Text => ReportWriter::Text(out),
+            ReportFormat::Csv => ReportWriter::Csv(Box::new(csv::Writer::from_writer(out))),
+            ReportFormat::Jsonl => ReportWriter::Jsonl(out),
+        };
+        RejectionReport { writer }
+    }
+
+    /// Report a row that couldn't be parsed into a transaction
+    pub fn parse_error(&mut self, row: &Row, reason: &str) -> Result<()> {
+        if let ReportWriter::Text(out) = &mut self.writer {
+            writeln!(
+                out,
+                "failed to parse line {} (byte {}) in '{}': {}",
+                row.line, row.byte, row.file, reason
+            )?;
+            return Ok(());
+        }
+
+        self.write(&Rejection {
+            file: &row.file,
+            line: row.line,
+            raw: row.raw_text(),
+            kind: None,
+            client: None,
+            tx: None,
+            amount: None,
+            code: PARSE_ERROR_CODE,
+            message: reason.to_string(),
+            fields: ErrorFields::default(),
+        })
+    }
+
+    /// Report a transaction that was rejected
+    pub fn rejected(&mut self, row: &Row, tx: &Transaction, err: &Error) -> Result<()> {
+        if let ReportWriter::Text(out) = &mut self.writer {
+            writeln!(out, "{} failed: {}", tx, err)?;
+            return Ok(());
+        }
+
+        self.write(&Rejection {
+            file: &row.file,
+            line: row.line,
+            raw: row.raw_text(),
+            kind: Some(tx.kind()),
+            client: Some(tx.client_id()),
+            tx: Some(tx.tx_id()),
+            amount: tx.amount(),
+            code: err.code(),
+            message: err.to_string(),
+            fields: ErrorFields::from(err),
+        })
+    }
+
+    /// Report a transaction whose amount was rounded to fit
+    pub fn rounded(&mut self, row: &Row, tx: &Transaction, rounding: &Rounding) -> Result<()> {
+        if let ReportWriter::Text(out) = &mut self.writer {
+            writeln!(
+                out,
+                "line {} (byte {}) in '{}': {}",
+                row.line, row.byte, row.file, rounding
+            )?;
+            return Ok(());
+        }
+
+        self.write(&Rejection {
+            file: &row.file,
+            line: row.line,
+            raw: row.raw_text(),
+            kind: Some(tx.kind()),
+            client: Some(tx.client_id()),
+            tx: Some(tx.tx_id()),
+            amount: tx.amount(),
+            code: AMOUNT_ROUNDED_CODE,
+            message: rounding.to_string(),
+            fields: ErrorFields::default(),
+        })
+    }
+
+    fn write(&mut self, rejection: &Rejection) -> Result<()> {
+        match &mut self.writer {
+            ReportWriter::Text(_) => unreachable!("text reports are written directly"),
+            ReportWriter::Csv(wtr) => wtr.serialize(rejection)?,
+            ReportWriter::Jsonl(out) => {
+                serde_json::to_writer(&mut *out, rejection)?;
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        match &mut self.writer {
+            ReportWriter::Text(out) | ReportWriter::Jsonl(out) => out.flush()?,
+            ReportWriter::Csv(wtr) => wtr.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// A csv file holding every row that couldn't be used, written out verbatim
+/// along with where it came from and why, so the rows can be fixed and
+/// submitted again
+pub struct Quarantine {
+    wtr: csv::Writer<File>,
+}
+
+#[derive(Debug, Serialize)]
+struct QuarantinedRow<'a> {
+    file: &'a str,
+    line: u64,
+    byte: u64,
+    reason: &'a str,
+    row: Bytes<'a>,
+}
+
+/// Bytes written to a csv field as they are, even if they aren't valid utf-8
+#[derive(Debug)]
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl Quarantine {
+    pub fn create(path: &Path) -> Result<Self> {
+        let wtr = csv::Writer::from_path(path)
+            .map_err(|e| format!("failed to create '{}': {}", path.display(), e))?;
+        Ok(Quarantine { wtr })
+    }
+
+    pub fn add(&mut self, row: &Row, reason: &str) -> Result<()> {
+        self.wtr.serialize(QuarantinedRow {
+            file: &row.file,
+            line: row.line,
+            byte: row.byte,
+            reason,
+            row: Bytes(&row.raw),
+        })?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes out every alert raised by the fraud rules, as a line of text for
+/// people to read or as JSON Lines for an audit trail
+pub enum AlertReport {
+    Text(Box<dyn Write>),
+    Jsonl(Box<dyn Write>),
+}
+
+impl AlertReport {
+    pub fn add(&mut self, alert: &Alert) -> Result<()> {
+        match self {
+            AlertReport::Text(out) => writeln!(
+                out,
+                "rule '{}' ({}) triggered on {} {} of client {}: {}",
+                alert.rule, alert.action, alert.kind, alert.tx, alert.client, alert.reason
+            )?,
+            AlertReport::Jsonl(out) => {
+                serde_json::to_writer(&mut *out, alert)?;
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        match self {
+            AlertReport::Text(out) | AlertReport::Jsonl(out) => out.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// A row of the csv suspicious activity report, one for each transaction
+/// involved in a case
+#[derive(Debug, Serialize)]
+struct SuspiciousTransaction<'a> {
+    case: usize,
+    pattern: String,
+    client: ClientId,
+    sequence: u64,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    tx: &'a TxId,
+    amount: Amount,
+    state: Option<State>,
+    reason: &'a str,
+}
+
+/// Write out the suspicious activity found by the money laundering checks,
+/// numbering each case from 1
+pub fn write_suspicious_activity(
+    mut out: Box<dyn Write>,
+    format: ReportFormat,
+    suspicions: &[Suspicion],
+) -> Result<()> {
+    match format {
+        ReportFormat::Text => {
+            for (case, suspicion) in suspicions.iter().enumerate() {
+                let transactions: Vec<_> = suspicion
+                    .transactions
+                    .iter()
+                    .map(|tx| format!("{} {}", tx.kind, tx.tx))
+                    .collect();
+                writeln!(
+                    out,
+                    "case {}: {} by client {}: {} ({})",
+                    case + 1,
+                    suspicion.pattern,
+                    suspicion.client,
+                    suspicion.reason,
+                    transactions.join(", ")
+                )?;
+            }
+            out.flush()?;
+        }
+        ReportFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(out);
+            for (case, suspicion) in suspicions.iter().enumerate() {
+                for tx in &suspicion.transactions {
+                    wtr.serialize(SuspiciousTransaction {
+                        case: case + 1,
+                        pattern: suspicion.pattern.to_string(),
+                        client: suspicion.client,
+                        sequence: tx.sequence,
+                        kind: tx.kind,
+                        tx: &tx.tx,
+                        amount: tx.amount,
+                        state: tx.state,
+                        reason: &suspicion.reason,
+                    })?;
+                }
+            }
+            wtr.flush()?;
+        }
+        ReportFormat::Jsonl => {
+            #[derive(Serialize)]
+            struct Case<'a> {
+                case: usize,
+                #[serde(flatten)]
+                suspicion: &'a Suspicion,
+            }
+            for (case, suspicion) in suspicions.iter().enumerate() {
+                serde_json::to_writer(
+                    &mut out,
+                    &Case {
+                        case: case + 1,
+                        suspicion,
+                    },
+                )?;
+                writeln!(out)?;
+            }
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,432 @@
+//! A small JSON HTTP service around a ledger:
+//!
+//! - `POST /transactions` applies the transactions in the body, as a csv with a
+//!   header row, JSON Lines, or a JSON array of objects
+//! - `GET /accounts` lists every account, `GET /accounts/{client}` fetches one
+//! - `GET /transactions/{tx}` fetches a logged transaction and its state
+//! - `GET /metrics` returns metrics in the Prometheus text format
+//!
+//! Requests are handled one at a time, in the order they arrive, so the ledger
+//! never needs to be locked.
+
+use std::borrow::Cow;
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::info;
+
+use crate::commands::Result;
+use crate::input::{CsvReader, JsonlReader, Row};
+use crate::ledger::{Amount, ClientId, ClientIdRepr, Error, Ledger, Precision, TxId};
+use crate::metrics::Metrics;
+use crate::report::{ErrorFields, PARSE_ERROR_CODE};
+
+use super::{submit, Failure, Outcome};
+
+/// The name rows read from a request body are reported against
+const REQUEST_NAME: &str = "<request>";
+
+/// The largest request body taken, in bytes
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+
+/// Serve requests against `ledger` on `addr` until the process is stopped
+pub fn serve(mut ledger: Ledger, addr: SocketAddr, precision: Precision) -> Result<()> {
+    let server = Server::http(addr).map_err(|e| format!("failed to listen on {}: {}", addr, e))?;
+    if let Some(addr) = server.server_addr().to_ip() {
+        eprintln!("Listening on http://{}", addr);
+    }
+    let mut metrics = Metrics::new(&ledger);
+    for mut request in server.incoming_requests() {
+        let response = handle(&mut ledger, &mut metrics, &mut request, precision);
+        info!(
+            method = %request.method(),
+            url = request.url(),
+            status = response.status_code().0,
+            "handled request"
+        );
+        if let Err(e) = request.respond(response) {
+            eprintln!("failed to send response: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Serves nothing but `GET /metrics`, for modes that don't speak HTTP
+/// themselves
+pub struct MetricsServer(Server);
+
+impl MetricsServer {
+    pub fn bind(addr: SocketAddr) -> Result<Self> {
+        let server =
+            Server::http(addr).map_err(|e| format!("failed to listen on {}: {}", addr, e))?;
+        Ok(MetricsServer(server))
+    }
+
+    /// The address actually listened on, e.g. with the port picked by the OS
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.0.server_addr().to_ip()
+    }
+
+    /// Answer requests until the process is stopped, with `render` producing
+    /// the metrics for each one. `None` means they're no longer available
+    pub fn run(self, mut render: impl FnMut() -> Option<String>) {
+        for request in self.0.incoming_requests() {
+            let path = request.url().split('?').next().unwrap_or_default();
+            let response = match (path, request.method()) {
+                ("/metrics", Method::Get) => match render() {
+                    Some(text) => prometheus(text),
+                    None => error(503, "unavailable", "the ledger has stopped".to_string()),
+                },
+                ("/metrics", _) => error(
+                    405,
+                    "method_not_allowed",
+                    format!("{} is not allowed on {}", request.method(), path),
+                ),
+                _ => error(404, "not_found", format!("no such endpoint {}", path)),
+            };
+            if let Err(e) = request.respond(response) {
+                eprintln!("failed to send response: {}", e);
+            }
+        }
+    }
+}
+
+type JsonResponse = Response<Cursor<Vec<u8>>>;
+
+fn handle(
+    ledger: &mut Ledger,
+    metrics: &mut Metrics,
+    request: &mut Request,
+    precision: Precision,
+) -> JsonResponse {
+    let url = request.url().to_string();
+    let path = url.split('?').next().unwrap_or_default();
+    let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+    let method = request.method().clone();
+
+    match (segments.as_slice(), method) {
+        (["transactions"], Method::Post) => post_transactions(ledger, metrics, request, precision),
+        (["metrics"], Method::Get) => prometheus(metrics.prometheus(ledger)),
+        (["accounts"], Method::Get) => json(200, &ledger.accounts().collect::<Vec<_>>()),
+        (["accounts", client], Method::Get) => match client.parse::<ClientIdRepr>() {
+            Ok(client) => match ledger.account(ClientId::from(client)) {
+                Some(account) => json(200, account),
+                None => rejected(&Error::AccountNotFound),
+            },
+            Err(_) => error(
+                400,
+                "bad_request",
+                format!("invalid client id '{}'", client),
+            ),
+        },
+        (["transactions", tx], Method::Get) => match percent_decode(tx) {
+            Some(tx) => match ledger.transaction(&TxId::new(&tx)) {
+                Some(lt) => json(200, lt),
+                None => rejected(&Error::TransactionNotFound),
+            },
+            None => error(
+                400,
+                "bad_request",
+                format!("invalid transaction id '{}'", tx),
+            ),
+        },
+        (
+            ["transactions"] | ["accounts"] | ["accounts", _] | ["transactions", _] | ["metrics"],
+            _,
+        ) => error(
+            405,
+            "method_not_allowed",
+            format!("{} is not allowed on {}", request.method(), path),
+        ),
+        _ => error(404, "not_found", format!("no such endpoint {}", path)),
+    }
+}
+
+fn post_transactions(
+    ledger: &mut Ledger,
+    metrics: &mut Metrics,
+    request: &mut Request,
+    precision: Precision,
+) -> JsonResponse {
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .map(|h| h.value.as_str().to_string());
+    let too_large = || {
+        error(
+            413,
+            "payload_too_large",
+            format!("the body is larger than the {} bytes allowed", MAX_BODY_LEN),
+        )
+    };
+    if request.body_length().is_some_and(|len| len > MAX_BODY_LEN) {
+        return too_large();
+    }
+    let mut body = Vec::new();
+    // read one byte past the limit to tell a body that's too large (e.g. one
+    // sent chunked, without a length) from one that's exactly the limit
+    if let Err(e) = request
+        .as_reader()
+        .take(MAX_BODY_LEN as u64 + 1)
+        .read_to_end(&mut body)
+    {
+        return error(400, "bad_request", format!("failed to read body: {}", e));
+    }
+    if body.len() > MAX_BODY_LEN {
+        return too_large();
+    }
+
+    let rows = match BodyFormat::pick(content_type.as_deref(), &body) {
+        Some(BodyFormat::Csv) => CsvReader::new(body.as_slice(), REQUEST_NAME, precision)
+            .and_then(|rdr| rdr.collect::<Result<Vec<_>>>())
+            .map_err(|e| e.to_string()),
+        Some(BodyFormat::Json) => json_lines(&body).and_then(|lines| {
+            JsonlReader::new(lines.as_slice(), REQUEST_NAME, precision)
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| e.to_string())
+        }),
+        None => {
+            return error(
+                415,
+                "unsupported_media_type",
+                format!(
+                    "unsupported content type '{}', expected csv or JSON",
+                    content_type.unwrap_or_default()
+                ),
+            )
+        }
+    };
+    let rows: Vec<Row> = match rows {
+        Ok(rows) => rows,
+        Err(reason) => return error(400, "bad_request", reason),
+    };
+
+    let outcomes = submit(ledger, rows);
+    for outcome in &outcomes {
+        metrics.read(&outcome.row.transaction);
+        if let Err(Failure::Rejected(err)) = &outcome.result {
+            metrics.rejected(err);
+        }
+    }
+    let results: Vec<_> = outcomes.iter().map(Submitted::from).collect();
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    // A single transaction gets the status of its own result, otherwise some
+    // of them may have failed and each result has its own status
+    let status = match results.as_slice() {
+        [result] => result.status,
+        _ if failed > 0 => 207,
+        _ => 200,
+    };
+    json(
+        status,
+        &SubmitResponse {
+            processed: results.len() - failed,
+            failed,
+            results,
+        },
+    )
+}
+
+/// The formats a body of transactions can be posted in
+enum BodyFormat {
+    Csv,
+    /// A JSON array of objects, or JSON Lines
+    Json,
+}
+
+impl BodyFormat {
+    /// Pick the format from the content type, or the body itself if there
+    /// isn't one. `None` if the content type isn't one we take
+    fn pick(content_type: Option<&str>, body: &[u8]) -> Option<Self> {
+        let mime = match content_type {
+            Some(content_type) => content_type.split(';').next().unwrap_or_default().trim(),
+            None => match body.iter().find(|b| !b.is_ascii_whitespace()) {
+                Some(b'{' | b'[') => return Some(BodyFormat::Json),
+                _ => return Some(BodyFormat::Csv),
+            },
+        };
+        match mime {
+            "text/csv" | "text/plain" => Some(BodyFormat::Csv),
+            "application/json" | "application/jsonl" | "application/x-ndjson" => {
+                Some(BodyFormat::Json)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Decode the `%XX` escapes in a path segment. `None` if an escape is
+/// malformed or the result isn't valid UTF-8
+fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = tail
+                .get(..2)
+                .filter(|h| h.iter().all(u8::is_ascii_hexdigit))?;
+            let hex = std::str::from_utf8(hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Turn a JSON body into JSON Lines, so it's read exactly like a JSON Lines
+/// input. A body that is already JSON Lines (or a single object) is left as is
+fn json_lines(body: &[u8]) -> std::result::Result<Vec<u8>, String> {
+    if body.iter().find(|b| !b.is_ascii_whitespace()) != Some(&b'[') {
+        return Ok(body.to_vec());
+    }
+    let objects: Vec<serde_json::Value> =
+        serde_json::from_slice(body).map_err(|e| format!("invalid JSON: {}", e))?;
+    let mut lines = Vec::new();
+    for object in objects {
+        serde_json::to_writer(&mut lines, &object).map_err(|e| e.to_string())?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+/// The HTTP status for each way a transaction can be rejected
+fn status(err: &Error) -> u16 {
+    use Error::*;
+
+    match err {
+        Blocked { .. } | LimitExceeded { .. } => 403,
+        TransactionNotFound | AccountNotFound => 404,
+        InsufficientFunds { .. }
+        | InvalidTransactionState { .. }
+        | DisputeLimitReached { .. }
+        | AccountLocked
+        | BatchRolledBack => 409,
+        InvalidAmount | MismatchedClient | UnknownTransactionType | BalanceOverflow => 422,
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SubmitResponse<'a> {
+    processed: usize,
+    failed: usize,
+    results: Vec<Submitted<'a>>,
+}
+
+/// The result of a single submitted transaction
+#[derive(Debug, Serialize)]
+struct Submitted<'a> {
+    line: u64,
+    #[serde(rename = "type")]
+    kind: Option<&'static str>,
+    client: Option<ClientId>,
+    tx: Option<&'a TxId>,
+    amount: Option<Amount>,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorBody<'a>>,
+    /// How the amount was rounded, if it had too many decimal places
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rounded: Option<String>,
+}
+
+impl<'a> From<&'a Outcome> for Submitted<'a> {
+    fn from(outcome: &'a Outcome) -> Self {
+        let tx = outcome.row.transaction.as_ref().ok();
+        let (status, error) = match &outcome.result {
+            Ok(()) => (200, None),
+            Err(Failure::Parse(reason)) => (
+                400,
+                Some(ErrorBody {
+                    code: PARSE_ERROR_CODE,
+                    message: reason.clone(),
+                    fields: ErrorFields::default(),
+                    raw: Some(outcome.row.raw_text()),
+                }),
+            ),
+            Err(Failure::Rejected(err)) => (status(err), Some(ErrorBody::from(err))),
+        };
+        Submitted {
+            line: outcome.row.line,
+            kind: tx.map(|tx| tx.kind()),
+            client: tx.map(|tx| tx.client_id()),
+            tx: tx.map(|tx| tx.tx_id()),
+            amount: tx.and_then(|tx| tx.amount()),
+            status,
+            error,
+            rounded: outcome.row.rounding.as_ref().map(ToString::to_string),
+        }
+    }
+}
+
+/// A structured error, with the same codes used in failed transaction reports.
+/// The error specific fields are only included for the errors they apply to
+#[derive(Debug, Serialize)]
+struct ErrorBody<'a> {
+    code: &'static str,
+    message: String,
+    #[serde(flatten)]
+    fields: ErrorFields,
+    /// The row that couldn't be parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<Cow<'a, str>>,
+}
+
+impl From<&Error> for ErrorBody<'_> {
+    fn from(err: &Error) -> Self {
+        ErrorBody {
+            code: err.code(),
+            message: err.to_string(),
+            fields: ErrorFields::from(err),
+            raw: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse<'a> {
+    error: ErrorBody<'a>,
+}
+
+fn json(status: u16, body: &impl Serialize) -> JsonResponse {
+    let body = serde_json::to_vec(body).expect("responses always serialize");
+    let content_type = Header::from_bytes("Content-Type", "application/json")
+        .expect("the content type header is valid");
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn prometheus(text: String) -> JsonResponse {
+    let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+        .expect("the content type header is valid");
+    Response::from_data(text.into_bytes()).with_header(content_type)
+}
+
+fn rejected(err: &Error) -> JsonResponse {
+    json(
+        status(err),
+        &ErrorResponse {
+            error: ErrorBody::from(err),
+        },
+    )
+}
+
+fn error(status: u16, code: &'static str, message: String) -> JsonResponse {
+    json(
+        status,
+        &ErrorResponse {
+            error: ErrorBody {
+                code,
+                message,
+                fields: ErrorFields::default(),
+                raw: None,
+            },
+        },
+    )
+}
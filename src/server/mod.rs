@@ -0,0 +1,81 @@
+//! Long running modes that keep a single ledger in memory and apply
+//! transactions to it as they are submitted
+
+use std::io;
+
+use crate::commands::Batch;
+use crate::input::Row;
+use crate::ledger::{Error, Ledger};
+use crate::report::AlertReport;
+
+pub use http::{serve, MetricsServer};
+pub use socket::{Ack, Client, Endpoint, Listener};
+
+mod http;
+mod socket;
+
+/// The result of applying a single submitted row to the ledger
+#[derive(Debug)]
+pub struct Outcome {
+    pub row: Row,
+    pub result: Result<(), Failure>,
+}
+
+/// Why a submitted row wasn't applied
+#[derive(Debug)]
+pub enum Failure {
+    /// The row couldn't be parsed into a transaction
+    Parse(String),
+    /// The transaction was rejected by the ledger
+    Rejected(Error),
+}
+
+/// Apply rows to the ledger in order, returning the outcome of each one. Rows
+/// sharing a batch id are applied atomically, the same as with `process`
+pub fn submit(ledger: &mut Ledger, rows: impl IntoIterator<Item = Row>) -> Vec<Outcome> {
+    let mut outcomes: Vec<Outcome> = Vec::new();
+    // the index of each outcome applied as part of the open batch
+    let mut batch: Option<Batch<usize>> = None;
+    for row in rows {
+        if !row.continues_batch(batch.as_ref().map(|b| b.id.as_str())) {
+            if let Some(batch) = batch.take() {
+                batch.finish(ledger);
+            }
+            batch = row.batch.clone().map(|id| Batch::begin(id, ledger));
+        }
+
+        let result = match (&row.transaction, &batch) {
+            (Err(reason), _) => Err(Failure::Parse(reason.clone())),
+            (Ok(_), Some(Batch { failed: true, .. })) => {
+                Err(Failure::Rejected(Error::BatchRolledBack))
+            }
+            (Ok(tx), _) => ledger.process(tx.clone()).map_err(Failure::Rejected),
+        };
+        report_alerts(ledger);
+        if let Some(batch) = &mut batch {
+            match result {
+                Ok(()) => batch.applied.push(outcomes.len()),
+                Err(_) => {
+                    for applied in batch.rollback(ledger) {
+                        outcomes[applied].result = Err(Failure::Rejected(Error::BatchRolledBack));
+                    }
+                }
+            }
+        }
+        outcomes.push(Outcome { row, result });
+    }
+    if let Some(batch) = batch.take() {
+        batch.finish(ledger);
+    }
+    outcomes
+}
+
+/// Write the alerts the fraud rules raised to stderr, the same as `process`
+/// does by default
+pub fn report_alerts(ledger: &mut Ledger) {
+    let mut alerts = AlertReport::Text(Box::new(io::stderr()));
+    for alert in ledger.take_alerts() {
+        // not being able to write to stderr is no reason to stop taking transactions
+        let _ = alerts.add(&alert);
+    }
+}
@@ -0,0 +1,563 @@
+//! A line oriented protocol for streaming transactions over a TCP or Unix
+//! domain socket.
+//!
+//! Clients send one csv row per line, with the columns type,client,tx,amount
+//! and no header. Every row is answered with one line, in the order the rows
+//! were sent: `ok` if the transaction went through, otherwise `error` followed
+//! by the error code and message, e.g. `error insufficient_funds insufficient
+//! funds, available: '1.0000'`. A transaction whose amount was rounded is
+//! answered with `ok amount_rounded` and how it was rounded instead.
+//!
+//! A row can have a fifth column with a batch id. Consecutive rows with the
+//! same batch id are applied as a whole or not at all, the same as in files, so
+//! they are only answered once the batch ends: on a row outside of it, a blank
+//! line, or the connection closing. Blank lines are otherwise ignored.
+//!
+//! Each connection is read on its own thread, but every transaction is handed
+//! to a single thread that owns the ledger, so they are applied one at a time
+//! in the order they arrive. Requests for metrics are handed to the same thread,
+//! since they read the ledger too.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use tracing::{debug, info};
+
+use crate::commands::Result;
+use crate::input::{parse_batch_line, Row};
+use crate::ledger::{Ledger, Precision, Rounding, Transaction};
+use crate::metrics::Metrics;
+use crate::report::{AMOUNT_ROUNDED_CODE, PARSE_ERROR_CODE};
+
+use super::{submit, Failure, MetricsServer, Outcome};
+
+/// The name rows read from a connection are reported against
+const SOCKET_NAME: &str = "<socket>";
+
+/// Where a listener accepts connections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// The answer to a single row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ack {
+    Ok,
+    /// The transaction went through, with its amount rounded as described
+    Rounded(String),
+    Error {
+        code: String,
+        message: String,
+    },
+}
+
+impl Display for Ack {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Ack::Ok => write!(f, "ok"),
+            Ack::Rounded(rounding) => write!(f, "ok {} {}", AMOUNT_ROUNDED_CODE, rounding),
+            Ack::Error { code, message } => write!(f, "error {} {}", code, message),
+        }
+    }
+}
+
+impl From<&Outcome> for Ack {
+    fn from(outcome: &Outcome) -> Self {
+        match &outcome.result {
+            Ok(()) => match &outcome.row.rounding {
+                Some(rounding) => Ack::Rounded(rounding.to_string()),
+                None => Ack::Ok,
+            },
+            Err(Failure::Parse(reason)) => Ack::Error {
+                code: PARSE_ERROR_CODE.to_string(),
+                message: reason.clone(),
+            },
+            Err(Failure::Rejected(err)) => Ack::Error {
+                code: err.code().to_string(),
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+impl FromStr for Ack {
+    type Err = String;
+
+    fn from_str(line: &str) -> std::result::Result<Self, String> {
+        match line.split_once(' ') {
+            None if line == "ok" => Ok(Ack::Ok),
+            Some(("ok", rest)) => match rest.split_once(' ') {
+                Some((AMOUNT_ROUNDED_CODE, rounding)) => Ok(Ack::Rounded(rounding.to_string())),
+                _ => Err(format!("invalid acknowledgement '{}'", line)),
+            },
+            Some(("error", rest)) => {
+                let (code, message) = rest.split_once(' ').unwrap_or((rest, ""));
+                Ok(Ack::Error {
+                    code: code.to_string(),
+                    message: message.to_string(),
+                })
+            }
+            _ => Err(format!("invalid acknowledgement '{}'", line)),
+        }
+    }
+}
+
+/// A row as parsed by a connection, which is turned into a [`Row`] on the
+/// ledger thread
+struct Line {
+    raw: String,
+    transaction: std::result::Result<Transaction, String>,
+    rounding: Option<Rounding>,
+    batch: Option<String>,
+}
+
+/// What's handed to the ledger thread
+enum Request {
+    /// A row, or every row in a batch, along with where to send their acks
+    Submission {
+        lines: Vec<Line>,
+        reply: Sender<Vec<Ack>>,
+    },
+    /// Render the metrics, in the Prometheus text format
+    Metrics(Sender<String>),
+}
+
+/// A bound socket, ready to accept connections
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub fn bind(endpoint: &Endpoint) -> Result<Self> {
+        let failed = |e: io::Error| format!("failed to listen on {}: {}", endpoint, e);
+        match endpoint {
+            Endpoint::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).map_err(failed)?)),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Ok(Listener::Unix(
+                UnixListener::bind(path).map_err(failed)?,
+                path.clone(),
+            )),
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err("unix sockets aren't supported on this platform".into()),
+        }
+    }
+
+    /// The endpoint actually listened on, e.g. with the port picked by the OS
+    pub fn endpoint(&self) -> io::Result<Endpoint> {
+        match self {
+            Listener::Tcp(listener) => Ok(Endpoint::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(Endpoint::Unix(path.clone())),
+        }
+    }
+
+    /// Accept connections and apply the transactions sent over them to
+    /// `ledger`, until the process is stopped. Metrics are served by
+    /// `metrics`, if given
+    pub fn run(self, mut ledger: Ledger, precision: Precision, metrics: Option<MetricsServer>) {
+        let (queue, requests) = mpsc::channel::<Request>();
+        if let Some(server) = metrics {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                server.run(|| {
+                    let (reply, text) = mpsc::channel();
+                    queue.send(Request::Metrics(reply)).ok()?;
+                    text.recv().ok()
+                })
+            });
+        }
+        thread::spawn(move || self.accept(queue, precision));
+
+        let mut metrics = Metrics::new(&ledger);
+        let file: Rc<str> = Rc::from(SOCKET_NAME);
+        for request in requests {
+            let (lines, reply) = match request {
+                Request::Submission { lines, reply } => (lines, reply),
+                Request::Metrics(reply) => {
+                    let _ = reply.send(metrics.prometheus(&ledger));
+                    continue;
+                }
+            };
+            let rows = lines.into_iter().map(|line| Row {
+                file: file.clone(),
+                line: 0,
+                byte: 0,
+                raw: line.raw.into_bytes(),
+                batch: line.batch,
+                starts_batch: false,
+                transaction: line.transaction,
+                rounding: line.rounding,
+            });
+            let outcomes = submit(&mut ledger, rows);
+            for outcome in &outcomes {
+                metrics.read(&outcome.row.transaction);
+                if let Err(Failure::Rejected(err)) = &outcome.result {
+                    metrics.rejected(err);
+                }
+            }
+            // the connection may have gone away, which only matters to it
+            let _ = reply.send(outcomes.iter().map(Ack::from).collect());
+        }
+    }
+
+    fn accept(self, queue: Sender<Request>, precision: Precision) {
+        let connect = |rdr: io::Result<Box<dyn Read + Send>>, wtr: Box<dyn Write + Send>| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                debug!("connection opened");
+                match rdr.and_then(|rdr| connection(rdr, wtr, queue, precision)) {
+                    Ok(()) => debug!("connection closed"),
+                    Err(e) => {
+                        info!(error = %e, "connection failed");
+                        eprintln!("connection failed: {}", e);
+                    }
+                }
+            });
+        };
+        match self {
+            Listener::Tcp(listener) => {
+                for stream in listener.incoming() {
+                    // every ack is a tiny write that the client is waiting on,
+                    // so it shouldn't be held back to batch it with others
+                    let stream = stream.and_then(|s| s.set_nodelay(true).map(|()| s));
+                    match stream {
+                        Ok(stream) => connect(
+                            stream.try_clone().map(|s| Box::new(s) as _),
+                            Box::new(stream),
+                        ),
+                        Err(e) => eprintln!("failed to accept a connection: {}", e),
+                    }
+                }
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => connect(
+                            stream.try_clone().map(|s| Box::new(s) as _),
+                            Box::new(stream),
+                        ),
+                        Err(e) => eprintln!("failed to accept a connection: {}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Read rows from a single connection until it's closed, answering each one
+/// once the ledger thread has applied it
+fn connection(
+    rdr: Box<dyn Read + Send>,
+    mut wtr: Box<dyn Write + Send>,
+    queue: Sender<Request>,
+    precision: Precision,
+) -> io::Result<()> {
+    let (reply, acks) = mpsc::channel();
+    let mut submit = |lines: Vec<Line>| -> io::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let stopped = || io::Error::new(io::ErrorKind::BrokenPipe, "the ledger has stopped");
+        let submission = Request::Submission {
+            lines,
+            reply: reply.clone(),
+        };
+        queue.send(submission).map_err(|_| stopped())?;
+        for ack in acks.recv().map_err(|_| stopped())? {
+            writeln!(wtr, "{}", ack)?;
+        }
+        wtr.flush()
+    };
+
+    // the rows of the open batch, which are held back until it ends
+    let mut batch: Vec<Line> = Vec::new();
+    for raw in BufReader::new(rdr).lines() {
+        let raw = raw?;
+        if raw.trim().is_empty() {
+            submit(std::mem::take(&mut batch))?;
+            continue;
+        }
+        let (transaction, rounding, id) = parse_batch_line(&raw, precision);
+        let line = Line {
+            raw,
+            transaction,
+            rounding,
+            batch: id,
+        };
+        if batch.first().map(|l| &l.batch) != Some(&line.batch) {
+            submit(std::mem::take(&mut batch))?;
+        }
+        match line.batch {
+            Some(_) => batch.push(line),
+            None => submit(vec![line])?,
+        }
+    }
+    submit(batch)
+}
+
+/// Sends rows to a listener, one at a time, waiting for each to be answered
+pub struct Client {
+    rdr: BufReader<Box<dyn Read>>,
+    wtr: Box<dyn Write>,
+    line: String,
+}
+
+impl Client {
+    pub fn connect(endpoint: &Endpoint) -> io::Result<Self> {
+        let (rdr, wtr): (Box<dyn Read>, Box<dyn Write>) = match endpoint {
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                (Box::new(stream.try_clone()?), Box::new(stream))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = UnixStream::connect(path)?;
+                (Box::new(stream.try_clone()?), Box::new(stream))
+            }
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "unix sockets aren't supported on this platform",
+                ))
+            }
+        };
+        Ok(Client {
+            rdr: BufReader::new(rdr),
+            wtr,
+            line: String::new(),
+        })
+    }
+
+    /// Send a single csv row, returning how it was answered
+    pub fn send(&mut self, row: &str) -> io::Result<Ack> {
+        writeln!(self.wtr, "{}", row)?;
+        self.wtr.flush()?;
+        self.read_ack()
+    }
+
+    /// Send a transaction, written as a csv row
+    pub fn send_transaction(&mut self, tx: &Transaction) -> io::Result<Ack> {
+        self.send(&row(tx, None)?)
+    }
+
+    /// Send the transactions making up a batch, which are applied as a whole
+    /// or not at all, returning how each one was answered
+    pub fn send_batch(&mut self, batch: &str, txs: &[Transaction]) -> io::Result<Vec<Ack>> {
+        for tx in txs {
+            writeln!(self.wtr, "{}", row(tx, Some(batch))?)?;
+        }
+        // a blank line ends the batch, so it's applied and answered
+        writeln!(self.wtr)?;
+        self.wtr.flush()?;
+        txs.iter().map(|_| self.read_ack()).collect()
+    }
+
+    fn read_ack(&mut self) -> io::Result<Ack> {
+        self.line.clear();
+        if self.rdr.read_line(&mut self.line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the connection was closed",
+            ));
+        }
+        self.line
+            .trim_end()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// A transaction written as a csv row, along with its batch id if it has one.
+/// Ids are quoted if they need to be
+fn row(tx: &Transaction, batch: Option<&str>) -> io::Result<String> {
+    let amount = tx.amount().map(|a| a.to_string()).unwrap_or_default();
+    let (client, id) = (tx.client_id().to_string(), tx.tx_id().to_string());
+    let mut fields = vec![tx.kind(), &client, &id, &amount];
+    fields.extend(batch);
+    let mut row = Vec::new();
+    let mut wtr = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(&mut row);
+    wtr.write_record(&fields)?;
+    wtr.flush()?;
+    drop(wtr);
+    Ok(String::from_utf8_lossy(&row).trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::parse_line;
+    use crate::ledger::Error;
+
+    fn start(endpoint: &Endpoint) -> Endpoint {
+        start_with(endpoint, Precision::Reject)
+    }
+
+    fn start_with(endpoint: &Endpoint, precision: Precision) -> Endpoint {
+        let listener = Listener::bind(endpoint).unwrap();
+        let endpoint = listener.endpoint().unwrap();
+        thread::spawn(move || listener.run(Ledger::new(), precision, None));
+        endpoint
+    }
+
+    fn tcp() -> Endpoint {
+        Endpoint::Tcp("127.0.0.1:0".parse().unwrap())
+    }
+
+    fn error(code: &str, message: &str) -> Ack {
+        Ack::Error {
+            code: code.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_ack_round_trip() {
+        for ack in [
+            Ack::Ok,
+            Ack::Rounded("amount 1.00005 was rounded to 1.0000 (half-even)".to_string()),
+            error("account_locked", "the account is locked"),
+            error("parse_error", ""),
+        ] {
+            assert_eq!(ack.to_string().parse::<Ack>(), Ok(ack));
+        }
+        assert!("okay".parse::<Ack>().is_err());
+        assert!("ok fine".parse::<Ack>().is_err());
+    }
+
+    #[test]
+    fn test_tcp() {
+        let endpoint = start(&tcp());
+        let mut client = Client::connect(&endpoint).unwrap();
+        assert_eq!(client.send("deposit,1,1,1.5").unwrap(), Ack::Ok);
+        assert_eq!(
+            client.send("withdrawal, 1, 2, 2").unwrap(),
+            error(
+                "insufficient_funds",
+                "insufficient funds, available: '1.5000'"
+            )
+        );
+        // the amount is left empty rather than off, the same as in a csv input
+        assert_eq!(
+            client.send("dispute,1,1").unwrap(),
+            error("parse_error", "found 3 fields, expected 4")
+        );
+        assert_eq!(client.send("dispute,1,1,").unwrap(), Ack::Ok);
+        assert_eq!(
+            client.send("deposit,1,3,1.00001").unwrap(),
+            error(
+                "parse_error",
+                "amount has 5 decimal places, at most 4 are allowed"
+            )
+        );
+        assert_eq!(
+            client.send("deposit,1,3,1,,5").unwrap(),
+            error("parse_error", "found 6 fields, expected 5")
+        );
+
+        // a second connection shares the same ledger
+        let mut other = Client::connect(&endpoint).unwrap();
+        assert_eq!(
+            other.send("dispute,1,1,").unwrap(),
+            error(
+                "invalid_transaction_state",
+                "transaction is in the incorrect state: 'Disputed'"
+            )
+        );
+    }
+
+    #[test]
+    fn test_rounded() {
+        let endpoint = start_with(&tcp(), Precision::HalfEven);
+        let mut client = Client::connect(&endpoint).unwrap();
+        assert_eq!(
+            client.send("deposit,1,1,1.00005").unwrap(),
+            Ack::Rounded("amount 1.00005 was rounded to 1.0000 (half-even)".to_string())
+        );
+        assert_eq!(
+            client.send("withdrawal,1,2,1.00001").unwrap(),
+            Ack::Rounded("amount 1.00001 was rounded to 1.0000 (half-even)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_batches() {
+        let endpoint = start(&tcp());
+        let mut client = Client::connect(&endpoint).unwrap();
+        let tx = |line: &str| parse_line(line, Precision::Reject).0.unwrap();
+        assert_eq!(client.send("deposit,1,1,5").unwrap(), Ack::Ok);
+
+        // the failed withdrawal rolls back the deposit before it
+        let acks = client
+            .send_batch("a,1", &[tx("deposit,1,2,1"), tx("withdrawal,1,3,100")])
+            .unwrap();
+        assert_eq!(
+            acks,
+            [
+                error("batch_rolled_back", &Error::BatchRolledBack.to_string()),
+                error(
+                    "insufficient_funds",
+                    "insufficient funds, available: '6.0000'"
+                ),
+            ]
+        );
+        assert_eq!(
+            client.send("withdrawal,1,4,6").unwrap(),
+            error(
+                "insufficient_funds",
+                "insufficient funds, available: '5.0000'"
+            )
+        );
+
+        let acks = client
+            .send_batch("b", &[tx("deposit,1,5,1"), tx("withdrawal,1,6,6")])
+            .unwrap();
+        assert_eq!(acks, [Ack::Ok, Ack::Ok]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix() {
+        let path = std::env::temp_dir().join(format!(
+            "transactions-rs-{}-socket-test.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let endpoint = start(&Endpoint::Unix(path.clone()));
+        let mut client = Client::connect(&endpoint).unwrap();
+        assert_eq!(client.send("deposit,2,1,3").unwrap(), Ack::Ok);
+        assert_eq!(
+            client.send("teleport,2,2,1").unwrap(),
+            error(
+                "unknown_transaction_type",
+                "the transaction used an unknown transaction type"
+            )
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
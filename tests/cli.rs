@@ -0,0 +1,1427 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_transactions-rs"))
+        .args(args)
+        .output()
+        .expect("failed to run transactions-rs")
+}
+
+fn run_with_stdin(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_transactions-rs"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run transactions-rs");
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Path to a file in the `test` directory, as a string to pass as an argument
+fn fixture(name: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test")
+        .join(name)
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// A path in the temp dir that is unique to this test run
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("transactions-rs-{}-{}", std::process::id(), name))
+}
+
+/// Accounts are written in a fixed order, so the output must match the
+/// expected file exactly
+fn assert_accounts(output: &[u8], expected: &str) {
+    let expected = fs::read_to_string(fixture(expected)).unwrap();
+    assert_eq!(String::from_utf8_lossy(output), expected);
+}
+
+#[test]
+fn test_process_fixtures() {
+    for name in [
+        "basic.csv",
+        "largedisputes.csv",
+        "string_ids.csv",
+        "batches.csv",
+    ] {
+        let output = run(&["process", &fixture(name)]);
+        assert!(output.status.success(), "{} failed", name);
+        assert_accounts(&output.stdout, &format!("{}.expected", name));
+    }
+}
+
+#[test]
+fn test_process_without_subcommand() {
+    let output = run(&[&fixture("basic.csv")]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "basic.csv.expected");
+}
+
+#[test]
+fn test_process_output_and_errors_files() {
+    let accounts = temp_path("process-accounts.csv");
+    let errors = temp_path("process-errors.txt");
+    let output = run(&[
+        "process",
+        &fixture("basic.csv"),
+        "--output",
+        accounts.to_str().unwrap(),
+        "--errors",
+        errors.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    assert_accounts(&fs::read(&accounts).unwrap(), "basic.csv.expected");
+    let errors = fs::read_to_string(&errors).unwrap();
+    assert_eq!(errors.lines().count(), 3);
+    assert!(errors.contains("unknown transaction type"));
+    assert!(errors.contains("failed to parse line"));
+}
+
+#[test]
+fn test_process_strict() {
+    let output = run(&["process", "--strict", &fixture("basic.csv")]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+
+    // processing stops at the unknown transaction type, before the parse errors
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unknown transaction type"));
+    assert!(stderr.contains("line 8 (byte 122)"));
+    assert!(stderr.contains("this transaction type doesn't exist,2,37,1.5"));
+    assert!(!stderr.contains("failed to parse line"));
+}
+
+#[test]
+fn test_process_strict_writes_nothing() {
+    let accounts = temp_path("strict-accounts.csv");
+    let snapshot = temp_path("strict-snapshot.json");
+    let output = run(&[
+        "process",
+        "--strict",
+        &fixture("basic.csv"),
+        "--output",
+        accounts.to_str().unwrap(),
+        "--save-snapshot",
+        snapshot.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(!accounts.exists());
+    assert!(!snapshot.exists());
+
+    // without any failures strict mode makes no difference
+    let output = run(&["process", "--strict", &fixture("string_ids.csv")]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "string_ids.csv.expected");
+}
+
+#[test]
+fn test_process_missing_input() {
+    let output = run(&["process", &fixture("does-not-exist.csv")]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_validate() {
+    let output = run(&["validate", &fixture("basic.csv")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("found 3 problems"));
+
+    let output = run(&["validate", &fixture("string_ids.csv")]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_validate_strict() {
+    let output = run(&["validate", "--strict", &fixture("basic.csv")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("found 1 problems"));
+}
+
+#[test]
+fn test_replay() {
+    let snapshot = temp_path("replay-snapshot.json");
+    let output = run(&[
+        "process",
+        &fixture("basic.csv"),
+        "--save-snapshot",
+        snapshot.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+
+    // the replay disputes a deposit that was resolved before the snapshot,
+    // which is only allowed when more than one dispute is
+    let output = run(&[
+        "replay",
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        &fixture("basic_replay.csv"),
+        "--max-disputes",
+        "2",
+    ]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "basic_replay.csv.expected");
+
+    let output = run(&[
+        "replay",
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        &fixture("basic_replay.csv"),
+        "--errors-format",
+        "jsonl",
+    ]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with("4,6.0000,0.0000,6.0000,false\n"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let rejected: Vec<serde_json::Value> = stderr
+        .lines()
+        .filter(|l| l.starts_with('{'))
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(rejected[0]["code"], "dispute_limit_reached");
+    assert_eq!(rejected[0]["disputes"], 1);
+    assert_eq!(rejected[1]["state"], "Resolved");
+}
+
+#[test]
+fn test_process_as_of() {
+    // the row that can't be parsed doesn't get a sequence number, but the ones
+    // with unknown types do, so sequence 9 is the dispute on tx 3
+    let output = run(&["process", &fixture("basic.csv"), "--as-of", "9"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n\
+         1,0.5000,0.0000,0.5000,false\n\
+         2,1.9950,0.0000,1.9950,false\n\
+         3,0.0000,9.0000,9.0000,false\n\
+         4,0.0000,6.0000,6.0000,false\n"
+    );
+
+    let output = run(&["process", &fixture("basic.csv"), "--as-of", "1000"]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "basic.csv.expected");
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--as-of 1000 is past the last transaction processed (12)"));
+}
+
+#[test]
+fn test_process_statement() {
+    let output = run(&["process", &fixture("basic.csv"), "--statement", "3"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "sequence,type,tx,amount,state,available,held,total,locked\n\
+         4,deposit,3,9.0000,Processed,9.0000,0.0000,9.0000,false\n\
+         9,dispute,3,9.0000,Disputed,0.0000,9.0000,9.0000,false\n\
+         11,chargeback,3,9.0000,ChargedBack,0.0000,0.0000,0.0000,true\n"
+    );
+
+    let output = run(&[
+        "process",
+        &fixture("basic.csv"),
+        "--statement",
+        "4",
+        "--as-of",
+        "8",
+        "--format",
+        "jsonl",
+    ]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+        lines[1],
+        r#"{"sequence":8,"type":"dispute","tx":"4","amount":"6.0000","state":"Disputed","available":"0.0000","held":"6.0000","total":"6.0000","locked":false}"#
+    );
+}
+
+#[test]
+fn test_replay_as_of() {
+    let snapshot = temp_path("replay-as-of-snapshot.json");
+    let output = run(&[
+        "process",
+        &fixture("basic.csv"),
+        "--save-snapshot",
+        snapshot.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+
+    // sequence numbers carry on from the snapshot, and its history is kept
+    let output = run(&[
+        "replay",
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        &fixture("basic_replay.csv"),
+        "--as-of",
+        "5",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).lines().nth(1),
+        Some("1,0.5000,0.0000,0.5000,false")
+    );
+}
+
+#[test]
+fn test_replay_missing_snapshot() {
+    let snapshot = temp_path("replay-missing-snapshot.json");
+    let output = run(&[
+        "replay",
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+        &fixture("basic_replay.csv"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_process_metrics() {
+    let metrics = temp_path("metrics.prom");
+    let output = run(&[
+        "process",
+        &fixture("basic.csv"),
+        "--summary",
+        "--metrics",
+        metrics.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Read 13 rows (1 failed to parse) in "),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains(
+        "Transactions: 1 chargeback, 4 deposit, 2 dispute, 1 resolve, 2 unknown, 2 withdrawal\n\
+         Rejected: 2 unknown_transaction_type\n\
+         Accounts: 4 created, 1 locked, 0.0000 held in disputes\n"
+    ));
+
+    let text = std::fs::read_to_string(&metrics).unwrap();
+    assert!(text.contains("ledger_rows_read_total 13\n"));
+    assert!(text.contains("ledger_transactions_total{type=\"deposit\"} 4\n"));
+    assert!(text.contains("ledger_accounts_locked 1\n"));
+}
+
+#[test]
+fn test_process_json_logs() {
+    let output = run(&[
+        &fixture("basic.csv"),
+        "--errors-format",
+        "csv",
+        "--errors",
+        temp_path("json-logs-errors.csv").to_str().unwrap(),
+        "--log-level",
+        "info",
+        "--log-format",
+        "json",
+    ]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let logs: Vec<_> = stderr
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .collect();
+    // only the two rejections are logged at info
+    assert_eq!(logs.len(), 2, "{}", stderr);
+    assert!(logs[0].contains(r#""code":"unknown_transaction_type""#));
+    assert!(logs[0].contains(
+        r#""span":{"client":"2","kind":"unknown","sequence":7,"tx":"37","name":"transaction"}"#
+    ));
+
+    let output = run(&["process", &fixture("basic.csv"), "--log-level", "info["]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_process_rules() {
+    let alerts = temp_path("rules-alerts.jsonl");
+    let output = run(&[
+        "process",
+        &fixture("rules.csv"),
+        "--rules",
+        &fixture("rules.json"),
+        "--alerts",
+        alerts.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n\
+         1,10.0000,20.0000,30.0000,true\n\
+         2,5001.0000,0.0000,5001.0000,false\n\
+         3,-10.0000,10.0000,0.0000,true\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed: the transaction was blocked by rule 'fresh-deposit'"));
+
+    // the blocking rule is a field of its own in reports
+    let errors = temp_path("rules-errors.jsonl");
+    let output = run(&[
+        "process",
+        &fixture("rules.csv"),
+        "--rules",
+        &fixture("rules.json"),
+        "--errors",
+        errors.to_str().unwrap(),
+        "--errors-format",
+        "jsonl",
+    ]);
+    assert!(output.status.success());
+    let errors = fs::read_to_string(&errors).unwrap();
+    let blocked: Vec<serde_json::Value> = errors
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+        .filter(|r| r["code"] == "blocked_by_rule")
+        .collect();
+    assert!(!blocked.is_empty(), "{}", errors);
+    assert_eq!(blocked[0]["rule"], "fresh-deposit");
+
+    let alerts = fs::read_to_string(&alerts).unwrap();
+    let lines: Vec<_> = alerts.lines().collect();
+    assert_eq!(lines.len(), 5, "{}", alerts);
+    assert_eq!(
+        lines[0],
+        r#"{"sequence":5,"rule":"repeat-disputes","action":"lock","type":"dispute","client":1,"tx":"2","reason":"2 disputes in the last 4 transactions"}"#
+    );
+    assert!(lines[4].contains(r#""rule":"overdrawn","action":"lock""#));
+
+    let output = run(&[
+        "process",
+        &fixture("rules.csv"),
+        "--rules",
+        &fixture("basic.csv"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_process_max_disputes() {
+    let input = "type,client,tx,amount\n\
+                 deposit,1,1,10\n\
+                 dispute,1,1,\n\
+                 resolve,1,1,\n\
+                 dispute,1,1,\n\
+                 chargeback,1,1,\n";
+    // by default resolving a dispute is final
+    let output = run_with_stdin(
+        &["process", "-", "--errors-format", "csv"],
+        input.as_bytes(),
+    );
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with("1,10.0000,0.0000,10.0000,false\n"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(
+        "<stdin>,5,\"dispute,1,1,\",dispute,1,1,,dispute_limit_reached,the transaction has already been disputed the most times allowed (1),,,,,,,,1\n"
+    ), "{}", stderr);
+    assert!(stderr.contains("chargeback,1,1,,invalid_transaction_state,"));
+    assert!(stderr.contains(",Resolved,,,,,,\n"));
+    // the default cap changing the results is called out, but only once
+    let warning =
+        "Warning: a dispute was rejected as transactions can only be disputed once by default";
+    let twice = format!("{}dispute,1,1,\n", input);
+    let output = run_with_stdin(&["process", "-"], twice.as_bytes());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr.matches("disputed the most times allowed").count(),
+        2,
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches(warning).count(), 1, "{}", stderr);
+
+    // asking for the cap explicitly doesn't warn
+    let output = run_with_stdin(&["process", "-", "--max-disputes", "1"], input.as_bytes());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("disputed the most times allowed"));
+    assert!(!stderr.contains(warning));
+
+    let output = run_with_stdin(
+        &[
+            "process",
+            "-",
+            "--max-disputes",
+            "2",
+            "--errors-format",
+            "csv",
+        ],
+        input.as_bytes(),
+    );
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with("1,0.0000,0.0000,0.0000,true\n"));
+}
+
+#[test]
+fn test_process_limits() {
+    let output = run(&[
+        "process",
+        &fixture("limits.csv"),
+        "--limits",
+        &fixture("limits.json"),
+        "--tiers",
+        &fixture("limits_tiers.csv"),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n\
+         1,500.0000,0.0000,500.0000,false\n\
+         2,999.0000,0.0000,999.0000,false\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr.matches("failed: limit exceeded").count(),
+        5,
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains(
+        "limit exceeded: the client is allowed withdrawals of at most '500.0000', attempted '501.0000'"
+    ));
+
+    // the limit and attempted amount are fields of their own in reports
+    let output = run(&[
+        "process",
+        &fixture("limits.csv"),
+        "--limits",
+        &fixture("limits.json"),
+        "--tiers",
+        &fixture("limits_tiers.csv"),
+        "--errors-format",
+        "jsonl",
+    ]);
+    let limits: Vec<_> = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .map(|r| {
+            (
+                r["limit"].clone(),
+                r["allowed"].clone(),
+                r["within"].clone(),
+                r["attempted"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        limits[..3],
+        [
+            (
+                "deposit".into(),
+                "1000.0000".into(),
+                serde_json::Value::Null,
+                "1000.0001".into()
+            ),
+            (
+                "withdrawal".into(),
+                "500.0000".into(),
+                serde_json::Value::Null,
+                "501.0000".into()
+            ),
+            (
+                "withdrawal_volume".into(),
+                "600.0000".into(),
+                5.into(),
+                "201.0000".into()
+            ),
+        ]
+    );
+    assert_eq!(
+        limits[3],
+        (
+            "withdrawal_count".into(),
+            "2".into(),
+            5.into(),
+            "100.0000".into()
+        )
+    );
+
+    // the tiers can only be assigned along with the limits
+    let output = run(&[
+        "process",
+        &fixture("limits.csv"),
+        "--tiers",
+        &fixture("limits_tiers.csv"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = run(&[
+        "process",
+        &fixture("limits.csv"),
+        "--limits",
+        &fixture("limits.json"),
+        "--tiers",
+        &fixture("basic.csv"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_process_aml() {
+    let report = temp_path("suspicious-activity.csv");
+    let output = run(&[
+        "process",
+        &fixture("aml.csv"),
+        "--aml",
+        &fixture("aml.json"),
+        "--suspicious-activity",
+        report.to_str().unwrap(),
+        "--suspicious-activity-format",
+        "csv",
+    ]);
+    assert!(output.status.success());
+    // nothing is rejected by the checks
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n\
+         1,9399.9999,0.0000,9399.9999,false\n\
+         2,-1800.0000,2000.0000,200.0000,false\n\
+         3,6010.0000,0.0000,6010.0000,false\n\
+         4,250.0000,0.0000,250.0000,false\n"
+    );
+
+    let report = fs::read_to_string(&report).unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 10, "{}", report);
+    assert_eq!(
+        lines[0],
+        "case,pattern,client,sequence,type,tx,amount,state,reason"
+    );
+    assert_eq!(
+        lines[1],
+        r#"1,structuring,1,1,deposit,1,9500.0000,Processed,"3 deposits totalling 29399.9999, each under 10000.0000""#
+    );
+    assert!(lines[6].starts_with("3,rapid_cycle,2,6,deposit,6,2000.0000,Disputed,"));
+    assert_eq!(
+        lines[9],
+        "4,dormant_account,3,15,deposit,15,6000.0000,Processed,6000.0000 moved after 5 transactions without activity"
+    );
+
+    let output = run(&[
+        "process",
+        &fixture("aml.csv"),
+        "--aml",
+        &fixture("aml.json"),
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(
+        "case 3: rapid_cycle by client 2: deposit of 2000.0000 followed by 1900.0000 withdrawn within 2 transactions (deposit 6, withdrawal 7, withdrawal 8)"
+    ));
+}
+
+#[test]
+fn test_repl() {
+    let snapshot = temp_path("repl-snapshot.json");
+    let script = format!(
+        "deposit,1,1,10\n\
+         withdrawal,1,2,50\n\
+         dispute,1,1,\n\
+         tx 1\n\
+         undo\n\
+         account 1\n\
+         save {snapshot}\n\
+         load {basic}\n\
+         account 4\n\
+         undo\n\
+         account 4\n\
+         deposit,1,3,5\n\
+         restore {snapshot}\n\
+         account 1\n\
+         undo\n\
+         quit\n\
+         account 1\n",
+        snapshot = snapshot.display(),
+        basic = fixture("basic.csv"),
+    );
+    let output = run_with_stdin(&["repl"], script.as_bytes());
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines[..4],
+        [
+            "ok",
+            "error: insufficient funds, available: '10.0000' (insufficient_funds)",
+            "ok",
+            "type,client,tx,amount,state"
+        ]
+    );
+    assert_eq!(lines[4], "deposit,1,1,10.0000,Disputed");
+    assert_eq!(lines[5], "undid dispute 1 of client 1");
+    assert_eq!(lines[7], "1,10.0000,0.0000,10.0000,false");
+    assert!(stdout.contains("line 11: failed to parse: found 3 fields, expected 4\n"));
+    assert!(stdout.contains("4,6.0000,0.0000,6.0000,false\n"));
+    assert!(stdout.contains("\naccount 4 not found\n"));
+    // restoring the snapshot drops the deposit made after it was saved, and
+    // anything left to undo
+    assert!(stdout.ends_with(
+        "client,available,held,total,locked\n1,10.0000,0.0000,10.0000,false\nnothing to undo\n"
+    ));
+}
+
+#[test]
+fn test_repl_undo_limit() {
+    let input = temp_path("repl-rounded.csv");
+    std::fs::write(&input, "type,client,tx,amount\ndeposit,2,1000,1.00005\n").unwrap();
+    let mut script: String = (1..=101)
+        .map(|tx| format!("deposit,1,{},1\n", tx))
+        .collect();
+    script += &"undo\n".repeat(101);
+    script += &format!("account 1\nload {}\n", input.display());
+    let output = run_with_stdin(&["repl", "--precision", "half-even"], script.as_bytes());
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    // only the last 100 deposits can be undone, the first one is kept
+    assert_eq!(lines[200], "undid deposit 2 of client 1");
+    assert_eq!(lines[201], "nothing to undo");
+    assert_eq!(lines[203], "1,1.0000,0.0000,1.0000,false");
+    assert_eq!(
+        lines[204..],
+        [
+            "line 2: amount 1.00005 was rounded to 1.0000 (half-even)",
+            &format!("loaded 1 transactions from '{}', 0 failed", input.display()),
+        ]
+    );
+}
+
+#[test]
+fn test_repl_rules() {
+    let snapshot = temp_path("repl-rules-snapshot.json");
+    let script = format!(
+        "deposit,1,1,5000\n\
+         withdrawal,1,2,10\n\
+         save {snapshot}\n\
+         restore {snapshot}\n\
+         withdrawal,1,3,10\n",
+        snapshot = snapshot.display(),
+    );
+    let output = run_with_stdin(
+        &["repl", "--rules", &fixture("rules.json")],
+        script.as_bytes(),
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    // the rules still apply once a snapshot is restored
+    assert!(lines[1].ends_with("(blocked_by_rule)"), "{}", stdout);
+    assert!(lines[4].ends_with("(blocked_by_rule)"), "{}", stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        stderr
+            .matches("rule 'fresh-deposit' (block) triggered")
+            .count(),
+        2,
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_diff() {
+    let expected = fixture("basic.csv.expected");
+    let output = run(&["diff", &expected, &expected]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = run(&["diff", &expected, &fixture("basic_replay.csv.expected")]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            "< 1,0.5000,0.0000,0.5000,false",
+            "> 1,2.0000,0.0000,2.0000,false",
+            "< 4,6.0000,0.0000,6.0000,false",
+            "> 4,0.0000,0.0000,0.0000,true",
+        ]
+    );
+}
+
+#[test]
+fn test_process_errors_csv_report() {
+    let errors = temp_path("report.csv");
+    let output = run(&[
+        "process",
+        &fixture("basic.csv"),
+        "--errors",
+        errors.to_str().unwrap(),
+        "--errors-format",
+        "csv",
+    ]);
+    assert!(output.status.success());
+
+    let mut rdr = csv::Reader::from_path(&errors).unwrap();
+    let headers = rdr.headers().unwrap().clone();
+    assert_eq!(
+        headers.iter().collect::<Vec<_>>(),
+        [
+            "file",
+            "line",
+            "raw",
+            "type",
+            "client",
+            "tx",
+            "amount",
+            "code",
+            "message",
+            "available",
+            "state",
+            "limit",
+            "allowed",
+            "within",
+            "attempted",
+            "rule",
+            "disputes"
+        ]
+    );
+    let records: Vec<_> = rdr.records().map(Result::unwrap).collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(&records[0][0], fixture("basic.csv"));
+    assert_eq!(&records[0][1], "8");
+    assert_eq!(
+        &records[0][2],
+        "this transaction type doesn't exist,2,37,1.5"
+    );
+    assert_eq!(&records[0][7], "unknown_transaction_type");
+    assert_eq!(&records[1][1], "11");
+    assert_eq!(&records[1][2], "withdrawal,100,1.134");
+    assert_eq!(&records[1][7], "parse_error");
+}
+
+#[test]
+fn test_process_errors_jsonl_report() {
+    let input = temp_path("report-input.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount\n\
+         deposit,1,1,1.5\n\
+         withdrawal,1,2,5\n\
+         dispute,1,1,\n\
+         dispute,1,1,\n",
+    )
+    .unwrap();
+    let errors = temp_path("report.jsonl");
+    let output = run(&[
+        "process",
+        input.to_str().unwrap(),
+        "--errors",
+        errors.to_str().unwrap(),
+        "--errors-format",
+        "jsonl",
+    ]);
+    assert!(output.status.success());
+
+    let errors = fs::read_to_string(&errors).unwrap();
+    let records: Vec<serde_json::Value> = errors
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(
+        records,
+        [
+            serde_json::json!({
+                "file": input.to_str().unwrap(),
+                "line": 3,
+                "raw": "withdrawal,1,2,5",
+                "type": "withdrawal",
+                "client": 1,
+                "tx": "2",
+                "amount": "5.0000",
+                "code": "insufficient_funds",
+                "message": "insufficient funds, available: '1.5000'",
+                "available": "1.5000",
+                "state": null,
+                "limit": null,
+                "allowed": null,
+                "within": null,
+                "attempted": null,
+                "rule": null,
+                "disputes": null,
+            }),
+            serde_json::json!({
+                "file": input.to_str().unwrap(),
+                "line": 5,
+                "raw": "dispute,1,1,",
+                "type": "dispute",
+                "client": 1,
+                "tx": "1",
+                "amount": null,
+                "code": "invalid_transaction_state",
+                "message": "transaction is in the incorrect state: 'Disputed'",
+                "available": null,
+                "state": "Disputed",
+                "limit": null,
+                "allowed": null,
+                "within": null,
+                "attempted": null,
+                "rule": null,
+                "disputes": null,
+            }),
+        ]
+    );
+}
+
+#[test]
+fn test_process_quarantine() {
+    let quarantine = temp_path("quarantine.csv");
+    let output = run(&[
+        "process",
+        &fixture("basic.csv"),
+        "--quarantine",
+        quarantine.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "basic.csv.expected");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!(
+        "failed to parse line 11 (byte 195) in '{}': found 3 fields, expected 4",
+        fixture("basic.csv")
+    )));
+
+    assert_eq!(
+        fs::read_to_string(&quarantine).unwrap(),
+        format!(
+            "file,line,byte,reason,row\n\
+             {0},8,122,the transaction used an unknown transaction type,\"this transaction type doesn't exist,2,37,1.5\"\n\
+             {0},11,195,\"found 3 fields, expected 4\",\"withdrawal,100,1.134\"\n\
+             {0},12,216,the transaction used an unknown transaction type,\"blahblah,100,37.32,500324\"\n",
+            fixture("basic.csv")
+        )
+    );
+}
+
+#[test]
+fn test_quarantine_invalid_utf8() {
+    let input = temp_path("invalid-utf8.csv");
+    fs::write(
+        &input,
+        b"type,client,tx,amount\ndeposit,1,1,1\ndeposit,1,2,\xff1\n",
+    )
+    .unwrap();
+    let quarantine = temp_path("invalid-utf8-quarantine.csv");
+    let output = run(&[
+        "process",
+        input.to_str().unwrap(),
+        "--quarantine",
+        quarantine.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // the reason is the csv crate's message as it is, without another prefix
+    assert!(
+        stderr.contains("': invalid utf-8: invalid UTF-8 in field 3 near byte index 0\n"),
+        "{}",
+        stderr
+    );
+    assert_eq!(stderr.matches("invalid utf-8").count(), 1, "{}", stderr);
+
+    // the row is kept byte for byte, so it can be fixed up and resubmitted
+    let quarantine = fs::read(&quarantine).unwrap();
+    assert!(quarantine.ends_with(b",\"deposit,1,2,\xff1\"\n"));
+}
+
+#[test]
+fn test_quarantine_binary_rows() {
+    let input = temp_path("quarantine-binary.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount,batch\n\
+         teleport,1,1,5,a\n",
+    )
+    .unwrap();
+    let binary = temp_path("quarantine-binary.txb");
+    let output = run(&[
+        "convert",
+        input.to_str().unwrap(),
+        "--output",
+        binary.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    // a record cut short by a corrupt file
+    let mut bytes = fs::read(&binary).unwrap();
+    bytes.extend_from_slice(&[0x01, 0xab, 0xcd]);
+    fs::write(&binary, bytes).unwrap();
+
+    let quarantine = temp_path("quarantine-binary-quarantine.csv");
+    let output = run(&[
+        "process",
+        binary.to_str().unwrap(),
+        "--quarantine",
+        quarantine.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+    // the rebuilt row keeps its batch, and the record that couldn't be read is
+    // kept as its bytes in hex
+    let quarantine = fs::read_to_string(&quarantine).unwrap();
+    let rows: Vec<_> = quarantine.lines().skip(1).collect();
+    assert_eq!(rows.len(), 2, "{}", quarantine);
+    assert!(
+        rows[0].ends_with(
+            ",1,14,the transaction used an unknown transaction type,\"unknown,1,1,5.0000,a\""
+        ),
+        "{}",
+        rows[0]
+    );
+    assert!(
+        rows[1].ends_with(",2,44,truncated record,01abcd"),
+        "{}",
+        rows[1]
+    );
+}
+
+#[test]
+fn test_validate_quarantine() {
+    let quarantine = temp_path("validate-quarantine.csv");
+    let output = run(&[
+        "validate",
+        &fixture("basic.csv"),
+        "--quarantine",
+        quarantine.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+
+    let quarantine = fs::read_to_string(&quarantine).unwrap();
+    let lines: Vec<_> = quarantine.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[2].starts_with(&format!("{},11,195,", fixture("basic.csv"))));
+}
+
+#[test]
+fn test_process_batch_rolled_back() {
+    let errors = temp_path("batch-errors.csv");
+    let output = run(&[
+        "process",
+        &fixture("batches.csv"),
+        "--errors",
+        errors.to_str().unwrap(),
+        "--errors-format",
+        "csv",
+    ]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "batches.csv.expected");
+
+    // the failing row, then the rest of its batch, then the later dispute on a
+    // transaction that was rolled back
+    let mut rdr = csv::Reader::from_path(&errors).unwrap();
+    let codes: Vec<_> = rdr
+        .records()
+        .map(|r| {
+            let r = r.unwrap();
+            (r[1].to_string(), r[7].to_string())
+        })
+        .collect();
+    assert_eq!(
+        codes,
+        [
+            ("10", "insufficient_funds"),
+            ("7", "batch_rolled_back"),
+            ("8", "batch_rolled_back"),
+            ("9", "batch_rolled_back"),
+            ("11", "batch_rolled_back"),
+            ("12", "transaction_not_found"),
+        ]
+        .map(|(line, code)| (line.to_string(), code.to_string()))
+    );
+}
+
+#[test]
+fn test_process_multiple_inputs() {
+    let first = temp_path("multiple-first.csv");
+    let second = temp_path("multiple-second.csv");
+    fs::write(&first, "type,client,tx,amount\ndeposit,1,1,10\n").unwrap();
+    fs::write(
+        &second,
+        "type, client, tx, amount\ndispute,1,1,\nwithdrawal,1,2,5\n",
+    )
+    .unwrap();
+
+    // the dispute in the second file applies to the deposit from the first
+    let output = run(&["process", first.to_str().unwrap(), second.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n1,0.0000,10.0000,10.0000,false\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("insufficient funds"));
+}
+
+#[test]
+fn test_process_stdin() {
+    let output = run_with_stdin(
+        &["process", "-", &fixture("string_ids.csv")],
+        b"type,client,tx,amount\ndeposit,9,1,1\nbad row\n",
+    );
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("9,1.0000,0.0000,1.0000,false"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to parse line 3 (byte 36) in '<stdin>'"));
+}
+
+#[test]
+fn test_process_missing_column() {
+    let input = temp_path("missing-column.csv");
+    fs::write(&input, "type,client,amount\ndeposit,1,10\n").unwrap();
+
+    // headers are checked before anything is processed
+    let output = run(&["process", &fixture("basic.csv"), input.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is missing the 'tx' column"));
+}
+
+#[test]
+fn test_process_compressed_fixtures() {
+    for name in ["basic.csv.gz", "basic.csv.zst"] {
+        let output = run(&["process", &fixture(name)]);
+        assert!(output.status.success(), "{} failed", name);
+        assert_accounts(&output.stdout, "basic.csv.expected");
+    }
+}
+
+#[test]
+fn test_process_truncated_input() {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    writeln!(encoder, "type,client,tx,amount").unwrap();
+    for tx in 1..=10_000 {
+        writeln!(encoder, "deposit,1,{},1.0", tx).unwrap();
+    }
+    let bytes = encoder.finish().unwrap();
+    let input = temp_path("truncated.csv.gz");
+    fs::write(&input, &bytes[..bytes.len() / 2]).unwrap();
+
+    // the rest of the input is lost, so nothing is written rather than
+    // balances for only part of it
+    for command in ["process", "validate"] {
+        let output = run(&[command, input.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(2), "{}", command);
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("failed to read"), "{}", stderr);
+        assert!(!stderr.contains("failed to parse"));
+    }
+}
+
+#[test]
+fn test_process_compressed_stdin() {
+    // without a file name to go by, the compression is detected from the input
+    let output = run_with_stdin(
+        &["process", "-"],
+        &fs::read(fixture("basic.csv.zst")).unwrap(),
+    );
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "basic.csv.expected");
+}
+
+#[test]
+fn test_process_jsonl_input() {
+    let output = run(&["process", &fixture("basic.jsonl")]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "basic.csv.expected");
+
+    // the format can't be picked from the extension of stdin
+    let output = run_with_stdin(
+        &["process", "--input-format", "jsonl", "-"],
+        &fs::read(fixture("basic.jsonl")).unwrap(),
+    );
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "basic.csv.expected");
+}
+
+#[test]
+fn test_process_json_output() {
+    let accounts = temp_path("accounts.json");
+    let output = run(&[
+        "process",
+        &fixture("string_ids.csv"),
+        "--output",
+        accounts.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+
+    let accounts: Vec<serde_json::Value> =
+        serde_json::from_str(&fs::read_to_string(&accounts).unwrap()).unwrap();
+    assert_eq!(
+        accounts,
+        [
+            serde_json::json!({
+                "client": 1,
+                "available": "-2.5000",
+                "held": "5.2500",
+                "total": "2.7500",
+                "locked": true,
+            }),
+            serde_json::json!({
+                "client": 2,
+                "available": "3.0000",
+                "held": "0.0000",
+                "total": "3.0000",
+                "locked": false,
+            }),
+        ]
+    );
+}
+
+#[test]
+fn test_process_jsonl_output() {
+    let output = run(&["process", &fixture("basic.csv"), "--format", "jsonl"]);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let clients: Vec<_> = stdout
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap()["client"].as_u64())
+        .collect();
+    assert_eq!(clients, [Some(1), Some(2), Some(3), Some(4)]);
+}
+
+#[test]
+fn test_convert_binary_replay() {
+    let binary = temp_path("largedisputes.txb");
+    let output = run(&[
+        "convert",
+        &fixture("largedisputes.csv"),
+        "--output",
+        binary.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+
+    let output = run(&["process", binary.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "largedisputes.csv.expected");
+}
+
+#[test]
+fn test_convert_binary_batches() {
+    let binary = temp_path("batches.txb");
+    let output = run(&[
+        "convert",
+        &fixture("batches.csv"),
+        "--output",
+        binary.to_str().unwrap(),
+    ]);
+    assert!(output.status.success());
+
+    // the batches are kept, so the failed one is rolled back just the same
+    let output = run(&["process", binary.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "batches.csv.expected");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("the batch was rolled back").count(), 4);
+}
+
+#[test]
+fn test_convert_skips_parse_errors() {
+    let binary = temp_path("basic.txb");
+    let output = run(&[
+        "convert",
+        &fixture("basic.csv"),
+        "--output",
+        binary.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to parse line 11 (byte 195)"));
+    assert!(stderr.contains("Converted 12 rows, skipped 1"));
+
+    // unknown transaction types are kept, so they're still reported when the
+    // binary file is processed
+    let output = run(&["process", binary.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_accounts(&output.stdout, "basic.csv.expected");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("unknown transaction type").count(), 2);
+}
+
+#[test]
+fn test_convert_drops_batches_with_parse_errors() {
+    let input = temp_path("batch_parse_error.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount,batch\n\
+         deposit,1,1,5,\n\
+         deposit,1,2,3,a\n\
+         withdrawal,1,3,1.2.3,a\n\
+         deposit,1,4,1,a\n",
+    )
+    .unwrap();
+    let input = input.to_str().unwrap();
+    let processed = run(&["process", input]);
+    assert_eq!(processed.status.code(), Some(0));
+
+    let binary = temp_path("batch_parse_error.txb");
+    let output = run(&["convert", input, "--output", binary.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to parse line 4 (byte 59)"));
+    assert_eq!(stderr.matches("the batch was rolled back").count(), 2);
+    assert!(stderr.contains("Converted 1 rows, skipped 3"));
+
+    // replaying the binary file gives the same accounts as processing the input
+    let replayed = run(&["process", binary.to_str().unwrap()]);
+    assert!(replayed.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&replayed.stdout),
+        String::from_utf8_lossy(&processed.stdout)
+    );
+    assert!(String::from_utf8_lossy(&replayed.stdout).contains("1,5.0000,0.0000,5.0000,false"));
+}
+
+#[test]
+fn test_convert_keeps_batches_with_the_same_id_apart() {
+    let first = temp_path("same_batch_id_first.csv");
+    fs::write(
+        &first,
+        "type,client,tx,amount,batch\n\
+         deposit,1,1,10,\n\
+         withdrawal,1,2,4,a\n",
+    )
+    .unwrap();
+    // batch a starts again at the top of the next input, and again once batch
+    // b, which is left out as one of its rows can't be parsed, is over
+    let second = temp_path("same_batch_id_second.csv");
+    fs::write(
+        &second,
+        "type,client,tx,amount,batch\n\
+         withdrawal,1,3,8,a\n\
+         deposit,1,4,1,b\n\
+         deposit,1,5,x,b\n\
+         withdrawal,1,6,1,a\n",
+    )
+    .unwrap();
+    let inputs = [first.to_str().unwrap(), second.to_str().unwrap()];
+    let processed = run(&["process", inputs[0], inputs[1]]);
+    assert!(processed.status.success());
+    assert!(String::from_utf8_lossy(&processed.stdout).contains("1,5.0000,0.0000,5.0000,false"));
+
+    let binary = temp_path("same_batch_id.txb");
+    let output = run(&[
+        "convert",
+        inputs[0],
+        inputs[1],
+        "--output",
+        binary.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+
+    // each batch is rolled back or committed on its own, as it was in the inputs
+    let replayed = run(&["process", binary.to_str().unwrap()]);
+    assert!(replayed.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&replayed.stdout),
+        String::from_utf8_lossy(&processed.stdout)
+    );
+}
+
+#[test]
+fn test_process_sort() {
+    let clients = |args: &[&str]| {
+        let output = run(args);
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .skip(1)
+            .map(|l| l.split(',').next().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    let basic = fixture("basic.csv");
+
+    assert_eq!(
+        clients(&["process", &basic, "--sort", "total", "--descending"]),
+        ["4", "2", "1", "3"]
+    );
+    assert_eq!(
+        clients(&["process", &basic, "--descending"]),
+        ["4", "3", "2", "1"]
+    );
+    // accounts with the same held amount stay in client order
+    assert_eq!(
+        clients(&["process", &basic, "--sort", "held"]),
+        ["1", "2", "3", "4"]
+    );
+}
+
+#[test]
+fn test_process_precision() {
+    let input = temp_path("precision.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount\n\
+         deposit,1,1,0.00055\n\
+         deposit,1,2,1.5\n",
+    )
+    .unwrap();
+
+    // amounts with too many decimal places are rejected by default
+    let output = run(&["process", input.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("amount has 5 decimal places, at most 4 are allowed"));
+
+    for (precision, total) in [
+        ("half-even", "1.5006"),
+        ("half-up", "1.5006"),
+        ("truncate", "1.5005"),
+    ] {
+        let errors = temp_path(&format!("precision-{}.jsonl", precision));
+        let output = run(&[
+            "process",
+            input.to_str().unwrap(),
+            "--precision",
+            precision,
+            "--errors",
+            errors.to_str().unwrap(),
+            "--errors-format",
+            "jsonl",
+        ]);
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!(
+                "client,available,held,total,locked\n1,{0},0.0000,{0},false\n",
+                total
+            )
+        );
+
+        // the rounding is reported, but the transaction still goes through
+        let errors = fs::read_to_string(&errors).unwrap();
+        let records: Vec<serde_json::Value> = errors
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["line"], 2);
+        assert_eq!(records[0]["code"], "amount_rounded");
+        let rounded = if precision == "truncate" {
+            "0.0005"
+        } else {
+            "0.0006"
+        };
+        assert_eq!(records[0]["amount"], rounded);
+        assert_eq!(
+            records[0]["message"],
+            format!("amount 0.00055 was rounded to {} ({})", rounded, precision)
+        );
+    }
+}
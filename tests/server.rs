@@ -0,0 +1,510 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Child, ChildStderr, Command, Stdio};
+
+/// A running server, which is killed when dropped
+struct Server {
+    child: Child,
+    stderr: BufReader<ChildStderr>,
+    addr: String,
+}
+
+impl Server {
+    /// Start the server on a free loopback port, waiting until it's listening
+    fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_transactions-rs"))
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to run transactions-rs");
+        let stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut server = Server {
+            child,
+            stderr,
+            addr: String::new(),
+        };
+        server.addr = server.read_addr();
+        server
+    }
+
+    /// Read the next address the server prints that it's listening on
+    fn read_addr(&mut self) -> String {
+        let mut line = String::new();
+        self.stderr.read_line(&mut line).unwrap();
+        line.trim()
+            .trim_end_matches("/metrics")
+            .rsplit("://")
+            .next()
+            .expect("the server prints the address it's listening on")
+            .to_string()
+    }
+
+    fn request(&self, method: &str, path: &str, content_type: &str, body: &str) -> (u16, String) {
+        request(&self.addr, method, path, content_type, body)
+    }
+
+    fn get(&self, path: &str) -> (u16, String) {
+        self.request("GET", path, "text/plain", "")
+    }
+
+    fn post(&self, content_type: &str, body: &str) -> (u16, String) {
+        self.request("POST", "/transactions", content_type, body)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Send a request, returning the status code and body of the response
+fn request(addr: &str, method: &str, path: &str, content_type: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        content_type,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let status = response
+        .split(' ')
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .expect("a status line");
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .unwrap_or_default();
+    (status, body)
+}
+
+fn fixture(name: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test")
+        .join(name)
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn test_http_post_and_query() {
+    let server = Server::start(&["serve", "--listen", "127.0.0.1:0"]);
+
+    let csv = std::fs::read_to_string(fixture("basic.csv")).unwrap();
+    let (status, body) = server.post("text/csv", &csv);
+    assert_eq!(status, 207);
+    assert!(
+        body.starts_with(r#"{"processed":10,"failed":3,"#),
+        "{}",
+        body
+    );
+    assert!(body.contains(r#"{"line":11,"type":null,"client":null,"tx":null,"amount":null,"status":400,"error":{"code":"parse_error","#));
+
+    let (status, body) = server.get("/accounts");
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"[{"client":1,"available":"0.5000","held":"0.0000","total":"0.5000","locked":false},{"client":2,"available":"1.9950","held":"0.0000","total":"1.9950","locked":false},{"client":3,"available":"0.0000","held":"0.0000","total":"0.0000","locked":true},{"client":4,"available":"6.0000","held":"0.0000","total":"6.0000","locked":false}]"#
+    );
+
+    let (status, body) = server.get("/accounts/2");
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"{"client":2,"available":"1.9950","held":"0.0000","total":"1.9950","locked":false}"#
+    );
+
+    let (status, body) = server.get("/transactions/3");
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        r#"{"transaction":{"type":"deposit","client":3,"tx":"3","amount":"9.0000"},"state":"ChargedBack","transitions":[{"sequence":4,"state":"Processed"},{"sequence":9,"state":"Disputed"},{"sequence":11,"state":"ChargedBack"}]}"#
+    );
+}
+
+#[test]
+fn test_http_metrics() {
+    let server = Server::start(&["serve", "--listen", "127.0.0.1:0"]);
+    server.post(
+        "text/csv",
+        "type,client,tx,amount\ndeposit,1,1,10\ndispute,1,1,\ndispute,1,2,\n",
+    );
+
+    let (status, body) = server.get("/metrics");
+    assert_eq!(status, 200);
+    assert!(body.contains("ledger_rows_read_total 3\n"), "{}", body);
+    assert!(body.contains("ledger_transactions_total{type=\"dispute\"} 2\n"));
+    assert!(body.contains("ledger_rejected_total{code=\"transaction_not_found\"} 1\n"));
+    assert!(body.contains("ledger_held_funds 10.0000\n"));
+}
+
+#[test]
+fn test_http_json_body() {
+    let server = Server::start(&["serve", "--listen", "127.0.0.1:0"]);
+
+    let (status, body) = server.post(
+        "application/json",
+        r#"[{"type": "deposit", "client": 7, "tx": 1, "amount": 5.25},
+            {"type": "dispute", "client": 7, "tx": 1}]"#,
+    );
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.starts_with(r#"{"processed":2,"failed":0,"#));
+
+    let (status, body) = server.post(
+        "application/x-ndjson",
+        "{\"type\": \"deposit\", \"client\": 7, \"tx\": 2, \"amount\": \"1\"}\n",
+    );
+    assert_eq!(status, 200, "{}", body);
+
+    let (_, body) = server.get("/accounts/7");
+    assert_eq!(
+        body,
+        r#"{"client":7,"available":"1.0000","held":"5.2500","total":"6.2500","locked":false}"#
+    );
+    let (_, body) = server.get("/transactions/1");
+    assert!(
+        body.ends_with(r#""state":"Disputed","transitions":[{"sequence":1,"state":"Processed"},{"sequence":2,"state":"Disputed"}]}"#),
+        "{}",
+        body
+    );
+}
+
+#[test]
+fn test_http_errors() {
+    let server = Server::start(&["serve", "--listen", "127.0.0.1:0"]);
+    let post = |row: &str| server.post("text/csv", &format!("type,client,tx,amount\n{}\n", row));
+
+    assert_eq!(post("deposit,1,1,10").0, 200);
+
+    // a single transaction is answered with the status of its own error
+    let (status, body) = post("withdrawal,1,2,50");
+    assert_eq!(status, 409);
+    assert!(body.contains(r#""error":{"code":"insufficient_funds","message":"insufficient funds, available: '10.0000'","available":"10.0000"}"#));
+
+    let (status, body) = post("resolve,1,1,");
+    assert_eq!(status, 409);
+    assert!(body.contains(r#""code":"invalid_transaction_state""#));
+    assert!(body.contains(r#""state":"Processed""#));
+
+    assert_eq!(post("withdrawal,2,3,1").0, 404);
+    assert_eq!(post("dispute,1,99,").0, 404);
+    assert_eq!(post("deposit,1,4,-1").0, 422);
+    assert_eq!(post("teleport,1,5,1").0, 422);
+    assert_eq!(post("deposit,2,7,1").0, 200);
+    assert_eq!(post("dispute,2,1,").0, 422);
+    assert_eq!(post("deposit,1,6,1.00001").0, 400);
+
+    let (status, body) = server.get("/accounts/42");
+    assert_eq!(status, 404);
+    assert_eq!(
+        body,
+        r#"{"error":{"code":"account_not_found","message":"account not found"}}"#
+    );
+    assert_eq!(server.get("/accounts/abc").0, 400);
+    assert_eq!(server.get("/transactions/abc").0, 404);
+    assert_eq!(server.get("/transactions/%zz").0, 400);
+    assert_eq!(server.get("/nowhere").0, 404);
+    assert_eq!(
+        server.request("DELETE", "/accounts", "text/plain", "").0,
+        405
+    );
+    assert_eq!(server.post("application/xml", "<tx/>").0, 415);
+    assert_eq!(server.post("text/csv", "type,client\n").0, 400);
+
+    // a body over the limit is turned away on its length alone
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    write!(
+        stream,
+        "POST /transactions HTTP/1.1\r\nHost: {}\r\nContent-Type: text/csv\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        server.addr,
+        64 * 1024 * 1024
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+    assert!(response.contains(r#""code":"payload_too_large""#));
+}
+
+#[test]
+fn test_http_rules() {
+    let server = Server::start(&[
+        "serve",
+        "--listen",
+        "127.0.0.1:0",
+        "--rules",
+        &fixture("rules.json"),
+    ]);
+    let post = |row: &str| server.post("text/csv", &format!("type,client,tx,amount\n{}\n", row));
+
+    assert_eq!(post("deposit,1,1,5000").0, 200);
+    let (status, body) = post("withdrawal,1,2,10");
+    assert_eq!(status, 403);
+    assert!(body.contains(r#""code":"blocked_by_rule""#), "{}", body);
+    assert!(body.contains(r#""rule":"fresh-deposit""#), "{}", body);
+    assert_eq!(
+        server.get("/accounts/1").1,
+        r#"{"client":1,"available":"5000.0000","held":"0.0000","total":"5000.0000","locked":false}"#
+    );
+}
+
+#[test]
+fn test_http_limits() {
+    let server = Server::start(&[
+        "serve",
+        "--listen",
+        "127.0.0.1:0",
+        "--limits",
+        &fixture("limits.json"),
+        "--tiers",
+        &fixture("limits_tiers.csv"),
+    ]);
+    let post = |row: &str| server.post("text/csv", &format!("type,client,tx,amount\n{}\n", row));
+
+    assert_eq!(post("deposit,1,1,1000").0, 200);
+    let (status, body) = post("withdrawal,1,2,501");
+    assert_eq!(status, 403);
+    assert!(body.contains(r#""code":"limit_exceeded""#), "{}", body);
+    assert!(body
+        .contains("the client is allowed withdrawals of at most '500.0000', attempted '501.0000'"));
+    assert!(
+        body.contains(r#""limit":"withdrawal","allowed":"500.0000","attempted":"501.0000""#),
+        "{}",
+        body
+    );
+    assert_eq!(post("withdrawal,1,3,500").0, 200);
+
+    // client 2 is in the business tier
+    assert_eq!(post("deposit,2,4,5000").0, 200);
+    assert_eq!(post("withdrawal,2,5,501").0, 200);
+}
+
+#[test]
+fn test_http_max_disputes() {
+    let server = Server::start(&["serve", "--listen", "127.0.0.1:0", "--max-disputes", "2"]);
+    let post = |row: &str| server.post("text/csv", &format!("type,client,tx,amount\n{}\n", row));
+
+    assert_eq!(post("deposit,1,1,10").0, 200);
+    for _ in 0..2 {
+        assert_eq!(post("dispute,1,1,").0, 200);
+        assert_eq!(post("resolve,1,1,").0, 200);
+    }
+    let (status, body) = post("dispute,1,1,");
+    assert_eq!(status, 409);
+    assert!(
+        body.contains(r#""code":"dispute_limit_reached""#),
+        "{}",
+        body
+    );
+    assert!(body.contains(r#""disputes":2"#), "{}", body);
+
+    // without --max-disputes resolving the first dispute is final
+    let server = Server::start(&["serve", "--listen", "127.0.0.1:0"]);
+    let post = |row: &str| server.post("text/csv", &format!("type,client,tx,amount\n{}\n", row));
+    assert_eq!(post("deposit,1,1,10").0, 200);
+    assert_eq!(post("dispute,1,1,").0, 200);
+    assert_eq!(post("resolve,1,1,").0, 200);
+    let (status, body) = post("dispute,1,1,");
+    assert_eq!(status, 409);
+    assert!(body.contains(r#""disputes":1"#), "{}", body);
+}
+
+#[test]
+fn test_http_string_tx_ids() {
+    let server = Server::start(&["serve", "--listen", "127.0.0.1:0"]);
+
+    let (status, body) = server.post(
+        "application/json",
+        r#"[{"type": "deposit", "client": 1, "tx": "ref a/1", "amount": 2}]"#,
+    );
+    assert_eq!(status, 200, "{}", body);
+
+    // the id is percent-encoded in the path
+    let (status, body) = server.get("/transactions/ref%20a%2F1");
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.starts_with(r#"{"transaction":{"type":"deposit","client":1,"tx":"ref a/1","#));
+    assert_eq!(server.get("/transactions/ref%20b").0, 404);
+}
+
+#[test]
+fn test_http_batches() {
+    let server = Server::start(&["serve", "--listen", "127.0.0.1:0"]);
+    let (status, body) = server.post(
+        "text/csv",
+        "type,client,tx,amount,batch\n\
+         deposit,1,1,100,\n\
+         withdrawal,1,2,50,a\n\
+         withdrawal,1,3,60,a\n",
+    );
+    assert_eq!(status, 207);
+    assert!(body.contains(r#""line":3,"type":"withdrawal","client":1,"tx":"2","amount":"50.0000","status":409,"error":{"code":"batch_rolled_back""#));
+    let (_, body) = server.get("/accounts/1");
+    assert!(body.contains(r#""total":"100.0000""#));
+}
+
+#[test]
+fn test_http_from_snapshot() {
+    let snapshot = std::env::temp_dir().join(format!(
+        "transactions-rs-{}-http-snapshot.json",
+        std::process::id()
+    ));
+    let output = Command::new(env!("CARGO_BIN_EXE_transactions-rs"))
+        .args([
+            "process",
+            &fixture("basic.csv"),
+            "--save-snapshot",
+            snapshot.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let server = Server::start(&[
+        "serve",
+        "--listen",
+        "127.0.0.1:0",
+        "--snapshot",
+        snapshot.to_str().unwrap(),
+    ]);
+    let (status, body) = server.get("/accounts/4");
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""total":"6.0000""#));
+}
+
+#[test]
+fn test_listen_and_send() {
+    let server = Server::start(&["listen", "--tcp", "127.0.0.1:0"]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transactions-rs"))
+        .args(["send", "--tcp", &server.addr, &fixture("basic.csv")])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("failed to parse line 11 (byte 195)"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Sent 12 transactions, 3 failed"),
+        "{}",
+        stderr
+    );
+
+    // the ledger stays in memory between connections
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    stream
+        .write_all(b"withdrawal,4,7,6\n\nwithdrawal,4,8,1\ndeposit,3,9,1\n")
+        .unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut acks = String::new();
+    stream.read_to_string(&mut acks).unwrap();
+    assert_eq!(
+        acks,
+        "ok\n\
+         error insufficient_funds insufficient funds, available: '0.0000'\n\
+         error account_locked the account is locked\n"
+    );
+}
+
+#[test]
+fn test_listen_batches() {
+    let server = Server::start(&["listen", "--tcp", "127.0.0.1:0"]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_transactions-rs"))
+        .args(["send", "--tcp", &server.addr, &fixture("batches.csv")])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    // payroll-2 is rolled back as a whole, so the dispute after it has nothing to dispute
+    assert_eq!(
+        stderr.matches("(batch_rolled_back)").count(),
+        4,
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("(transaction_not_found)"), "{}", stderr);
+    assert!(
+        stderr.contains("Sent 11 transactions, 6 failed"),
+        "{}",
+        stderr
+    );
+
+    // a batch is answered once a row outside of it arrives
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    stream
+        .write_all(b"deposit,6,11,5,b\nwithdrawal,6,12,2,b\nwithdrawal,6,13,4\n")
+        .unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut acks = String::new();
+    stream.read_to_string(&mut acks).unwrap();
+    assert_eq!(
+        acks,
+        "ok\n\
+         ok\n\
+         error insufficient_funds insufficient funds, available: '3.0000'\n"
+    );
+}
+
+#[test]
+fn test_listen_batch_with_too_many_fields() {
+    let server = Server::start(&["listen", "--tcp", "127.0.0.1:0"]);
+
+    // the row with too many fields still belongs to the batch, and rolls it back
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    stream
+        .write_all(
+            b"deposit,7,21,5,c\ndeposit,7,22,1,c,extra\ndeposit,7,23,1,c\nwithdrawal,7,24,1\n",
+        )
+        .unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut acks = String::new();
+    stream.read_to_string(&mut acks).unwrap();
+    let acks: Vec<_> = acks.lines().collect();
+    assert_eq!(acks.len(), 4, "{:?}", acks);
+    assert!(
+        acks[0].starts_with("error batch_rolled_back "),
+        "{:?}",
+        acks
+    );
+    assert_eq!(acks[1], "error parse_error found 6 fields, expected 5");
+    assert!(
+        acks[2].starts_with("error batch_rolled_back "),
+        "{:?}",
+        acks
+    );
+    assert_eq!(acks[3], "error account_not_found account not found");
+}
+
+#[test]
+fn test_listen_metrics() {
+    let mut server = Server::start(&["listen", "--tcp", "127.0.0.1:0", "--metrics", "127.0.0.1:0"]);
+    let metrics = server.read_addr();
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    stream
+        .write_all(b"deposit,1,1,5\nwithdrawal,1,2,6\n")
+        .unwrap();
+    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    let mut acks = String::new();
+    stream.read_to_string(&mut acks).unwrap();
+
+    let (status, body) = request(&metrics, "GET", "/metrics", "text/plain", "");
+    assert_eq!(status, 200);
+    assert!(body.contains("ledger_rows_read_total 2\n"), "{}", body);
+    assert!(body.contains("ledger_rejected_total{code=\"insufficient_funds\"} 1\n"));
+}